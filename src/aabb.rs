@@ -1,7 +1,10 @@
 use crate::camera::Ray;
+use crate::config::INF;
+use crate::matrix::Matrix44;
 use crate::mesh::Triangle;
 use crate::vector::Vector3;
 
+#[derive(Clone, Copy, Debug)]
 pub struct Aabb {
     pub min: Vector3,
     pub max: Vector3,
@@ -26,6 +29,56 @@ impl From<Triangle> for Aabb {
 }
 
 impl Aabb {
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Vector3::new(INF, INF, INF),
+            max: Vector3::new(-INF, -INF, -INF),
+        }
+    }
+
+    pub fn corners(&self) -> [Vector3; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            Vector3::new(a.x, a.y, a.z),
+            Vector3::new(b.x, a.y, a.z),
+            Vector3::new(a.x, b.y, a.z),
+            Vector3::new(b.x, b.y, a.z),
+            Vector3::new(a.x, a.y, b.z),
+            Vector3::new(b.x, a.y, b.z),
+            Vector3::new(a.x, b.y, b.z),
+            Vector3::new(b.x, b.y, b.z),
+        ]
+    }
+
+    // Finite on every axis, unlike the boxes of planes and the empty box
+    pub fn is_bounded(&self) -> bool {
+        [self.min, self.max]
+            .iter()
+            .all(|v| v.x.is_finite() && v.y.is_finite() && v.z.is_finite())
+    }
+
+    // Box enclosing this box transformed by the matrix. Unbounded boxes, such as those of
+    // planes, stay unbounded on every axis
+    pub fn transformed(&self, matrix: &Matrix44) -> Aabb {
+        if !self.is_bounded() && self.min.x <= self.max.x {
+            return Aabb {
+                min: Vector3::all_of(-INF),
+                max: Vector3::all_of(INF),
+            };
+        }
+        self.corners().iter().fold(Aabb::empty(), |aabb, corner| {
+            let p = *matrix * *corner;
+            aabb.merged(&Aabb { min: p, max: p })
+        })
+    }
+
+    pub fn expanded(&self, margin: f64) -> Aabb {
+        Aabb {
+            min: self.min - Vector3::all_of(margin),
+            max: self.max + Vector3::all_of(margin),
+        }
+    }
+
     pub fn intersect_with_aabb(&self, other: &Aabb) -> bool {
         self.min.x < other.max.x
             && self.max.x > other.min.x
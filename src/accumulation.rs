@@ -0,0 +1,159 @@
+use image::codecs::hdr::HdrEncoder;
+use image::{ImageBuffer, ImageResult, Rgb};
+use std::io::Write;
+
+use crate::color::{color_to_luminance, color_to_rgb, Color};
+use crate::config;
+use crate::tile::Region;
+
+#[derive(Clone, Debug)]
+pub struct AccumulatedPixel {
+    // Sum of supersampled radiance, SUPER_SAMPLING^2 samples per sampling
+    pub color: Color,
+    // Sum of squared luminance of each sampling's mean, for variance estimation
+    pub squared_luminance: f64,
+    pub sampling: u32,
+}
+
+impl AccumulatedPixel {
+    pub fn empty() -> AccumulatedPixel {
+        AccumulatedPixel {
+            color: Color::zero(),
+            squared_luminance: 0.0,
+            sampling: 0,
+        }
+    }
+
+    pub fn add(&mut self, supersampled: Color) {
+        let luminance = color_to_luminance(&(supersampled * super_sampling_scale()));
+        self.color += supersampled;
+        self.squared_luminance += luminance * luminance;
+        self.sampling += 1;
+    }
+
+    pub fn merge(&mut self, other: &AccumulatedPixel) {
+        self.color += other.color;
+        self.squared_luminance += other.squared_luminance;
+        self.sampling += other.sampling;
+    }
+
+    pub fn radiance(&self) -> Color {
+        if self.sampling == 0 {
+            Color::zero()
+        } else {
+            self.color * (super_sampling_scale() / self.sampling as f64)
+        }
+    }
+
+    // Standard error of the mean luminance relative to the mean itself
+    pub fn relative_error(&self) -> f64 {
+        if self.sampling < 2 {
+            return config::INF;
+        }
+
+        let n = self.sampling as f64;
+        let mean = color_to_luminance(&self.radiance());
+        let variance = ((self.squared_luminance / n - mean * mean) * n / (n - 1.0)).max(0.0);
+        (variance / n).sqrt() / mean.max(config::ADAPTIVE_SAMPLING_MIN_LUMINANCE)
+    }
+}
+
+pub struct AccumulationBuffer {
+    // Resolution of the whole frame, which determines the camera framing
+    pub width: u32,
+    pub height: u32,
+    // Part of the frame accumulated in pixels
+    pub region: Region,
+    pub pixels: Vec<AccumulatedPixel>,
+}
+
+impl AccumulationBuffer {
+    pub fn new(width: u32, height: u32) -> AccumulationBuffer {
+        AccumulationBuffer::with_region(width, height, Region::new(0, 0, width, height))
+    }
+
+    pub fn with_region(width: u32, height: u32, region: Region) -> AccumulationBuffer {
+        AccumulationBuffer {
+            width,
+            height,
+            region,
+            pixels: vec![AccumulatedPixel::empty(); region.num_of_pixel()],
+        }
+    }
+
+    // Sums up samplings of other, whose region must be inside of this one
+    pub fn merge(&mut self, other: &AccumulationBuffer) -> Result<(), String> {
+        if self.width != other.width || self.height != other.height {
+            return Err(format!(
+                "Cannot merge {}x{} frame into {}x{} frame",
+                other.width, other.height, self.width, self.height
+            ));
+        }
+        if !self.region.contains(&other.region) {
+            return Err(format!(
+                "Cannot merge region {:?} into {:?}",
+                other.region, self.region
+            ));
+        }
+
+        let offset = Region::new(
+            other.region.x - self.region.x,
+            other.region.y - self.region.y,
+            other.region.width,
+            other.region.height,
+        );
+        for (i, pixel) in offset.pixel_indexes(self.region.width).zip(&other.pixels) {
+            self.pixels[i].merge(pixel);
+        }
+
+        Ok(())
+    }
+
+    pub fn max_sampling(&self) -> u32 {
+        self.pixels.iter().map(|p| p.sampling).max().unwrap_or(0)
+    }
+
+    pub fn total_sampling(&self) -> u64 {
+        self.pixels.iter().map(|p| p.sampling as u64).sum()
+    }
+
+    // Zero for an empty region, which has nothing left to refine
+    pub fn mean_relative_error(&self) -> f64 {
+        if self.pixels.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = self.pixels.iter().map(|p| p.relative_error()).sum();
+        sum / self.pixels.len() as f64
+    }
+
+    // Radiance HDR of mean radiance per pixel
+    pub fn write_hdr<W: Write>(&self, writer: W) -> ImageResult<()> {
+        let data: Vec<_> = self
+            .pixels
+            .iter()
+            .map(|p| {
+                let radiance = p.radiance();
+                Rgb([radiance.x as f32, radiance.y as f32, radiance.z as f32])
+            })
+            .collect();
+        HdrEncoder::new(writer).encode(
+            &data,
+            self.region.width as usize,
+            self.region.height as usize,
+        )
+    }
+
+    // Grayscale map of samplings per pixel, white is the most sampled pixel
+    pub fn sampling_map(&self) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+        let scale = (self.max_sampling().max(1) as f64).recip();
+        let mut imgbuf = ImageBuffer::new(self.region.width, self.region.height);
+        for (pixel, accumulated) in imgbuf.pixels_mut().zip(&self.pixels) {
+            *pixel = color_to_rgb(Color::all_of(accumulated.sampling as f64 * scale));
+        }
+        imgbuf
+    }
+}
+
+fn super_sampling_scale() -> f64 {
+    ((config::SUPER_SAMPLING * config::SUPER_SAMPLING) as f64).recip()
+}
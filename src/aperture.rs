@@ -0,0 +1,199 @@
+use image::DynamicImage;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use crate::config;
+use crate::io_util::invalid_data;
+use crate::vector::Vector2;
+
+// Angular resolution of the boundary table of a blade
+const POLYGON_TABLE_SIZE: usize = 256;
+
+// Piecewise constant distribution over [0, 1) tabulated from weights
+#[derive(Clone, Debug)]
+struct Distribution1D {
+    cdf: Vec<f64>,
+}
+
+impl Distribution1D {
+    fn new(weights: &[f64]) -> Distribution1D {
+        let mut cdf = Vec::with_capacity(weights.len() + 1);
+        let mut sum = 0.0;
+        cdf.push(sum);
+        for w in weights {
+            sum += w.max(0.0);
+            cdf.push(sum);
+        }
+        if sum > 0.0 {
+            for c in &mut cdf {
+                *c /= sum;
+            }
+        }
+        Distribution1D { cdf }
+    }
+
+    fn total_positive(&self) -> bool {
+        self.cdf.last().is_some_and(|&c| c > 0.0)
+    }
+
+    // Bin of u, and u remapped to [0, 1) within the bin
+    fn sample(&self, u: f64) -> (usize, f64) {
+        let n = self.cdf.len() - 1;
+        let i = (self.cdf.partition_point(|&c| c <= u).max(1) - 1).min(n - 1);
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let remapped = if width > 0.0 {
+            ((u - self.cdf[i]) / width).clamp(0.0, 1.0 - f64::EPSILON)
+        } else {
+            0.5
+        };
+        (i, remapped)
+    }
+}
+
+// Regular polygon of straight or curved blades, vertices on the unit circle
+#[derive(Clone, Debug)]
+pub struct PolygonAperture {
+    pub blades: u32,
+    // Degrees counterclockwise
+    pub rotation: f64,
+    // 0 is a straight edge, 1 is a circle
+    pub curvature: f64,
+    // Angle within a blade, weighted by the area it covers
+    angles: Distribution1D,
+}
+
+impl PolygonAperture {
+    pub fn new(blades: u32, rotation: f64, curvature: f64) -> PolygonAperture {
+        let mut aperture = PolygonAperture {
+            blades: blades.max(3),
+            rotation,
+            curvature: curvature.clamp(0.0, 1.0),
+            angles: Distribution1D { cdf: vec![] },
+        };
+        let blade_angle = aperture.blade_angle();
+        let weights: Vec<f64> = (0..POLYGON_TABLE_SIZE)
+            .map(|i| {
+                let angle = (i as f64 + 0.5) / POLYGON_TABLE_SIZE as f64 * blade_angle;
+                aperture.radius(angle).powi(2)
+            })
+            .collect();
+        aperture.angles = Distribution1D::new(&weights);
+        aperture
+    }
+
+    fn blade_angle(&self) -> f64 {
+        config::PI2 / self.blades as f64
+    }
+
+    // Distance to the edge at given angle from the first vertex of a blade
+    fn radius(&self, angle: f64) -> f64 {
+        let half = self.blade_angle() * 0.5;
+        let straight = half.cos() / (angle - half).cos();
+        straight + (1.0 - straight) * self.curvature
+    }
+
+    pub fn sample(&self, (u, v): (f64, f64)) -> Vector2 {
+        let scaled = u * self.blades as f64;
+        let blade = (scaled.floor() as u32).min(self.blades - 1);
+        let (bin, remapped) = self.angles.sample(scaled - blade as f64);
+        let angle = (bin as f64 + remapped) / POLYGON_TABLE_SIZE as f64 * self.blade_angle();
+
+        let r = self.radius(angle) * v.sqrt();
+        let theta = self.rotation.to_radians() + blade as f64 * self.blade_angle() + angle;
+        Vector2::new(r * theta.cos(), r * theta.sin())
+    }
+}
+
+// Aperture of arbitrary shape, transmittance by luminance of an image
+#[derive(Clone)]
+pub struct ApertureMask {
+    pub width: u32,
+    pub height: u32,
+    rows: Distribution1D,
+    columns: Vec<Distribution1D>,
+}
+
+impl fmt::Debug for ApertureMask {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ApertureMask {{ width: {}, height: {}, rows: {:?} }}",
+            self.width, self.height, self.rows.cdf
+        )
+    }
+}
+
+impl ApertureMask {
+    // Cumulative distributions of the rows, then of the pixels of each row
+    pub(crate) fn cdfs(&self) -> impl Iterator<Item = &[f64]> {
+        std::iter::once(&self.rows)
+            .chain(&self.columns)
+            .map(|d| d.cdf.as_slice())
+    }
+
+    pub fn new(path: &str) -> io::Result<ApertureMask> {
+        image::open(Path::new(path))
+            .map_err(|e| e.to_string())
+            .and_then(|image| ApertureMask::from_image(&image))
+            .map_err(|e| invalid_data(format!("{}: {}", path, e)))
+    }
+
+    // Fails if no pixel lets light through
+    pub fn from_image(image: &DynamicImage) -> Result<ApertureMask, String> {
+        let luma = image.to_luma8();
+        let (width, height) = luma.dimensions();
+        let columns: Vec<Distribution1D> = (0..height)
+            .map(|y| {
+                let weights: Vec<f64> =
+                    (0..width).map(|x| luma.get_pixel(x, y)[0] as f64).collect();
+                Distribution1D::new(&weights)
+            })
+            .collect();
+        let row_weights: Vec<f64> = luma
+            .rows()
+            .map(|row| row.map(|p| p[0] as f64).sum())
+            .collect();
+        let rows = Distribution1D::new(&row_weights);
+        if !rows.total_positive() {
+            return Err("aperture mask is all black".to_string());
+        }
+
+        Ok(ApertureMask {
+            width,
+            height,
+            rows,
+            columns,
+        })
+    }
+
+    // Longer side of the image spans [-1, 1]
+    pub fn sample(&self, (u, v): (f64, f64)) -> Vector2 {
+        // Remapped random numbers are uniform within the pixel
+        let (y, dy) = self.rows.sample(u);
+        let (x, dx) = self.columns[y].sample(v);
+
+        let scale = 2.0 / self.width.max(self.height) as f64;
+        Vector2::new(
+            (x as f64 + dx - self.width as f64 * 0.5) * scale,
+            (self.height as f64 * 0.5 - y as f64 - dy) * scale,
+        )
+    }
+}
+
+// Uniform on the unit disk, keeping strata of the square
+// https://doi.org/10.1080/10867651.1997.10487479
+pub fn concentric_disk(u: f64, v: f64) -> Vector2 {
+    let a = 2.0 * u - 1.0;
+    let b = 2.0 * v - 1.0;
+    if a == 0.0 && b == 0.0 {
+        return Vector2::new(0.0, 0.0);
+    }
+
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, config::PI * 0.25 * (b / a))
+    } else {
+        (b, config::PI * 0.5 - config::PI * 0.25 * (a / b))
+    };
+    Vector2::new(r * theta.cos(), r * theta.sin())
+}
@@ -1,21 +1,151 @@
 use rand::{Rng, StdRng};
 
+use crate::aperture;
+use crate::aperture::{ApertureMask, PolygonAperture};
+use crate::config;
+use crate::lens::LensSystem;
 use crate::vector::{Vector2, Vector3};
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum LensShape {
     Square,
     Circle,
+    Polygon(PolygonAperture),
+    Mask(ApertureMask),
+}
+
+#[derive(Clone, Debug)]
+pub enum Projection {
+    Perspective,
+    // Parallel rays, view_width is the world space width of the image
+    Orthographic { view_width: f64 },
+    // Full sphere in a 2:1 image, other aspect ratios crop it without distortion.
+    // Rays start eye_offset to the right of the position tangent to the circle around it,
+    // which makes omni-directional stereo of an eye
+    Equirectangular { eye_offset: f64 },
+    // Image circle inscribed in the shorter side of the image, fov is in degrees across it
+    Fisheye { fov: f64, mapping: FisheyeMapping },
+    // Traced through the elements of a real lens, the position is the center of the film.
+    // Replaces the thin lens, so lens_shape, lens_radius and focus_distance are unused
+    Lens(LensSystem),
+}
+
+// Full angles in degrees, or focal length and sensor size in the same unit e.g. mm
+#[derive(Clone, Copy, Debug)]
+pub enum FieldOfView {
+    Vertical(f64),
+    Horizontal(f64),
+    Diagonal(f64),
+    // The sensor fills the image, cropped if the aspect ratios differ
+    FocalLength {
+        focal_length: f64,
+        sensor_width: f64,
+        sensor_height: f64,
+    },
+}
+
+impl FieldOfView {
+    // Tangents of the half angles (horizontal, vertical) for an image of aspect_ratio
+    // (width / height)
+    pub fn half_tangents(&self, aspect_ratio: f64) -> (f64, f64) {
+        let half_tan = |fov: f64| (fov * 0.5).to_radians().tan();
+        match *self {
+            FieldOfView::Vertical(fov) => (half_tan(fov) * aspect_ratio, half_tan(fov)),
+            FieldOfView::Horizontal(fov) => (half_tan(fov), half_tan(fov) / aspect_ratio),
+            FieldOfView::Diagonal(fov) => {
+                let diagonal = (1.0 + aspect_ratio * aspect_ratio).sqrt();
+                (
+                    half_tan(fov) * aspect_ratio / diagonal,
+                    half_tan(fov) / diagonal,
+                )
+            }
+            FieldOfView::FocalLength {
+                focal_length,
+                sensor_width,
+                sensor_height,
+            } => {
+                if aspect_ratio >= sensor_width / sensor_height {
+                    let horizontal = 0.5 * sensor_width / focal_length;
+                    (horizontal, horizontal / aspect_ratio)
+                } else {
+                    let vertical = 0.5 * sensor_height / focal_length;
+                    (vertical * aspect_ratio, vertical)
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum FisheyeMapping {
+    // Distance from the center is proportional to the angle
+    Equidistant,
+    // Distance from the center is proportional to the solid angle
+    Equisolid,
+}
+
+// Interval the shutter is open, in the same unit as keyframes
+#[derive(Clone, Copy, Debug)]
+pub struct Shutter {
+    pub open: f64,
+    pub close: f64,
+}
+
+impl Shutter {
+    pub fn sample(&self, u: f64) -> f64 {
+        self.open + (self.close - self.open) * u
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CameraKeyframe {
+    pub time: f64,
+    pub position: Vector3,
+    pub target: Vector3,
+}
+
+// Camera basis at a moment
+struct Frame {
+    position: Vector3,
+    right: Vector3,
+    up: Vector3,
+    forward: Vector3,
+    plane_half_right: Vector3,
+    plane_half_up: Vector3,
+}
+
+impl Frame {
+    // Ray in the space of a lens system to the world, whose -z is forward
+    fn lens_ray(&self, lens: &LensSystem, origin: Vector3, direction: Vector3, time: f64) -> Ray {
+        let to_world = |v: Vector3| self.right * v.x + self.up * v.y - self.forward * v.z;
+        Ray {
+            origin: self.position + to_world(origin) * lens.scale,
+            direction: to_world(direction).normalized(),
+            time,
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Camera {
     pub position: Vector3, // Camera position in the world
+    pub y_up: Vector3,
+
+    // Linearly interpolated by time, sorted by time. Static at position if empty
+    pub keyframes: Vec<CameraKeyframe>,
+    pub shutter: Shutter,
+
+    pub projection: Projection,
+    // Off-axis shift of the image plane in normalized coordinates, for planar projections
+    pub shift: Vector2,
 
     pub lens_shape: LensShape,
 
     pub lens_radius: f64,
     pub focus_distance: f64,
+    // Squeezes the aperture toward the image center by this much per normalized distance,
+    // darkening the image by the share of the aperture it cuts off
+    pub cat_eye: f64,
 
     pub right: Vector3,   // basis vector for right
     pub up: Vector3,      // same for up
@@ -29,72 +159,351 @@ pub struct Camera {
 pub struct Ray {
     pub origin: Vector3,
     pub direction: Vector3,
+    // Moment in the shutter interval the ray travels at
+    pub time: f64,
 }
 
 impl Camera {
+    // aspect_ratio (width / height) of the image resolves the field of view
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         position: Vector3,
         target: Vector3,
         y_up: Vector3,
-        v_fov: f64,
+        fov: FieldOfView,
+        aspect_ratio: f64,
         lens_shape: LensShape,
         aperture: f64,
         focus_distance: f64,
     ) -> Camera {
         let lens_radius = 0.5 * aperture;
-        let plane_half_height = v_fov.to_radians().tan();
-        let forward = (target - position).normalized();
-        let right = forward.cross(&y_up).normalized();
-        let up = right.cross(&forward).normalized();
+        // Normalized coordinates span [-1, 1] across the shorter side of the image
+        let (half_horizontal, half_vertical) = fov.half_tangents(aspect_ratio);
+        let plane_half_size = half_horizontal.min(half_vertical);
+        let (right, up, forward) = basis(position, target, y_up);
 
         Camera {
             position,
+            y_up,
+            keyframes: vec![],
+            shutter: Shutter {
+                open: 0.0,
+                close: 0.0,
+            },
+            projection: Projection::Perspective,
+            shift: Vector2::zero(),
             lens_shape,
             lens_radius,
             focus_distance,
+            cat_eye: 0.0,
             forward,
             right,
             up,
-            plane_half_right: right * plane_half_height * focus_distance,
-            plane_half_up: up * plane_half_height * focus_distance,
+            plane_half_right: right * plane_half_size * focus_distance,
+            plane_half_up: up * plane_half_size * focus_distance,
         }
     }
 
-    fn compose_ray(&self, normalized_coord: &Vector2, camera_position_offset: Vector3) -> Ray {
-        Ray {
-            origin: self.position + camera_position_offset,
-            direction: (normalized_coord.x * self.plane_half_right
-                + normalized_coord.y * self.plane_half_up
-                + self.focus_distance * self.forward)
-                .normalized(),
+    #[allow(clippy::too_many_arguments)]
+    pub fn orthographic(
+        position: Vector3,
+        target: Vector3,
+        y_up: Vector3,
+        view_width: f64,
+        aspect_ratio: f64,
+        lens_shape: LensShape,
+        aperture: f64,
+        focus_distance: f64,
+    ) -> Camera {
+        let plane_half_size = 0.5 * view_width / aspect_ratio.max(1.0);
+        let camera = Camera::new(
+            position,
+            target,
+            y_up,
+            FieldOfView::Vertical(0.0),
+            aspect_ratio,
+            lens_shape,
+            aperture,
+            focus_distance,
+        );
+
+        Camera {
+            projection: Projection::Orthographic { view_width },
+            plane_half_right: camera.right * plane_half_size,
+            plane_half_up: camera.up * plane_half_size,
+            ..camera
         }
     }
 
-    pub fn ray(&self, normalized_coord: &Vector2) -> Ray {
-        self.compose_ray(normalized_coord, Vector3::new(0.0, 0.0, 0.0))
+    pub fn equirectangular(position: Vector3, target: Vector3, y_up: Vector3) -> Camera {
+        Camera {
+            projection: Projection::Equirectangular { eye_offset: 0.0 },
+            ..Camera::new(
+                position,
+                target,
+                y_up,
+                FieldOfView::Vertical(0.0),
+                1.0,
+                LensShape::Circle,
+                0.0,
+                1.0,
+            )
+        }
     }
 
-    fn sample_on_lens(&self, rng: &mut StdRng) -> Vector2 {
-        loop {
-            let (u, v) = rng.gen::<(f64, f64)>();
-            let square = Vector2::new(2.0 * u - 1.0, 2.0 * v - 1.0);
+    pub fn fisheye(
+        position: Vector3,
+        target: Vector3,
+        y_up: Vector3,
+        fov: f64,
+        mapping: FisheyeMapping,
+    ) -> Camera {
+        Camera {
+            projection: Projection::Fisheye { fov, mapping },
+            ..Camera::new(
+                position,
+                target,
+                y_up,
+                FieldOfView::Vertical(0.0),
+                1.0,
+                LensShape::Circle,
+                0.0,
+                1.0,
+            )
+        }
+    }
 
-            match self.lens_shape {
-                LensShape::Square => {
-                    return square;
+    // Same camera moved to position and aimed at target, keeping the field of view
+    pub fn looking(&self, position: Vector3, target: Vector3) -> Camera {
+        let (right, up, forward) = basis(position, target, self.y_up);
+        Camera {
+            position,
+            right,
+            up,
+            forward,
+            plane_half_right: right * self.plane_half_right.length(),
+            plane_half_up: up * self.plane_half_up.length(),
+            ..self.clone()
+        }
+    }
+
+    pub fn lens_system(
+        position: Vector3,
+        target: Vector3,
+        y_up: Vector3,
+        lens: LensSystem,
+    ) -> Camera {
+        Camera {
+            projection: Projection::Lens(lens),
+            ..Camera::new(
+                position,
+                target,
+                y_up,
+                FieldOfView::Vertical(0.0),
+                1.0,
+                LensShape::Circle,
+                0.0,
+                1.0,
+            )
+        }
+    }
+
+    fn frame_at(&self, time: f64) -> Frame {
+        let (position, target) = match interpolate_keyframes(&self.keyframes, time) {
+            Some(keyframe) => keyframe,
+            None => {
+                return Frame {
+                    position: self.position,
+                    right: self.right,
+                    up: self.up,
+                    forward: self.forward,
+                    plane_half_right: self.plane_half_right,
+                    plane_half_up: self.plane_half_up,
                 }
-                LensShape::Circle => {
-                    if square.norm() < 1.0 {
-                        return square;
-                    }
+            }
+        };
+
+        let (right, up, forward) = basis(position, target, self.y_up);
+        Frame {
+            position,
+            right,
+            up,
+            forward,
+            plane_half_right: right * self.plane_half_right.length(),
+            plane_half_up: up * self.plane_half_up.length(),
+        }
+    }
+
+    // Ray at the shutter opening
+    pub fn ray(&self, normalized_coord: &Vector2) -> Option<Ray> {
+        self.ray_at(normalized_coord, self.shutter.open)
+    }
+
+    // None if the projection doesn't cover the coordinate
+    pub fn ray_at(&self, normalized_coord: &Vector2, time: f64) -> Option<Ray> {
+        self.project(normalized_coord, &self.frame_at(time), time)
+    }
+
+    fn project(&self, normalized_coord: &Vector2, frame: &Frame, time: f64) -> Option<Ray> {
+        // Direction of given angles from forward, longitude to the right and latitude to the up
+        let spherical_direction = |longitude: f64, latitude: f64| {
+            latitude.cos() * (longitude.cos() * frame.forward + longitude.sin() * frame.right)
+                + latitude.sin() * frame.up
+        };
+
+        let plane_coord = *normalized_coord + self.shift;
+        let ray = match self.projection {
+            Projection::Perspective => Ray {
+                origin: frame.position,
+                direction: (plane_coord.x * frame.plane_half_right
+                    + plane_coord.y * frame.plane_half_up
+                    + self.focus_distance * frame.forward)
+                    .normalized(),
+                time,
+            },
+            Projection::Orthographic { .. } => Ray {
+                origin: frame.position
+                    + plane_coord.x * frame.plane_half_right
+                    + plane_coord.y * frame.plane_half_up,
+                direction: frame.forward,
+                time,
+            },
+            Projection::Equirectangular { eye_offset } => {
+                // Shorter side spans [-1, 1], which is 180 degrees
+                let longitude = normalized_coord.x * config::PI * 0.5;
+                let latitude = normalized_coord.y * config::PI * 0.5;
+                if longitude.abs() > config::PI || latitude.abs() > config::PI * 0.5 {
+                    return None;
+                }
+                let tangent = longitude.cos() * frame.right - longitude.sin() * frame.forward;
+                Ray {
+                    origin: frame.position + tangent * eye_offset,
+                    direction: spherical_direction(longitude, latitude),
+                    time,
+                }
+            }
+            Projection::Fisheye { fov, mapping } => {
+                let r = normalized_coord.length();
+                if r > 1.0 {
+                    return None;
+                }
+                let half_fov = (fov * 0.5).to_radians();
+                let theta = match mapping {
+                    FisheyeMapping::Equidistant => r * half_fov,
+                    FisheyeMapping::Equisolid => 2.0 * (r * (half_fov * 0.5).sin()).asin(),
+                };
+                let phi = normalized_coord.y.atan2(normalized_coord.x);
+                Ray {
+                    origin: frame.position,
+                    direction: theta.cos() * frame.forward
+                        + theta.sin() * (phi.cos() * frame.right + phi.sin() * frame.up),
+                    time,
                 }
             }
+            Projection::Lens(ref lens) => {
+                let (origin, direction) = lens.chief_ray(&plane_coord)?;
+                frame.lens_ray(lens, origin, direction, time)
+            }
+        };
+        Some(ray)
+    }
+
+    // Point on the lens in units of lens_radius, no rejection so that a pixel uses a fixed
+    // number of random numbers
+    pub fn sample_on_lens(&self, normalized_coord: &Vector2, uv: (f64, f64)) -> Vector2 {
+        let (u, v) = uv;
+        let lens = match self.lens_shape {
+            LensShape::Square => Vector2::new(2.0 * u - 1.0, 2.0 * v - 1.0),
+            LensShape::Circle => aperture::concentric_disk(u, v),
+            LensShape::Polygon(ref polygon) => polygon.sample(uv),
+            LensShape::Mask(ref mask) => mask.sample(uv),
+        };
+
+        // Cat's eye, the aperture seen off axis gets narrow toward the image center
+        let distance = normalized_coord.length();
+        if self.cat_eye <= 0.0 || distance == 0.0 {
+            return lens;
+        }
+        let radial = *normalized_coord / distance;
+        let along = lens.dot(radial);
+        lens + radial * (along * (self.cat_eye_squeeze(normalized_coord) - 1.0))
+    }
+
+    // Share of the aperture the cat's eye leaves open at the coordinate, 1 on axis
+    pub fn cat_eye_squeeze(&self, normalized_coord: &Vector2) -> f64 {
+        (1.0 - self.cat_eye.max(0.0) * normalized_coord.length()).max(0.0)
+    }
+
+    pub fn ray_with_dof(&self, normalized_coord: &Vector2, rng: &mut StdRng) -> Option<Ray> {
+        self.sample_ray(normalized_coord, rng).map(|(ray, _)| ray)
+    }
+
+    // Ray of a sample of the pixel and its weight, which differs from 1 only for lens
+    // systems and cat's eyes that vignette
+    pub fn sample_ray(&self, normalized_coord: &Vector2, rng: &mut StdRng) -> Option<(Ray, f64)> {
+        let lens_sample = rng.gen();
+        let time = self.shutter.sample(rng.gen());
+        let frame = self.frame_at(time);
+        if let Projection::Lens(ref lens) = self.projection {
+            let (origin, direction, weight) =
+                lens.sample_ray(&(*normalized_coord + self.shift), lens_sample)?;
+            return Some((frame.lens_ray(lens, origin, direction, time), weight));
         }
+
+        let lens_uv = self.sample_on_lens(normalized_coord, lens_sample) * self.lens_radius;
+        let ray = self.project(normalized_coord, &frame, time)?;
+        let lens_pos = frame.right * lens_uv.x + frame.up * lens_uv.y;
+
+        // Rays through any point of the lens converge on the focal plane, or sphere for
+        // panoramic projections
+        let focus_distance = match self.projection {
+            Projection::Perspective | Projection::Orthographic { .. } => {
+                self.focus_distance / ray.direction.dot(&frame.forward)
+            }
+            Projection::Equirectangular { .. } | Projection::Fisheye { .. } => self.focus_distance,
+            Projection::Lens(_) => unreachable!(),
+        };
+        let focus = ray.origin + ray.direction * focus_distance;
+        let origin = ray.origin + lens_pos;
+        let ray = Ray {
+            origin,
+            direction: (focus - origin).normalized(),
+            time,
+        };
+        // Less of a squeezed aperture lets less light through, pinholes have none to squeeze
+        let weight = if self.lens_radius > 0.0 {
+            self.cat_eye_squeeze(normalized_coord)
+        } else {
+            1.0
+        };
+        Some((ray, weight))
     }
+}
+
+// Right, up and forward of a camera at position looking at target
+fn basis(position: Vector3, target: Vector3, y_up: Vector3) -> (Vector3, Vector3, Vector3) {
+    let forward = (target - position).normalized();
+    let right = forward.cross(&y_up).normalized();
+    let up = right.cross(&forward).normalized();
+    (right, up, forward)
+}
 
-    pub fn ray_with_dof(&self, normalized_coord: &Vector2, rng: &mut StdRng) -> Ray {
-        let lens_uv = self.sample_on_lens(rng) * self.lens_radius;
-        let lens_pos = self.right * lens_uv.x + self.up * lens_uv.y;
-        self.compose_ray(normalized_coord, lens_pos)
+// Position and target at time, clamped to the first and last keyframes
+fn interpolate_keyframes(keyframes: &[CameraKeyframe], time: f64) -> Option<(Vector3, Vector3)> {
+    let first = keyframes.first()?;
+    let last = keyframes.last()?;
+    if time <= first.time {
+        return Some((first.position, first.target));
+    }
+    if time >= last.time {
+        return Some((last.position, last.target));
     }
+
+    let next = keyframes.partition_point(|k| k.time <= time);
+    let (a, b) = (&keyframes[next - 1], &keyframes[next]);
+    let t = (time - a.time) / (b.time - a.time);
+    Some((
+        a.position + (b.position - a.position) * t,
+        a.target + (b.target - a.target) * t,
+    ))
 }
@@ -0,0 +1,358 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use crate::accumulation::{AccumulatedPixel, AccumulationBuffer};
+use crate::camera::{Camera, LensShape, Projection};
+use crate::color::Color;
+use crate::config;
+use crate::io_util::{invalid_data, read_f64, read_u32, read_u64, write_f64, write_u32, write_u64};
+use crate::renderer::{replace_atomically, Renderer};
+use crate::scene::Illuminable;
+use crate::tile::Region;
+use crate::vector::Vector2;
+
+const MAGIC: &[u8; 4] = b"FECP";
+const VERSION: u32 = 3;
+
+// Rays per axis traced to fingerprint the scene
+const PROBE_RESOLUTION: u32 = 32;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(hash, |h, b| (h ^ *b as u64).wrapping_mul(FNV_PRIME))
+}
+
+fn fnv1a_f64s(hash: u64, values: &[f64]) -> u64 {
+    values.iter().fold(hash, |h, v| fnv1a(h, &v.to_le_bytes()))
+}
+
+// Parameters that shape the rays of the camera, each variant tagged by a byte
+fn camera_hash(hash: u64, camera: &Camera) -> u64 {
+    let vectors = [
+        camera.position,
+        camera.y_up,
+        camera.right,
+        camera.up,
+        camera.forward,
+        camera.plane_half_right,
+        camera.plane_half_up,
+    ];
+    let mut hash = vectors
+        .iter()
+        .fold(hash, |h, v| fnv1a_f64s(h, &[v.x, v.y, v.z]));
+    hash = fnv1a_f64s(
+        hash,
+        &[
+            camera.shutter.open,
+            camera.shutter.close,
+            camera.shift.x,
+            camera.shift.y,
+            camera.lens_radius,
+            camera.focus_distance,
+            camera.cat_eye,
+        ],
+    );
+
+    hash = fnv1a(hash, &(camera.keyframes.len() as u64).to_le_bytes());
+    for keyframe in &camera.keyframes {
+        let (position, target) = (keyframe.position, keyframe.target);
+        hash = fnv1a_f64s(
+            hash,
+            &[
+                keyframe.time,
+                position.x,
+                position.y,
+                position.z,
+                target.x,
+                target.y,
+                target.z,
+            ],
+        );
+    }
+
+    hash = match &camera.projection {
+        Projection::Perspective => fnv1a(hash, &[0]),
+        Projection::Orthographic { view_width } => fnv1a_f64s(fnv1a(hash, &[1]), &[*view_width]),
+        Projection::Equirectangular { eye_offset } => fnv1a_f64s(fnv1a(hash, &[2]), &[*eye_offset]),
+        Projection::Fisheye { fov, mapping } => {
+            fnv1a_f64s(fnv1a(hash, &[3, *mapping as u8]), &[*fov])
+        }
+        Projection::Lens(lens) => {
+            let hash = fnv1a_f64s(
+                fnv1a(hash, &[4]),
+                &[lens.film_half_width, lens.film_half_height, lens.scale],
+            );
+            lens.elements.iter().fold(hash, |h, e| {
+                fnv1a_f64s(
+                    h,
+                    &[e.curvature_radius, e.thickness, e.ior, e.aperture_radius],
+                )
+            })
+        }
+    };
+
+    match &camera.lens_shape {
+        LensShape::Square => fnv1a(hash, &[0]),
+        LensShape::Circle => fnv1a(hash, &[1]),
+        LensShape::Polygon(polygon) => fnv1a_f64s(
+            fnv1a(hash, &[2]),
+            &[polygon.blades as f64, polygon.rotation, polygon.curvature],
+        ),
+        LensShape::Mask(mask) => mask.cdfs().fold(fnv1a(hash, &[3]), fnv1a_f64s),
+    }
+}
+
+// Scene elements can't be serialized, so fingerprint what the renderer would see instead:
+// the camera, hits of a grid of camera rays and the light sources.
+pub fn scene_hash(scene: &dyn Illuminable, camera: &Camera) -> u64 {
+    let mut hash = camera_hash(FNV_OFFSET_BASIS, camera);
+
+    for y in 0..PROBE_RESOLUTION {
+        for x in 0..PROBE_RESOLUTION {
+            let normalized_coord =
+                Vector2::new(x as f64, y as f64) * 2.0 / (PROBE_RESOLUTION - 1) as f64 - 1.0;
+            if let Some(ray) = camera.ray(&normalized_coord) {
+                let (hit, intersection) = scene.intersect(&ray);
+                hash = fnv1a(hash, format!("{} {:?}", hit, intersection).as_bytes());
+            }
+        }
+    }
+
+    for emission in scene.emissions() {
+        hash = fnv1a(hash, format!("{:?}", emission.material()).as_bytes());
+        if let Some(surface) = emission.sample_on_surface((0.5, 0.5)) {
+            hash = fnv1a(
+                hash,
+                format!("{:?} {}", surface.position, surface.pdf).as_bytes(),
+            );
+        }
+    }
+
+    hash
+}
+
+// Adaptive sampling settings the buffer was accumulated with, which a resumed render must keep
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SamplingSettings {
+    pub max_sampling: u32,
+    pub min_sampling: u32,
+    pub error_threshold: f64,
+}
+
+impl SamplingSettings {
+    pub fn of<R: Renderer + ?Sized>(renderer: &R) -> SamplingSettings {
+        SamplingSettings {
+            max_sampling: renderer.max_sampling(),
+            min_sampling: renderer.min_sampling(),
+            error_threshold: renderer.error_threshold(),
+        }
+    }
+}
+
+pub fn write_to<W: Write>(
+    writer: &mut W,
+    scene_hash: u64,
+    settings: &SamplingSettings,
+    accumulation: &AccumulationBuffer,
+) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    write_u32(writer, VERSION)?;
+    write_u32(writer, config::SUPER_SAMPLING)?;
+    write_u32(writer, config::PATHTRACING_BOUNCE_LIMIT)?;
+    write_u64(writer, scene_hash)?;
+    write_u32(writer, settings.max_sampling)?;
+    write_u32(writer, settings.min_sampling)?;
+    write_f64(writer, settings.error_threshold)?;
+    write_u32(writer, accumulation.width)?;
+    write_u32(writer, accumulation.height)?;
+    write_u32(writer, accumulation.region.x)?;
+    write_u32(writer, accumulation.region.y)?;
+    write_u32(writer, accumulation.region.width)?;
+    write_u32(writer, accumulation.region.height)?;
+
+    for pixel in &accumulation.pixels {
+        write_f64(writer, pixel.color.x)?;
+        write_f64(writer, pixel.color.y)?;
+        write_f64(writer, pixel.color.z)?;
+        write_f64(writer, pixel.squared_luminance)?;
+        write_u32(writer, pixel.sampling)?;
+    }
+
+    writer.flush()
+}
+
+// The buffer must be of the given resolution, checked before allocating it
+pub fn read_from<R: Read>(
+    reader: &mut R,
+    scene_hash: u64,
+    width: u32,
+    height: u32,
+) -> io::Result<(SamplingSettings, AccumulationBuffer)> {
+    let (hash, settings, accumulation) = read_unchecked(reader, width, height)?;
+    if hash != scene_hash {
+        return Err(different_scene());
+    }
+    Ok((settings, accumulation))
+}
+
+fn different_scene() -> io::Error {
+    invalid_data("Checkpoint was rendered from a different scene or camera".to_string())
+}
+
+// Scene hash of the buffer along with it, for readers without the scene at hand
+pub fn read_unchecked<R: Read>(
+    reader: &mut R,
+    width: u32,
+    height: u32,
+) -> io::Result<(u64, SamplingSettings, AccumulationBuffer)> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("Not a checkpoint file".to_string()));
+    }
+
+    let version = read_u32(reader)?;
+    if version != VERSION {
+        return Err(invalid_data(format!(
+            "Unsupported checkpoint version {}",
+            version
+        )));
+    }
+
+    let super_sampling = read_u32(reader)?;
+    let bounce_limit = read_u32(reader)?;
+    if super_sampling != config::SUPER_SAMPLING || bounce_limit != config::PATHTRACING_BOUNCE_LIMIT
+    {
+        return Err(invalid_data(format!(
+            "Checkpoint was rendered with super sampling {} and bounce limit {}, but now {} and {}",
+            super_sampling,
+            bounce_limit,
+            config::SUPER_SAMPLING,
+            config::PATHTRACING_BOUNCE_LIMIT
+        )));
+    }
+
+    let scene_hash = read_u64(reader)?;
+    let settings = SamplingSettings {
+        max_sampling: read_u32(reader)?,
+        min_sampling: read_u32(reader)?,
+        error_threshold: read_f64(reader)?,
+    };
+
+    let buffer_width = read_u32(reader)?;
+    let buffer_height = read_u32(reader)?;
+    if buffer_width != width || buffer_height != height {
+        return Err(invalid_data(format!(
+            "Checkpoint resolution is {}x{}, but rendering {}x{}",
+            buffer_width, buffer_height, width, height
+        )));
+    }
+
+    let region = Region::new(
+        read_u32(reader)?,
+        read_u32(reader)?,
+        read_u32(reader)?,
+        read_u32(reader)?,
+    );
+    if !Region::new(0, 0, width, height).contains(&region) {
+        return Err(invalid_data(format!(
+            "Checkpoint region {:?} is out of the {}x{} image",
+            region, width, height
+        )));
+    }
+
+    let mut accumulation = AccumulationBuffer::with_region(width, height, region);
+    for pixel in &mut accumulation.pixels {
+        let color = Color::new(read_f64(reader)?, read_f64(reader)?, read_f64(reader)?);
+        *pixel = AccumulatedPixel {
+            color,
+            squared_luminance: read_f64(reader)?,
+            sampling: read_u32(reader)?,
+        };
+    }
+
+    Ok((scene_hash, settings, accumulation))
+}
+
+pub fn save(
+    path: &str,
+    scene_hash: u64,
+    settings: &SamplingSettings,
+    accumulation: &AccumulationBuffer,
+) -> io::Result<()> {
+    replace_atomically(path, |tmp_path| {
+        write_to(
+            &mut BufWriter::new(File::create(tmp_path)?),
+            scene_hash,
+            settings,
+            accumulation,
+        )
+    })
+}
+
+pub fn load(
+    path: &str,
+    scene_hash: u64,
+    settings: &SamplingSettings,
+    width: u32,
+    height: u32,
+    region: Region,
+) -> io::Result<AccumulationBuffer> {
+    let (saved_settings, accumulation) = read_from(
+        &mut BufReader::new(File::open(path)?),
+        scene_hash,
+        width,
+        height,
+    )?;
+
+    if saved_settings != *settings {
+        return Err(invalid_data(format!(
+            "Checkpoint was rendered with {:?}, but now {:?}",
+            saved_settings, settings
+        )));
+    }
+
+    if accumulation.region != region {
+        return Err(invalid_data(format!(
+            "Checkpoint region is {:?}, but rendering {:?}",
+            accumulation.region, region
+        )));
+    }
+
+    Ok(accumulation)
+}
+
+// Sums up buffers into the region, e.g. rendered separately with disjoint seed offsets.
+// Without a scene hash to check against, the buffers must share the hash of the first one.
+// Their sampling settings may differ, as each buffer only adds samples to the sum.
+// Returns the hash along with the sum, to save it as a checkpoint again
+pub fn merge(
+    paths: &[String],
+    scene_hash: Option<u64>,
+    width: u32,
+    height: u32,
+    region: Region,
+) -> io::Result<(u64, AccumulationBuffer)> {
+    let mut accumulation = AccumulationBuffer::with_region(width, height, region);
+    let mut expected_hash = scene_hash;
+    for path in paths {
+        File::open(path)
+            .and_then(|file| read_unchecked(&mut BufReader::new(file), width, height))
+            .and_then(|(hash, _, buffer)| {
+                if *expected_hash.get_or_insert(hash) != hash {
+                    return Err(different_scene());
+                }
+                accumulation.merge(&buffer).map_err(invalid_data)
+            })
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))?;
+    }
+    match expected_hash {
+        Some(hash) => Ok((hash, accumulation)),
+        None => Err(invalid_data("No checkpoints to merge".to_string())),
+    }
+}
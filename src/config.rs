@@ -23,3 +23,6 @@ pub const BILATERAL_FILTER_SIGMA_S: f64 = 16.0;
 
 //
 pub const PATHTRACING_BOUNCE_LIMIT: u32 = 10;
+
+// Adaptive Sampling
+pub const ADAPTIVE_SAMPLING_MIN_LUMINANCE: f64 = 1e-2;
@@ -0,0 +1,336 @@
+use std::collections::VecDeque;
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use image::ImageBuffer;
+
+use crate::accumulation::AccumulationBuffer;
+use crate::camera::Camera;
+use crate::checkpoint;
+use crate::checkpoint::SamplingSettings;
+use crate::config;
+use crate::io_util::{invalid_data, read_u32, read_u64, write_u32, write_u64};
+use crate::renderer::Renderer;
+use crate::scene::Illuminable;
+use crate::tile::{Region, Tiling};
+
+const MAGIC: &[u8; 4] = b"FEDR";
+const VERSION: u32 = 1;
+
+const MESSAGE_JOB: u32 = 1;
+const MESSAGE_DONE: u32 = 2;
+const MESSAGE_REJECTED: u32 = 3;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// How long the coordinator waits for a worker to reply before giving its job to another,
+// and for a new worker to take over once all of them are gone
+pub const DEFAULT_WORKER_TIMEOUT: Duration = Duration::from_secs(600);
+
+// Samplings [sampling_offset + 1, sampling_offset + sampling] of a region of the frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Job {
+    pub width: u32,
+    pub height: u32,
+    pub region: Region,
+    pub sampling_offset: u32,
+    pub sampling: u32,
+}
+
+// Split by tiles if given, and by chunks of samplings
+pub fn split_jobs(
+    width: u32,
+    height: u32,
+    region: Region,
+    tiling: Option<Tiling>,
+    sampling: u32,
+    job_sampling: u32,
+) -> Vec<Job> {
+    let tiles = match tiling {
+        Some(tiling) => tiling
+            .tiles(region.width, region.height)
+            .iter()
+            .map(|tile| {
+                Region::new(
+                    region.x + tile.x,
+                    region.y + tile.y,
+                    tile.width,
+                    tile.height,
+                )
+            })
+            .collect(),
+        None => vec![region],
+    };
+    let job_sampling = job_sampling.max(1);
+
+    let mut jobs = vec![];
+    for sampling_offset in (0..sampling).step_by(job_sampling as usize) {
+        for tile in &tiles {
+            jobs.push(Job {
+                width,
+                height,
+                region: *tile,
+                sampling_offset,
+                sampling: job_sampling.min(sampling - sampling_offset),
+            });
+        }
+    }
+    jobs
+}
+
+fn write_job<W: Write>(writer: &mut W, job: &Job) -> io::Result<()> {
+    write_u32(writer, MESSAGE_JOB)?;
+    write_u32(writer, job.width)?;
+    write_u32(writer, job.height)?;
+    write_u32(writer, job.region.x)?;
+    write_u32(writer, job.region.y)?;
+    write_u32(writer, job.region.width)?;
+    write_u32(writer, job.region.height)?;
+    write_u32(writer, job.sampling_offset)?;
+    write_u32(writer, job.sampling)?;
+    writer.flush()
+}
+
+fn read_job<R: Read>(reader: &mut R) -> io::Result<Job> {
+    Ok(Job {
+        width: read_u32(reader)?,
+        height: read_u32(reader)?,
+        region: Region::new(
+            read_u32(reader)?,
+            read_u32(reader)?,
+            read_u32(reader)?,
+            read_u32(reader)?,
+        ),
+        sampling_offset: read_u32(reader)?,
+        sampling: read_u32(reader)?,
+    })
+}
+
+struct Coordinator {
+    scene_hash: u64,
+    worker_timeout: Duration,
+    jobs: Mutex<VecDeque<Job>>,
+    num_of_job: usize,
+    completed: AtomicUsize,
+    // Workers that passed the handshake, in total and still connected
+    accepted_workers: AtomicUsize,
+    active_workers: AtomicUsize,
+    accumulation: Mutex<AccumulationBuffer>,
+}
+
+impl Coordinator {
+    fn is_done(&self) -> bool {
+        self.completed.load(Ordering::SeqCst) == self.num_of_job
+    }
+
+    // Every worker that took jobs is gone, leaving jobs that nobody renders
+    fn is_abandoned(&self) -> bool {
+        self.accepted_workers.load(Ordering::SeqCst) > 0
+            && self.active_workers.load(Ordering::SeqCst) == 0
+            && !self.is_done()
+    }
+
+    fn handle_worker(&self, stream: TcpStream) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(self.worker_timeout))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = BufWriter::new(stream);
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        let version = read_u32(&mut reader)?;
+        let super_sampling = read_u32(&mut reader)?;
+        let bounce_limit = read_u32(&mut reader)?;
+        let scene_hash = read_u64(&mut reader)?;
+        if &magic != MAGIC
+            || version != VERSION
+            || super_sampling != config::SUPER_SAMPLING
+            || bounce_limit != config::PATHTRACING_BOUNCE_LIMIT
+            || scene_hash != self.scene_hash
+        {
+            write_u32(&mut writer, MESSAGE_REJECTED)?;
+            writer.flush()?;
+            return Err(invalid_data(
+                "Worker has a different version, settings or scene".to_string(),
+            ));
+        }
+
+        self.accepted_workers.fetch_add(1, Ordering::SeqCst);
+        self.active_workers.fetch_add(1, Ordering::SeqCst);
+        let result = self.hand_out_jobs(&mut reader, &mut writer);
+        self.active_workers.fetch_sub(1, Ordering::SeqCst);
+        result
+    }
+
+    fn hand_out_jobs(
+        &self,
+        reader: &mut BufReader<TcpStream>,
+        writer: &mut BufWriter<TcpStream>,
+    ) -> io::Result<()> {
+        loop {
+            let job = self.jobs.lock().unwrap().pop_front();
+            let job = match job {
+                Some(job) => job,
+                None if self.is_done() => {
+                    write_u32(writer, MESSAGE_DONE)?;
+                    return writer.flush();
+                }
+                None => {
+                    // Jobs of failed workers may come back to the queue
+                    thread::sleep(POLL_INTERVAL);
+                    continue;
+                }
+            };
+
+            let result = write_job(writer, &job)
+                .and_then(|_| checkpoint::read_from(reader, self.scene_hash, job.width, job.height))
+                .map(|(_, accumulation)| accumulation)
+                .map_err(|e| match e.kind() {
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("no reply within {} sec", self.worker_timeout.as_secs_f64()),
+                    ),
+                    _ => e,
+                });
+            let merged = result.and_then(|result| {
+                if result.region != job.region {
+                    return Err(invalid_data(format!(
+                        "Worker returned region {:?} for {:?}",
+                        result.region, job.region
+                    )));
+                }
+                self.accumulation
+                    .lock()
+                    .unwrap()
+                    .merge(&result)
+                    .map_err(invalid_data)
+            });
+
+            // The job goes back to the queue for the remaining workers
+            if let Err(e) = merged {
+                self.jobs.lock().unwrap().push_back(job);
+                return Err(e);
+            }
+
+            let completed = self.completed.fetch_add(1, Ordering::SeqCst) + 1;
+            print!("distributed: {}/{} jobs done\r", completed, self.num_of_job);
+            let _ = io::stdout().flush();
+        }
+    }
+}
+
+// Hands out jobs to workers connecting to the listener until all of them are merged.
+// Workers not replying within worker_timeout are dropped and their jobs handed out again.
+// Fails once every worker that took jobs is gone with jobs left, and no new worker
+// connects within worker_timeout
+pub fn serve(
+    listener: TcpListener,
+    scene_hash: u64,
+    accumulation: AccumulationBuffer,
+    jobs: Vec<Job>,
+    worker_timeout: Duration,
+) -> io::Result<AccumulationBuffer> {
+    let coordinator = Arc::new(Coordinator {
+        scene_hash,
+        worker_timeout,
+        num_of_job: jobs.len(),
+        jobs: Mutex::new(jobs.into_iter().collect()),
+        completed: AtomicUsize::new(0),
+        accepted_workers: AtomicUsize::new(0),
+        active_workers: AtomicUsize::new(0),
+        accumulation: Mutex::new(accumulation),
+    });
+
+    listener.set_nonblocking(true)?;
+    let mut workers = vec![];
+    let mut abandoned_since = None;
+    while !coordinator.is_done() {
+        if !coordinator.is_abandoned() {
+            abandoned_since = None;
+        } else if abandoned_since.get_or_insert_with(Instant::now).elapsed() > worker_timeout {
+            let left = coordinator.num_of_job - coordinator.completed.load(Ordering::SeqCst);
+            return Err(io::Error::other(format!(
+                "All workers failed with {} of {} jobs left, and none connected within {} sec",
+                left,
+                coordinator.num_of_job,
+                worker_timeout.as_secs_f64()
+            )));
+        }
+        match listener.accept() {
+            Ok((stream, address)) => {
+                let coordinator = coordinator.clone();
+                workers.push(thread::spawn(move || {
+                    if let Err(e) = coordinator.handle_worker(stream) {
+                        println!("\nWorker {} failed: {}", address, e);
+                    }
+                }));
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(POLL_INTERVAL);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    for worker in workers {
+        let _ = worker.join();
+    }
+
+    let coordinator = Arc::try_unwrap(coordinator).unwrap_or_else(|_| unreachable!());
+    Ok(coordinator.accumulation.into_inner().unwrap())
+}
+
+// Renders jobs from the coordinator until it has no more, returns the number of jobs done
+pub fn work<A, R, F>(
+    address: A,
+    scene: &dyn Illuminable,
+    camera: &Camera,
+    scene_hash: u64,
+    mut renderer_for: F,
+) -> io::Result<u32>
+where
+    A: ToSocketAddrs,
+    R: Renderer,
+    F: FnMut(&Job) -> R,
+{
+    let stream = TcpStream::connect(address)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    writer.write_all(MAGIC)?;
+    write_u32(&mut writer, VERSION)?;
+    write_u32(&mut writer, config::SUPER_SAMPLING)?;
+    write_u32(&mut writer, config::PATHTRACING_BOUNCE_LIMIT)?;
+    write_u64(&mut writer, scene_hash)?;
+    writer.flush()?;
+
+    let mut num_of_job = 0;
+    loop {
+        match read_u32(&mut reader)? {
+            MESSAGE_JOB => {
+                let job = read_job(&mut reader)?;
+                let mut accumulation =
+                    AccumulationBuffer::with_region(job.width, job.height, job.region);
+                let mut imgbuf = ImageBuffer::new(job.region.width, job.region.height);
+                let mut renderer = renderer_for(&job);
+                renderer.accumulate(scene, camera, &mut accumulation, &mut imgbuf);
+                let settings = SamplingSettings::of(&renderer);
+                checkpoint::write_to(&mut writer, scene_hash, &settings, &accumulation)?;
+                num_of_job += 1;
+            }
+            MESSAGE_DONE => return Ok(num_of_job),
+            MESSAGE_REJECTED => {
+                return Err(invalid_data(
+                    "Coordinator rejected this worker, check the version, settings and scene"
+                        .to_string(),
+                ))
+            }
+            message => return Err(invalid_data(format!("Unknown message {}", message))),
+        }
+    }
+}
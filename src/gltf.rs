@@ -0,0 +1,911 @@
+use crate::camera::{Camera, FieldOfView, LensShape};
+use crate::color::Color;
+use crate::config;
+use crate::io_util::{invalid_data, read_u32};
+use crate::light::DiskLight;
+use crate::material::{Material, SurfaceType};
+use crate::matrix::Matrix44;
+use crate::mesh::{BvhMesh, Face, Mesh};
+use crate::quaternion::Quaternion;
+use crate::rayintersectable::{Disk, Intersectable, Sphere};
+use crate::scene::{Scene, Skybox};
+use crate::scene_graph::{Group, SceneNode};
+use crate::texture::{ImageTexture, Texture};
+use crate::vector::{Vector2, Vector3};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use image::DynamicImage;
+use percent_encoding::percent_decode_str;
+use serde_json::{Map, Value};
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// Radius of the spheres and disks standing in for point and spot lights, in scene units
+pub const PUNCTUAL_LIGHT_RADIUS: f64 = 0.01;
+
+const GLB_MAGIC: &[u8] = b"glTF";
+const GLB_JSON_CHUNK: u32 = 0x4e4f_534a;
+const GLB_BIN_CHUNK: u32 = 0x004e_4942;
+
+// Elements of the default scene of a glTF file, and the cameras placed in it in the order
+// of the nodes
+pub struct GltfScene {
+    pub scene: Scene,
+    pub cameras: Vec<Camera>,
+}
+
+pub struct GltfLoader;
+
+impl GltfLoader {
+    // .gltf with embedded or external buffers and images, or .glb. Cameras resolve their
+    // field of view for images of aspect_ratio
+    pub fn load(path: &str, aspect_ratio: f64) -> io::Result<GltfScene> {
+        let directory = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+        fs::read(path)
+            .and_then(|bytes| GltfLoader::parse(&bytes, directory, aspect_ratio))
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))
+    }
+
+    // External files are looked up relative to directory
+    pub fn parse(bytes: &[u8], directory: &Path, aspect_ratio: f64) -> io::Result<GltfScene> {
+        let (json, binary) = if bytes.starts_with(GLB_MAGIC) {
+            split_glb(bytes).map_err(invalid_data)?
+        } else {
+            (bytes, None)
+        };
+        let root: Value = serde_json::from_slice(json).map_err(|e| invalid_data(e.to_string()))?;
+
+        let document = Document::new(root, binary, directory).map_err(invalid_data)?;
+        document.scene(aspect_ratio).map_err(invalid_data)
+    }
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Option<u32> {
+    read_u32(&mut bytes.get(offset..)?).ok()
+}
+
+// JSON chunk and the optional binary chunk of a GLB container
+fn split_glb(bytes: &[u8]) -> Result<(&[u8], Option<&[u8]>), String> {
+    let version = u32_at(bytes, 4).ok_or("truncated GLB header")?;
+    if version != 2 {
+        return Err(format!("GLB version {} is not supported", version));
+    }
+    let length = (u32_at(bytes, 8).ok_or("truncated GLB header")? as usize).min(bytes.len());
+
+    let mut chunks = vec![];
+    let mut offset = 12;
+    while offset + 8 <= length {
+        let chunk_length = u32_at(bytes, offset).unwrap() as usize;
+        let chunk_type = u32_at(bytes, offset + 4).unwrap();
+        let data = (offset + 8)
+            .checked_add(chunk_length)
+            .and_then(|end| bytes.get(offset + 8..end))
+            .ok_or("truncated GLB chunk")?;
+        chunks.push((chunk_type, data));
+        // Chunks are padded to 4 bytes
+        offset += 8 + chunk_length.div_ceil(4) * 4;
+    }
+
+    match chunks.first() {
+        Some(&(GLB_JSON_CHUNK, json)) => {
+            let binary = chunks
+                .get(1)
+                .filter(|(chunk_type, _)| *chunk_type == GLB_BIN_CHUNK)
+                .map(|(_, data)| *data);
+            Ok((json, binary))
+        }
+        _ => Err("GLB does not start with a JSON chunk".to_string()),
+    }
+}
+
+// Contents of a data URI or of a file relative to directory
+fn read_uri(uri: &str, directory: &Path) -> Result<Vec<u8>, String> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        match data.split_once(";base64,") {
+            Some((_, encoded)) => STANDARD.decode(encoded).map_err(|e| e.to_string()),
+            None => Err("data URIs must be base64".to_string()),
+        }
+    } else {
+        // Relative URIs may escape characters as %XX
+        let path = directory.join(&*percent_decode_str(uri).decode_utf8_lossy());
+        fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+// Members of an array of the object, empty if missing
+fn array<'a>(json: &'a Value, key: &str) -> &'a [Value] {
+    json.get(key)
+        .and_then(Value::as_array)
+        .map_or(&[], Vec::as_slice)
+}
+
+// Non-negative integer, as indexes and counts are
+fn as_index(value: &Value) -> Option<usize> {
+    value.as_u64().and_then(|n| usize::try_from(n).ok())
+}
+
+fn number(json: &Value, key: &str, default: f64) -> Result<f64, String> {
+    match json.get(key) {
+        Some(value) => value
+            .as_f64()
+            .ok_or_else(|| format!("{} is not a number", key)),
+        None => Ok(default),
+    }
+}
+
+fn numbers(json: &Value, key: &str, default: &[f64]) -> Result<Vec<f64>, String> {
+    match json.get(key) {
+        Some(value) => value
+            .as_array()
+            .filter(|values| values.len() == default.len())
+            .and_then(|values| values.iter().map(Value::as_f64).collect())
+            .ok_or_else(|| format!("{} is not {} numbers", key, default.len())),
+        None => Ok(default.to_vec()),
+    }
+}
+
+fn index(json: &Value, key: &str) -> Result<Option<usize>, String> {
+    match json.get(key) {
+        Some(value) => as_index(value)
+            .map(Some)
+            .ok_or_else(|| format!("{} is not an index", key)),
+        None => Ok(None),
+    }
+}
+
+// Element of a top level array
+fn item<'a>(root: &'a Value, key: &str, index: usize) -> Result<&'a Value, String> {
+    array(root, key)
+        .get(index)
+        .ok_or_else(|| format!("{}[{}] is not defined", key, index))
+}
+
+fn with_context<T>(result: Result<T, String>, context: &str, index: usize) -> Result<T, String> {
+    result.map_err(|e| format!("{}[{}]: {}", context, index, e))
+}
+
+fn color(values: &[f64]) -> Color {
+    Color::new(values[0], values[1], values[2])
+}
+
+// glTF matrices are column major
+fn column_major(values: &[f64]) -> Matrix44 {
+    let mut matrix = Matrix44::identity();
+    for (i, value) in values.iter().enumerate() {
+        matrix[i % 4][i / 4] = *value;
+    }
+    matrix
+}
+
+// Transform of a node relative to its parent
+fn local_matrix(node: &Value) -> Result<Matrix44, String> {
+    if node.get("matrix").is_some() {
+        return Ok(column_major(&numbers(node, "matrix", &[0.0; 16])?));
+    }
+    let t = numbers(node, "translation", &[0.0; 3])?;
+    let r = numbers(node, "rotation", &[0.0, 0.0, 0.0, 1.0])?;
+    let s = numbers(node, "scale", &[1.0; 3])?;
+    let rotation = Quaternion {
+        w: r[3],
+        x: r[0],
+        y: r[1],
+        z: r[2],
+    };
+    Ok(Matrix44::translate(t[0], t[1], t[2])
+        * rotation.normalized().to_matrix()
+        * Matrix44::scale(s[0], s[1], s[2]))
+}
+
+// Decoded values of an accessor, components of each element in a row
+struct Elements {
+    values: Vec<f64>,
+    components: usize,
+}
+
+impl Elements {
+    fn get(&self, i: usize) -> &[f64] {
+        &self.values[i * self.components..(i + 1) * self.components]
+    }
+
+    fn len(&self) -> usize {
+        self.values.len() / self.components
+    }
+}
+
+// Value of the little endian bytes of a component, normalized to [0, 1] or [-1, 1] for
+// normalized integers
+type ComponentReader = fn(&[u8], bool) -> f64;
+
+// Component size in bytes and its reader
+fn component_reader(component_type: usize) -> Option<(usize, ComponentReader)> {
+    fn norm(value: f64, max: f64, normalized: bool) -> f64 {
+        if normalized {
+            (value / max).max(-1.0)
+        } else {
+            value
+        }
+    }
+    let reader: (usize, ComponentReader) = match component_type {
+        5120 => (1, |b, n| norm(b[0] as i8 as f64, 127.0, n)),
+        5121 => (1, |b, n| norm(b[0] as f64, 255.0, n)),
+        5122 => (2, |b, n| {
+            norm(i16::from_le_bytes([b[0], b[1]]) as f64, 32767.0, n)
+        }),
+        5123 => (2, |b, n| {
+            norm(u16::from_le_bytes([b[0], b[1]]) as f64, 65535.0, n)
+        }),
+        5125 => (4, |b, _| {
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64
+        }),
+        5126 => (4, |b, _| {
+            f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64
+        }),
+        _ => return None,
+    };
+    Some(reader)
+}
+
+// Point lights become small emissive spheres of the same intensity and spot lights small disks
+// facing the -z of the node with the intensity along it, lighting the outer cone. Directional
+// lights have no counterpart
+fn punctual_light(
+    light: &Value,
+    matrix: &Matrix44,
+) -> Result<Option<Box<dyn Intersectable>>, String> {
+    let intensity = color(&numbers(light, "color", &[1.0; 3])?) * number(light, "intensity", 1.0)?;
+
+    // Intensity of a sphere, or of a disk along its normal, is its radiance over its
+    // projected area
+    let material = Material::new(
+        SurfaceType::Diffuse,
+        Texture::black(),
+        Texture::of_color(intensity / (config::PI * PUNCTUAL_LIGHT_RADIUS * PUNCTUAL_LIGHT_RADIUS)),
+        Texture::white(),
+    );
+    let center = *matrix * Vector3::zero();
+
+    let light: Box<dyn Intersectable> = match light.get("type").and_then(Value::as_str) {
+        Some("point") => Box::new(Sphere {
+            center,
+            radius: PUNCTUAL_LIGHT_RADIUS,
+            material,
+        }),
+        Some("spot") => {
+            let spot = light.get("spot").unwrap_or(&Value::Null);
+            let outer_cone_angle = number(spot, "outerConeAngle", config::PI / 4.0)?;
+            let disk = Disk {
+                center,
+                normal: matrix
+                    .transform_direction(&Vector3::new(0.0, 0.0, -1.0))
+                    .normalized(),
+                radius: PUNCTUAL_LIGHT_RADIUS,
+                material,
+            };
+            Box::new(DiskLight::new(disk).with_spread(2.0 * outer_cone_angle))
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(light))
+}
+
+struct Document {
+    root: Value,
+    directory: PathBuf,
+    buffers: Vec<Vec<u8>>,
+    images: Vec<Arc<DynamicImage>>,
+    // Read once for all the primitives referring to them
+    materials: Vec<Material>,
+}
+
+impl Document {
+    fn new(root: Value, binary: Option<&[u8]>, directory: &Path) -> Result<Document, String> {
+        let mut document = Document {
+            root,
+            directory: directory.to_path_buf(),
+            buffers: vec![],
+            images: vec![],
+            materials: vec![],
+        };
+
+        for (i, buffer) in array(&document.root, "buffers").iter().enumerate() {
+            let data = match buffer.get("uri").and_then(Value::as_str) {
+                Some(uri) => with_context(read_uri(uri, directory), "buffers", i)?,
+                // The first buffer of a GLB without uri is its binary chunk
+                None if i == 0 => binary.ok_or("buffers[0] has no uri or GLB chunk")?.to_vec(),
+                None => return Err(format!("buffers[{}] has no uri", i)),
+            };
+            let length = with_context(index(buffer, "byteLength"), "buffers", i)?.unwrap_or(0);
+            if data.len() < length {
+                return Err(format!(
+                    "buffers[{}] has {} bytes instead of {}",
+                    i,
+                    data.len(),
+                    length
+                ));
+            }
+            document.buffers.push(data);
+        }
+
+        for i in 0..array(&document.root, "images").len() {
+            let image = with_context(document.image(i), "images", i)?;
+            document.images.push(Arc::new(image));
+        }
+        for i in 0..array(&document.root, "materials").len() {
+            let material = with_context(document.material(Some(i)), "materials", i)?;
+            document.materials.push(material);
+        }
+        document.warn_approximations()?;
+        Ok(document)
+    }
+
+    // Tells once per material and light what does not come through as in the file
+    fn warn_approximations(&self) -> Result<(), String> {
+        let default = Value::Object(Map::new());
+        for (i, material) in array(&self.root, "materials").iter().enumerate() {
+            let pbr = material.get("pbrMetallicRoughness").unwrap_or(&default);
+            let metallic = with_context(number(pbr, "metallicFactor", 1.0), "materials", i)?;
+            if metallic > 0.0 && metallic < 1.0 {
+                eprintln!(
+                    "Warning: materials[{}]: metallicFactor {} is rounded to {}",
+                    i,
+                    metallic,
+                    metallic.round()
+                );
+            }
+            if metallic > 0.0 && pbr.get("metallicRoughnessTexture").is_some() {
+                eprintln!(
+                    "Warning: materials[{}]: metalness of metallicRoughnessTexture is not used",
+                    i
+                );
+            }
+        }
+        for (i, light) in self.lights().iter().enumerate() {
+            match light.get("type").and_then(Value::as_str) {
+                Some("point") => eprintln!(
+                    "Warning: lights[{}]: point light is a sphere of radius {}",
+                    i, PUNCTUAL_LIGHT_RADIUS
+                ),
+                Some("spot") => eprintln!(
+                    "Warning: lights[{}]: spot light is a disk of radius {} with a hard edge at \
+                     outerConeAngle",
+                    i, PUNCTUAL_LIGHT_RADIUS
+                ),
+                Some(other) => eprintln!(
+                    "Warning: lights[{}]: {} lights are not supported and left out",
+                    i, other
+                ),
+                None => return Err(format!("lights[{}] has no type", i)),
+            }
+        }
+        Ok(())
+    }
+
+    fn lights(&self) -> &[Value] {
+        self.root
+            .get("extensions")
+            .and_then(|e| e.get("KHR_lights_punctual"))
+            .map_or(&[], |e| array(e, "lights"))
+    }
+
+    fn image(&self, i: usize) -> Result<DynamicImage, String> {
+        let image = item(&self.root, "images", i)?;
+        let bytes = match (
+            image.get("uri").and_then(Value::as_str),
+            index(image, "bufferView")?,
+        ) {
+            (Some(uri), _) => read_uri(uri, &self.directory)?,
+            (None, Some(view)) => self.buffer_view(view)?.0.to_vec(),
+            (None, None) => return Err("no uri or bufferView".to_string()),
+        };
+        image::load_from_memory(&bytes).map_err(|e| e.to_string())
+    }
+
+    // Bytes of a buffer view and its stride if interleaved
+    fn buffer_view(&self, i: usize) -> Result<(&[u8], Option<usize>), String> {
+        let view = item(&self.root, "bufferViews", i)?;
+        let context = |result| with_context(result, "bufferViews", i);
+        let buffer = context(index(view, "buffer"))?.ok_or("bufferView without buffer")?;
+        let offset = context(index(view, "byteOffset"))?.unwrap_or(0);
+        let length = context(index(view, "byteLength"))?.unwrap_or(0);
+        let stride = context(index(view, "byteStride"))?;
+        let data = self
+            .buffers
+            .get(buffer)
+            .and_then(|data| data.get(offset..offset.checked_add(length)?))
+            .ok_or_else(|| format!("bufferViews[{}] is out of its buffer", i))?;
+        Ok((data, stride))
+    }
+
+    fn accessor(&self, i: usize) -> Result<Elements, String> {
+        with_context(self.read_accessor(i), "accessors", i)
+    }
+
+    fn read_accessor(&self, i: usize) -> Result<Elements, String> {
+        let accessor = item(&self.root, "accessors", i)?;
+        if accessor.get("sparse").is_some() {
+            return Err("sparse accessors are not supported".to_string());
+        }
+        let count = index(accessor, "count")?.ok_or("no count")?;
+        let components = match accessor.get("type").and_then(Value::as_str) {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") => 4,
+            Some(other) => return Err(format!("type {} is not supported", other)),
+            None => return Err("no type".to_string()),
+        };
+        let component_type = index(accessor, "componentType")?.ok_or("no componentType")?;
+        let normalized = accessor
+            .get("normalized")
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+        let (size, read) = component_reader(component_type)
+            .ok_or_else(|| format!("componentType {} is not supported", component_type))?;
+
+        // Accessors without a buffer view are zeros
+        let view = match index(accessor, "bufferView")? {
+            Some(view) => view,
+            None => {
+                let length = count
+                    .checked_mul(components)
+                    .ok_or_else(|| format!("{} elements are too many", count))?;
+                return Ok(Elements {
+                    values: vec![0.0; length],
+                    components,
+                });
+            }
+        };
+        let (data, stride) = self.buffer_view(view)?;
+        let offset = index(accessor, "byteOffset")?.unwrap_or(0);
+        let stride = stride.unwrap_or(size * components);
+        if stride < size * components {
+            return Err(format!(
+                "byteStride of bufferViews[{}] overlaps elements",
+                view
+            ));
+        }
+        let end = match count {
+            0 => Some(offset),
+            _ => stride
+                .checked_mul(count - 1)
+                .and_then(|last| last.checked_add(offset))
+                .and_then(|last| last.checked_add(size * components)),
+        };
+        if end.filter(|end| *end <= data.len()).is_none() {
+            return Err(format!(
+                "{} elements are out of bufferViews[{}]",
+                count, view
+            ));
+        }
+
+        let mut values = Vec::with_capacity(count * components);
+        for e in 0..count {
+            for c in 0..components {
+                let start = offset + e * stride + c * size;
+                values.push(read(&data[start..start + size], normalized));
+            }
+        }
+        Ok(Elements { values, components })
+    }
+
+    // Image of a texture info, e.g. baseColorTexture, with color as its factor
+    fn texture(&self, info: Option<&Value>, color: Color) -> Result<Texture, String> {
+        let image = match info {
+            Some(info) => {
+                let texture = index(info, "index")?.ok_or("texture info without index")?;
+                let source = index(item(&self.root, "textures", texture)?, "source")?;
+                match source {
+                    Some(source) => Some(
+                        self.images
+                            .get(source)
+                            .ok_or_else(|| format!("images[{}] is not defined", source))?
+                            .clone(),
+                    ),
+                    None => None,
+                }
+            }
+            None => None,
+        };
+        Ok(Texture {
+            image_texture: image.map(|image| ImageTexture { image }),
+            color,
+        })
+    }
+
+    // Metallic-roughness materials have a single lobe here. Transmissive materials refract,
+    // metals are GGX whose reflectance at normal incidence is the base color and dielectrics
+    // are diffuse, rounding the metalness in between. Normal, occlusion and the metalness of
+    // textures are not used
+    fn material(&self, i: Option<usize>) -> Result<Material, String> {
+        let default = Value::Object(Map::new());
+        let material = match i {
+            Some(i) => item(&self.root, "materials", i)?,
+            None => &default,
+        };
+        let pbr = material.get("pbrMetallicRoughness").unwrap_or(&default);
+        let extensions = material.get("extensions").unwrap_or(&default);
+        let extension = |name: &str| extensions.get(name).unwrap_or(&default);
+
+        let mut base_color = color(&numbers(pbr, "baseColorFactor", &[1.0; 4])?);
+        let metallic = number(pbr, "metallicFactor", 1.0)?;
+        let roughness = number(pbr, "roughnessFactor", 1.0)?;
+        let transmission = number(
+            extension("KHR_materials_transmission"),
+            "transmissionFactor",
+            0.0,
+        )?;
+        let surface = if transmission > 0.5 {
+            SurfaceType::Refraction {
+                refractive_index: number(extension("KHR_materials_ior"), "ior", 1.5)?,
+            }
+        } else if metallic >= 0.5 {
+            // Fresnel reflectance is a single value, that of the brightest channel, and the
+            // others follow it in proportion
+            let f0 = base_color.x.max(base_color.y).max(base_color.z);
+            if f0 > 0.0 {
+                base_color = base_color / f0;
+            }
+            SurfaceType::GGX { f0 }
+        } else {
+            SurfaceType::Diffuse
+        };
+
+        // Roughness is perceptual, squared into the GGX alpha of materials like the MTL
+        // loader does. In textures it's the green channel, sampled as linear data
+        let mut roughness_texture = self.texture(
+            pbr.get("metallicRoughnessTexture"),
+            Color::all_of(roughness * roughness),
+        )?;
+        if let Some(ref mut texture) = roughness_texture.image_texture {
+            let mut green = texture.image.to_rgb8();
+            for pixel in green.pixels_mut() {
+                let alpha = (pixel.0[1] as f64 / 255.0).powi(2);
+                pixel.0 = [(alpha * 255.0).round() as u8; 3];
+            }
+            texture.image = Arc::new(DynamicImage::ImageRgb8(green));
+        }
+
+        let strength = number(
+            extension("KHR_materials_emissive_strength"),
+            "emissiveStrength",
+            1.0,
+        )?;
+        let emission = color(&numbers(material, "emissiveFactor", &[0.0; 3])?) * strength;
+
+        Ok(Material::new(
+            surface,
+            self.texture(pbr.get("baseColorTexture"), base_color)?,
+            self.texture(material.get("emissiveTexture"), emission)?,
+            roughness_texture,
+        ))
+    }
+
+    // Triangles of all primitives of a mesh in its own space, with a material per primitive
+    fn mesh(&self, i: usize) -> Result<Mesh, String> {
+        let gltf_mesh = item(&self.root, "meshes", i)?;
+        let mut mesh = Mesh {
+            vertexes: vec![],
+            colors: vec![],
+            normals: vec![],
+            uvs: vec![],
+            faces: vec![],
+            material: self.material(None)?,
+            materials: vec![],
+        };
+
+        for (p, primitive) in array(gltf_mesh, "primitives").iter().enumerate() {
+            let context = format!("primitives[{}]", p);
+            self.add_primitive(primitive, &mut mesh)
+                .map_err(|e| format!("{}: {}", context, e))?;
+        }
+        Ok(mesh)
+    }
+
+    fn add_primitive(&self, primitive: &Value, mesh: &mut Mesh) -> Result<(), String> {
+        // Points and lines have no area to hit
+        let mode = index(primitive, "mode")?.unwrap_or(4);
+        if mode < 4 {
+            return Ok(());
+        }
+        let default = Value::Object(Map::new());
+        let attributes = primitive.get("attributes").unwrap_or(&default);
+        let positions = self.accessor(index(attributes, "POSITION")?.ok_or("no POSITION")?)?;
+        let normals = index(attributes, "NORMAL")?
+            .map(|a| self.accessor(a))
+            .transpose()?;
+        let uvs = index(attributes, "TEXCOORD_0")?
+            .map(|a| self.accessor(a))
+            .transpose()?;
+        let count = positions.len();
+        if positions.components != 3
+            || normals
+                .as_ref()
+                .is_some_and(|n| n.components != 3 || n.len() != count)
+            || uvs
+                .as_ref()
+                .is_some_and(|t| t.components != 2 || t.len() != count)
+        {
+            return Err("attributes do not match POSITION".to_string());
+        }
+
+        let vertex_offset = mesh.vertexes.len();
+        let normal_offset = mesh.normals.len();
+        let uv_offset = mesh.uvs.len();
+        for v in 0..count {
+            let p = positions.get(v);
+            mesh.vertexes.push(Vector3::new(p[0], p[1], p[2]));
+        }
+        if let Some(ref normals) = normals {
+            for v in 0..count {
+                let n = normals.get(v);
+                mesh.normals
+                    .push(Vector3::new(n[0], n[1], n[2]).normalized());
+            }
+        }
+        if let Some(ref uvs) = uvs {
+            // The first row of glTF images is at v = 0
+            for v in 0..count {
+                let t = uvs.get(v);
+                mesh.uvs.push(Vector2::new(t[0], 1.0 - t[1]));
+            }
+        }
+
+        let indexes: Vec<usize> = match index(primitive, "indices")? {
+            Some(accessor) => {
+                let indexes = self.accessor(accessor)?;
+                if indexes.components != 1 {
+                    return Err("indices are not scalars".to_string());
+                }
+                indexes.values.iter().map(|i| *i as usize).collect()
+            }
+            None => (0..count).collect(),
+        };
+        if let Some(i) = indexes.iter().find(|i| **i >= count) {
+            return Err(format!("index {} is out of {} vertices", i, count));
+        }
+        let triangles: Vec<[usize; 3]> = match mode {
+            4 => indexes
+                .chunks_exact(3)
+                .map(|t| [t[0], t[1], t[2]])
+                .collect(),
+            // Strips alternate the winding
+            5 => (0..indexes.len().saturating_sub(2))
+                .map(|k| {
+                    if k % 2 == 0 {
+                        [indexes[k], indexes[k + 1], indexes[k + 2]]
+                    } else {
+                        [indexes[k], indexes[k + 2], indexes[k + 1]]
+                    }
+                })
+                .collect(),
+            6 => (1..indexes.len().saturating_sub(1))
+                .map(|k| [indexes[k], indexes[k + 1], indexes[0]])
+                .collect(),
+            _ => return Err(format!("mode {} is not supported", mode)),
+        };
+
+        let material = match index(primitive, "material")? {
+            Some(m) => {
+                let material = self
+                    .materials
+                    .get(m)
+                    .ok_or_else(|| format!("materials[{}] is not defined", m))?;
+                mesh.materials.push(material.clone());
+                Some(mesh.materials.len() - 1)
+            }
+            None => None,
+        };
+        for t in triangles {
+            mesh.faces.push(Face {
+                normals: normals.as_ref().map(|_| t.map(|i| normal_offset + i)),
+                uvs: uvs.as_ref().map(|_| t.map(|i| uv_offset + i)),
+                material,
+                ..Face::new(
+                    vertex_offset + t[0],
+                    vertex_offset + t[1],
+                    vertex_offset + t[2],
+                )
+            });
+        }
+        Ok(())
+    }
+
+    // Camera at the origin of a node looking down its -z with y up
+    fn camera(&self, i: usize, matrix: &Matrix44, aspect_ratio: f64) -> Result<Camera, String> {
+        let camera = item(&self.root, "cameras", i)?;
+        let position = *matrix * Vector3::zero();
+        let forward = matrix.transform_direction(&Vector3::new(0.0, 0.0, -1.0));
+        let y_up = matrix.transform_direction(&Vector3::new(0.0, 1.0, 0.0));
+        let target = position + forward.normalized();
+
+        match camera.get("type").and_then(Value::as_str) {
+            Some("perspective") => {
+                let perspective = camera.get("perspective").ok_or("no perspective")?;
+                let yfov = number(perspective, "yfov", 0.0)?;
+                Ok(Camera::new(
+                    position,
+                    target,
+                    y_up,
+                    FieldOfView::Vertical(yfov.to_degrees()),
+                    aspect_ratio,
+                    LensShape::Circle,
+                    0.0,
+                    1.0,
+                ))
+            }
+            Some("orthographic") => {
+                let orthographic = camera.get("orthographic").ok_or("no orthographic")?;
+                let xmag = number(orthographic, "xmag", 1.0)?;
+                let ymag = number(orthographic, "ymag", 1.0)?;
+                // Normalized coordinates span the shorter side
+                let view_width = if aspect_ratio >= 1.0 {
+                    2.0 * ymag * aspect_ratio
+                } else {
+                    2.0 * xmag
+                };
+                Ok(Camera::orthographic(
+                    position,
+                    target,
+                    y_up,
+                    view_width,
+                    aspect_ratio,
+                    LensShape::Circle,
+                    0.0,
+                    1.0,
+                ))
+            }
+            _ => Err("type is not perspective or orthographic".to_string()),
+        }
+    }
+
+    // Element of a light of KHR_lights_punctual placed by a node, None for those left out
+    fn light(&self, i: usize, matrix: &Matrix44) -> Result<Option<Box<dyn Intersectable>>, String> {
+        let light = self
+            .lights()
+            .get(i)
+            .ok_or_else(|| format!("lights[{}] is not defined", i))?;
+        with_context(punctual_light(light, matrix), "lights", i)
+    }
+
+    fn scene(&self, aspect_ratio: f64) -> Result<GltfScene, String> {
+        let nodes = array(&self.root, "nodes");
+        let roots: Vec<usize> =
+            match index(&self.root, "scene")?.or(if array(&self.root, "scenes").is_empty() {
+                None
+            } else {
+                Some(0)
+            }) {
+                Some(scene) => array(item(&self.root, "scenes", scene)?, "nodes")
+                    .iter()
+                    .map(|n| as_index(n).ok_or("scene node is not an index"))
+                    .collect::<Result<_, _>>()?,
+                // Without scenes every node that is not a child is a root
+                None => {
+                    let mut is_child = vec![false; nodes.len()];
+                    for node in nodes {
+                        for child in array(node, "children") {
+                            if let Some(c) = as_index(child).filter(|c| *c < nodes.len()) {
+                                is_child[c] = true;
+                            }
+                        }
+                    }
+                    (0..nodes.len()).filter(|n| !is_child[*n]).collect()
+                }
+            };
+
+        let mut context = NodeContext {
+            aspect_ratio,
+            ancestors: vec![],
+            meshes: vec![None; array(&self.root, "meshes").len()],
+            material: Arc::new(self.material(None)?),
+            cameras: vec![],
+            lights: vec![],
+        };
+        let mut root = Group::new(Matrix44::identity());
+        for n in roots {
+            let node = self.node(n, &Matrix44::identity(), &mut context)?;
+            root.children.push(node);
+        }
+
+        let mut elements = root.flatten();
+        elements.extend(context.lights);
+        Ok(GltfScene {
+            scene: Scene::new(
+                elements,
+                Skybox {
+                    px_texture: Texture::black(),
+                    nx_texture: Texture::black(),
+                    py_texture: Texture::black(),
+                    ny_texture: Texture::black(),
+                    pz_texture: Texture::black(),
+                    nz_texture: Texture::black(),
+                    intensity: Vector3::zero(),
+                },
+            ),
+            cameras: context.cameras,
+        })
+    }
+
+    // Group of a node and its descendants, collecting the cameras and lights in them with
+    // their world transforms
+    fn node(
+        &self,
+        n: usize,
+        parent: &Matrix44,
+        context: &mut NodeContext,
+    ) -> Result<SceneNode, String> {
+        if context.ancestors.contains(&n) {
+            return Err(format!("nodes[{}] is its own ancestor", n));
+        }
+        let node = item(&self.root, "nodes", n)?;
+        let mut group = Group::new(with_context(local_matrix(node), "nodes", n)?);
+        let matrix = *parent * group.matrix;
+        self.add_node(node, &matrix, &mut group, context)
+            .map_err(|e| format!("nodes[{}]: {}", n, e))?;
+
+        context.ancestors.push(n);
+        for child in array(node, "children") {
+            let child =
+                as_index(child).ok_or_else(|| format!("nodes[{}]: child is not an index", n))?;
+            let child = self.node(child, &matrix, context)?;
+            group.children.push(child);
+        }
+        context.ancestors.pop();
+        Ok(group.into())
+    }
+
+    fn add_node(
+        &self,
+        node: &Value,
+        matrix: &Matrix44,
+        group: &mut Group,
+        context: &mut NodeContext,
+    ) -> Result<(), String> {
+        if let Some(m) = index(node, "mesh")? {
+            let mesh = match context.meshes.get(m).cloned().flatten() {
+                Some(mesh) => mesh,
+                None => {
+                    let mesh = Arc::new(BvhMesh::from(with_context(self.mesh(m), "meshes", m)?));
+                    context.meshes[m] = Some(mesh.clone());
+                    mesh
+                }
+            };
+            if !mesh.mesh.faces.is_empty() {
+                group
+                    .children
+                    .push(SceneNode::mesh(mesh, context.material.clone()));
+            }
+        }
+        if let Some(c) = index(node, "camera")? {
+            let camera = with_context(self.camera(c, matrix, context.aspect_ratio), "cameras", c)?;
+            context.cameras.push(camera);
+        }
+        let light = node
+            .get("extensions")
+            .and_then(|e| e.get("KHR_lights_punctual"))
+            .map(|e| index(e, "light"))
+            .transpose()?
+            .flatten();
+        if let Some(l) = light {
+            if let Some(light) = self.light(l, matrix)? {
+                context.lights.push(light);
+            }
+        }
+        Ok(())
+    }
+}
+
+// State of the nodes being read, where meshes are shared by all the nodes referring to them
+struct NodeContext {
+    aspect_ratio: f64,
+    // Nodes above the one being read, to stop at cycles
+    ancestors: Vec<usize>,
+    meshes: Vec<Option<Arc<BvhMesh>>>,
+    // For primitives without a material
+    material: Arc<Material>,
+    cameras: Vec<Camera>,
+    lights: Vec<Box<dyn Intersectable>>,
+}
@@ -0,0 +1,79 @@
+use crate::aabb::Aabb;
+use crate::camera::Ray;
+use crate::color::Color;
+use crate::material::Material;
+use crate::matrix::Matrix44;
+use crate::mesh::BvhMesh;
+use crate::rayintersectable::{Intersectable, Intersection};
+use crate::transform::intersect_transformed;
+use std::sync::Arc;
+
+// Mesh shared between instances placed by their own transforms, so that its vertexes and BVH
+// are kept once however many times it appears
+pub struct Instance {
+    pub mesh: Arc<BvhMesh>,
+    // Replaces the main material of the mesh, faces with materials of their own keep them
+    pub material: Arc<Material>,
+    matrix: Matrix44,
+    inverse: Matrix44,
+    // Inverse transpose, which keeps normals perpendicular to the surface
+    normal_matrix: Matrix44,
+    aabb: Aabb,
+}
+
+impl Instance {
+    pub fn new(mesh: Arc<BvhMesh>, matrix: Matrix44, material: Arc<Material>) -> Instance {
+        let inverse = matrix.inverse();
+        Instance {
+            aabb: mesh.aabb().transformed(&matrix),
+            normal_matrix: inverse.transposed(),
+            inverse,
+            matrix,
+            mesh,
+            material,
+        }
+    }
+
+    pub fn matrix(&self) -> &Matrix44 {
+        &self.matrix
+    }
+}
+
+impl Intersectable for Instance {
+    fn aabb(&self) -> Aabb {
+        self.aabb
+    }
+
+    fn intersect(&self, ray: &Ray, intersection: &mut Intersection) -> bool {
+        if !self.aabb.intersect_with_ray(ray).0 {
+            return false;
+        }
+        intersect_transformed(
+            self.mesh.as_ref(),
+            &self.matrix,
+            &self.inverse,
+            &self.normal_matrix,
+            ray,
+            intersection,
+        )
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn material_at(&self, intersection: &Intersection) -> &Material {
+        match intersection.material_index {
+            Some(_) => self.mesh.material_at(intersection),
+            None => &self.material,
+        }
+    }
+
+    fn vertex_color(&self, intersection: &Intersection) -> Color {
+        self.mesh.vertex_color(intersection)
+    }
+
+    fn nee_available(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,39 @@
+use std::io;
+use std::io::{Read, Write};
+
+// Little endian binary values of checkpoints, distributed jobs and GLB containers, and the
+// error of malformed files
+
+pub(crate) fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub(crate) fn write_u32<W: Write>(writer: &mut W, v: u32) -> io::Result<()> {
+    writer.write_all(&v.to_le_bytes())
+}
+
+pub(crate) fn write_u64<W: Write>(writer: &mut W, v: u64) -> io::Result<()> {
+    writer.write_all(&v.to_le_bytes())
+}
+
+pub(crate) fn write_f64<W: Write>(writer: &mut W, v: f64) -> io::Result<()> {
+    writer.write_all(&v.to_le_bytes())
+}
+
+pub(crate) fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub(crate) fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+pub(crate) fn read_f64<R: Read>(reader: &mut R) -> io::Result<f64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
@@ -0,0 +1,408 @@
+use std::fs;
+use std::io;
+
+use crate::vector::{Vector2, Vector3};
+
+// Film radii the exit pupil is bounded at, from the center to the corner
+const PUPIL_BOUNDS_RESOLUTION: usize = 32;
+// Grid of points per axis on the rear plane traced to find the exit pupil
+const PUPIL_GRID_RESOLUTION: usize = 64;
+// Iterations moving the film to bring the focus distance into focus
+const FOCUS_ITERATIONS: usize = 32;
+
+// Surface of a lens prescription in millimeters, rows of lens tables run from the scene
+// side to the film
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LensElement {
+    // Positive if the center of curvature is on the film side, 0 for the aperture stop
+    pub curvature_radius: f64,
+    // Distance along the axis to the next surface, or to the film for the last one
+    pub thickness: f64,
+    // Of the medium behind the surface, 0 is also air
+    pub ior: f64,
+    pub aperture_radius: f64,
+}
+
+impl LensElement {
+    fn is_stop(&self) -> bool {
+        self.curvature_radius == 0.0
+    }
+
+    fn medium_ior(&self) -> f64 {
+        if self.ior == 0.0 {
+            1.0
+        } else {
+            self.ior
+        }
+    }
+}
+
+// Rows of "radius thickness ior aperture_diameter" in millimeters, # starts a comment
+pub fn parse_lens_table(table: &str) -> Result<Vec<LensElement>, String> {
+    let mut elements = vec![];
+    for (i, line) in table.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let values = line
+            .split_whitespace()
+            .map(|v| v.parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("Line {}: {}", i + 1, e))?;
+        match values[..] {
+            [curvature_radius, thickness, ior, aperture_diameter] => elements.push(LensElement {
+                curvature_radius,
+                thickness,
+                ior,
+                aperture_radius: aperture_diameter * 0.5,
+            }),
+            _ => {
+                return Err(format!(
+                    "Line {}: expected radius, thickness, ior and aperture but got {}",
+                    i + 1,
+                    line
+                ))
+            }
+        }
+    }
+    if elements.is_empty() {
+        return Err("Lens table has no elements".to_string());
+    }
+    Ok(elements)
+}
+
+pub fn read_lens_table(path: &str) -> io::Result<Vec<LensElement>> {
+    parse_lens_table(&fs::read_to_string(path)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, e)))
+}
+
+// Region of the rear plane rays from a film radius can pass through, x toward the radius
+#[derive(Clone, Copy, Debug)]
+struct PupilBounds {
+    min: Vector2,
+    max: Vector2,
+}
+
+impl PupilBounds {
+    fn area(&self) -> f64 {
+        let size = self.max - self.min;
+        size.x * size.y
+    }
+}
+
+// Lens in space of millimeters with the film at z = 0 and the scene toward -z
+#[derive(Clone, Debug)]
+pub struct LensSystem {
+    pub elements: Vec<LensElement>,
+    // Half sizes of the film, the sensor cropped to the image aspect ratio
+    pub film_half_width: f64,
+    pub film_half_height: f64,
+    // Scene units per millimeter
+    pub scale: f64,
+    // Axial position of each surface
+    vertex_z: Vec<f64>,
+    pupil_bounds: Vec<PupilBounds>,
+    // Integral of cos^4 over the rear plane passing light to the film center, for unit
+    // exposure there
+    center_exposure: f64,
+}
+
+impl LensSystem {
+    // Sensor size in millimeters fills the image like FieldOfView::FocalLength, and the
+    // film is moved so that focus_distance (scene units from the film) is in focus. Fails if
+    // no light gets through to the film center
+    pub fn new(
+        elements: Vec<LensElement>,
+        sensor_width: f64,
+        sensor_height: f64,
+        aspect_ratio: f64,
+        scale: f64,
+        focus_distance: f64,
+    ) -> Result<LensSystem, String> {
+        if elements.is_empty() {
+            return Err("Lens has no elements".to_string());
+        }
+        let (film_half_width, film_half_height) = if aspect_ratio >= sensor_width / sensor_height {
+            (sensor_width * 0.5, sensor_width * 0.5 / aspect_ratio)
+        } else {
+            (sensor_height * 0.5 * aspect_ratio, sensor_height * 0.5)
+        };
+
+        let mut lens = LensSystem {
+            elements,
+            film_half_width,
+            film_half_height,
+            scale,
+            vertex_z: vec![],
+            pupil_bounds: vec![],
+            center_exposure: 0.0,
+        };
+        lens.update_vertex_z();
+        lens.focus(focus_distance / scale);
+        lens.update_pupil_bounds()?;
+        Ok(lens)
+    }
+
+    // Distance from the rear surface to the film
+    pub fn film_distance(&self) -> f64 {
+        self.elements.last().map_or(0.0, |e| e.thickness)
+    }
+
+    fn rear(&self) -> &LensElement {
+        self.elements.last().unwrap()
+    }
+
+    fn update_vertex_z(&mut self) {
+        let mut z = 0.0;
+        self.vertex_z = self
+            .elements
+            .iter()
+            .rev()
+            .map(|element| {
+                z -= element.thickness;
+                z
+            })
+            .collect();
+        self.vertex_z.reverse();
+    }
+
+    // Moves the film so that a point on the axis at distance (mm from the film) is in focus,
+    // which also changes the field of view like real lenses breathe
+    fn focus(&mut self, distance: f64) {
+        let height = self.rear().aperture_radius * 1e-3;
+        for _ in 0..FOCUS_ITERATIONS {
+            let origin = Vector3::new(0.0, 0.0, -distance);
+            let front_z = self.vertex_z[0];
+            let direction = (Vector3::new(height, 0.0, front_z) - origin).normalized();
+            let (o, d) = match self.trace(origin, direction, false) {
+                Some(ray) => ray,
+                None => return,
+            };
+            if d.x >= 0.0 {
+                // Diverging, nothing to focus on
+                return;
+            }
+            // Paraxial image of the point crosses the axis here
+            let image_z = o.z - o.x / d.x * d.z;
+            let n = self.elements.len();
+            self.elements[n - 1].thickness += image_z;
+            self.update_vertex_z();
+            if image_z.abs() < 1e-9 {
+                return;
+            }
+        }
+    }
+
+    // Hit point and normal facing against the ray, None if outside of the aperture
+    fn intersect(
+        &self,
+        element: &LensElement,
+        z: f64,
+        origin: Vector3,
+        direction: Vector3,
+    ) -> Option<(Vector3, Vector3)> {
+        let (t, normal) = if element.is_stop() {
+            let t = (z - origin.z) / direction.z;
+            (t, Vector3::new(0.0, 0.0, -direction.z.signum()))
+        } else {
+            let radius = element.curvature_radius;
+            let center = Vector3::new(0.0, 0.0, z + radius);
+            let oc = origin - center;
+            let b = oc.dot(&direction);
+            let discriminant = b * b - (oc.dot(&oc) - radius * radius);
+            if discriminant < 0.0 {
+                return None;
+            }
+            let root = discriminant.sqrt();
+            // Surface near the vertex is the nearer or further half of the sphere
+            let closer = (direction.z > 0.0) ^ (radius < 0.0);
+            let t = if closer { -b - root } else { -b + root };
+            let normal = (origin + direction * t - center).normalized();
+            let normal = if normal.dot(&direction) > 0.0 {
+                -normal
+            } else {
+                normal
+            };
+            (t, normal)
+        };
+
+        if t <= 0.0 {
+            return None;
+        }
+        let position = origin + direction * t;
+        if position.x * position.x + position.y * position.y
+            > element.aperture_radius * element.aperture_radius
+        {
+            return None;
+        }
+        Some((position, normal))
+    }
+
+    // Refracts through every surface, None if blocked by an aperture or totally reflected
+    fn trace(
+        &self,
+        mut origin: Vector3,
+        mut direction: Vector3,
+        from_film: bool,
+    ) -> Option<(Vector3, Vector3)> {
+        let n = self.elements.len();
+        let order: Vec<usize> = if from_film {
+            (0..n).rev().collect()
+        } else {
+            (0..n).collect()
+        };
+
+        for i in order {
+            let element = &self.elements[i];
+            let (position, normal) =
+                self.intersect(element, self.vertex_z[i], origin, direction)?;
+            origin = position;
+            if element.is_stop() {
+                continue;
+            }
+
+            let front_ior = if i > 0 {
+                self.elements[i - 1].medium_ior()
+            } else {
+                1.0
+            };
+            let (ior_in, ior_out) = if from_film {
+                (element.medium_ior(), front_ior)
+            } else {
+                (front_ior, element.medium_ior())
+            };
+            direction = direction.refract(&normal, ior_in / ior_out);
+            if direction == Vector3::zero() {
+                return None;
+            }
+            direction = direction.normalized();
+        }
+        Some((origin, direction))
+    }
+
+    fn film_radius(&self) -> f64 {
+        self.film_half_width.hypot(self.film_half_height)
+    }
+
+    fn pupil_bounds_at(&self, film_radius: f64) -> &PupilBounds {
+        let i = (film_radius / self.film_radius() * PUPIL_BOUNDS_RESOLUTION as f64) as usize;
+        &self.pupil_bounds[i.min(PUPIL_BOUNDS_RESOLUTION - 1)]
+    }
+
+    // Bounds points of a grid on the rear plane whose rays from the film get through
+    fn update_pupil_bounds(&mut self) -> Result<(), String> {
+        let rear_z = self.vertex_z[self.elements.len() - 1];
+        let extent = self.rear().aperture_radius * 1.5;
+        let cell = 2.0 * extent / PUPIL_GRID_RESOLUTION as f64;
+        let grid: Vec<Vector2> = (0..PUPIL_GRID_RESOLUTION * PUPIL_GRID_RESOLUTION)
+            .map(|i| {
+                let (x, y) = (i % PUPIL_GRID_RESOLUTION, i / PUPIL_GRID_RESOLUTION);
+                Vector2::new(x as f64 + 0.5, y as f64 + 0.5) * cell - extent
+            })
+            .collect();
+        let passes = |film_radius: f64, p: &Vector2| {
+            let origin = Vector3::new(film_radius, 0.0, 0.0);
+            let direction = (Vector3::new(p.x, p.y, rear_z) - origin).normalized();
+            self.trace(origin, direction, true).is_some()
+        };
+
+        let center_exposure: f64 = grid
+            .iter()
+            .filter(|p| passes(0.0, p))
+            .map(|p| direction_cos_to_axis(&Vector3::new(p.x, p.y, rear_z)).powi(4) * cell * cell)
+            .sum();
+
+        let pupil_bounds = (0..PUPIL_BOUNDS_RESOLUTION)
+            .map(|i| {
+                let r0 = i as f64 / PUPIL_BOUNDS_RESOLUTION as f64 * self.film_radius();
+                let r1 = (i + 1) as f64 / PUPIL_BOUNDS_RESOLUTION as f64 * self.film_radius();
+                let mut bounds = PupilBounds {
+                    min: Vector2::all_of(f64::INFINITY),
+                    max: Vector2::all_of(f64::NEG_INFINITY),
+                };
+                for p in grid.iter().filter(|p| passes(r0, p) || passes(r1, p)) {
+                    bounds.min = Vector2::new(bounds.min.x.min(p.x), bounds.min.y.min(p.y));
+                    bounds.max = Vector2::new(bounds.max.x.max(p.x), bounds.max.y.max(p.y));
+                }
+                if bounds.min.x > bounds.max.x {
+                    // Fully vignetted
+                    return PupilBounds {
+                        min: Vector2::zero(),
+                        max: Vector2::zero(),
+                    };
+                }
+                // Points between the grid may pass too
+                PupilBounds {
+                    min: bounds.min - cell,
+                    max: bounds.max + cell,
+                }
+            })
+            .collect();
+
+        if center_exposure <= 0.0 {
+            return Err("No light reaches the film center".to_string());
+        }
+        self.pupil_bounds = pupil_bounds;
+        self.center_exposure = center_exposure;
+        Ok(())
+    }
+
+    fn film_point(normalized_coord: &Vector2, film_half_size: f64) -> Vector3 {
+        // Lenses form inverted images on the film
+        Vector3::new(
+            -normalized_coord.x * film_half_size,
+            -normalized_coord.y * film_half_size,
+            0.0,
+        )
+    }
+
+    fn film_half_size(&self) -> f64 {
+        self.film_half_width.min(self.film_half_height)
+    }
+
+    // Ray leaving the front of the lens toward a point (u, v) of the exit pupil, and its
+    // weight relative to the film center
+    pub fn sample_ray(
+        &self,
+        normalized_coord: &Vector2,
+        uv: (f64, f64),
+    ) -> Option<(Vector3, Vector3, f64)> {
+        let film = LensSystem::film_point(normalized_coord, self.film_half_size());
+        let film_radius = film.xy().length();
+        let bounds = self.pupil_bounds_at(film_radius);
+        if bounds.area() <= 0.0 {
+            return None;
+        }
+
+        let pupil = bounds.min + (bounds.max - bounds.min) * Vector2::new(uv.0, uv.1);
+        // Rotate from the x axis to the angle of the film point
+        let (sin, cos) = if film_radius > 0.0 {
+            (film.y / film_radius, film.x / film_radius)
+        } else {
+            (0.0, 1.0)
+        };
+        let rear_z = self.vertex_z[self.elements.len() - 1];
+        let target = Vector3::new(
+            cos * pupil.x - sin * pupil.y,
+            sin * pupil.x + cos * pupil.y,
+            rear_z,
+        );
+
+        let direction = (target - film).normalized();
+        let (origin, direction) = self.trace(film, direction, true)?;
+        let cos_theta = direction_cos_to_axis(&(target - film));
+        let weight = bounds.area() * cos_theta.powi(4) / self.center_exposure;
+        Some((origin, direction, weight))
+    }
+
+    // Ray through the middle of the exit pupil, None if it is blocked
+    pub fn chief_ray(&self, normalized_coord: &Vector2) -> Option<(Vector3, Vector3)> {
+        self.sample_ray(normalized_coord, (0.5, 0.5))
+            .map(|(origin, direction, _)| (origin, direction))
+    }
+}
+
+fn direction_cos_to_axis(v: &Vector3) -> f64 {
+    v.z.abs() / v.length()
+}
@@ -3,6 +3,7 @@ pub mod config;
 pub mod math;
 pub mod matrix;
 pub mod mesh;
+pub mod quaternion;
 pub mod vector;
 
 pub mod color;
@@ -13,12 +14,26 @@ mod material_utils;
 pub mod texture;
 pub mod tonemap;
 
+pub mod motion;
 pub mod rayintersectable;
+pub mod light;
+pub mod transform;
+pub mod instance;
+pub mod scene_graph;
 
+pub mod accumulation;
+pub mod checkpoint;
+mod io_util;
+pub mod distributed;
+pub mod tile;
+pub mod aperture;
+pub mod lens;
 pub mod camera;
 pub mod renderer;
 pub mod scene;
+pub mod stereo;
 
+pub mod gltf;
 pub mod loader;
 
 mod tests;
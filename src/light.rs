@@ -0,0 +1,287 @@
+use crate::aabb::Aabb;
+use crate::camera::Ray;
+use crate::config;
+use crate::material::Material;
+use crate::material_utils::get_tangent_space_basis_gram_schmidtd;
+use crate::math::clamp;
+use crate::rayintersectable::{Disk, Intersectable, Intersection, Rectangle};
+use crate::scene::Surface;
+use crate::vector::Vector3;
+
+// Share of the emission of a one-sided light facing normal that leaves toward direction, all
+// of it within half the spread from the normal and none behind. The edge is hard and the
+// radiance within is not raised, so narrowing the spread cuts off power rather than focusing it
+fn spread_weight(normal: &Vector3, direction: &Vector3, spread: f64) -> f64 {
+    let cos = normal.dot(&direction.normalized());
+    if cos > 0.0 && cos >= (0.5 * spread).cos() {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+// Density over the area of a light of samples uniform over the solid angle it covers. A density
+// over solid angle becomes one over area by the cosine at the light over the squared distance
+fn area_pdf(solid_angle: f64, cos_light: f64, distance2: f64) -> f64 {
+    cos_light / (distance2 * solid_angle)
+}
+
+// Point of the rectangle from corner along perpendicular edges, uniform over the solid angle
+// it covers seen from position, with that solid angle. None when it covers none
+// https://www.arnoldrenderer.com/research/egsr2013_spherical_rectangle.pdf
+fn sample_spherical_rectangle(
+    position: &Vector3,
+    corner: &Vector3,
+    edge_u: &Vector3,
+    edge_v: &Vector3,
+    random: (f64, f64),
+) -> Option<(Vector3, f64)> {
+    let (length_u, length_v) = (edge_u.length(), edge_v.length());
+    let x = *edge_u / length_u;
+    let y = *edge_v / length_v;
+    let mut z = x.cross(&y);
+    let d = *corner - *position;
+    let (x0, y0, mut z0) = (d.dot(&x), d.dot(&y), d.dot(&z));
+    // Seen from the front, so that the edges turn the same way
+    if z0 > 0.0 {
+        z0 = -z0;
+        z = -z;
+    }
+    if z0.abs() < 1e-9 {
+        return None;
+    }
+    let (x1, y1) = (x0 + length_u, y0 + length_v);
+
+    // Normals of the planes through position and each edge, inward
+    let v00 = Vector3::new(x0, y0, z0);
+    let v01 = Vector3::new(x0, y1, z0);
+    let v10 = Vector3::new(x1, y0, z0);
+    let v11 = Vector3::new(x1, y1, z0);
+    let n0 = v00.cross(&v10).normalized();
+    let n1 = v10.cross(&v11).normalized();
+    let n2 = v11.cross(&v01).normalized();
+    let n3 = v01.cross(&v00).normalized();
+    let angle = |a: &Vector3, b: &Vector3| clamp(-a.dot(b), -1.0, 1.0).acos();
+    let (g0, g1, g2, g3) = (
+        angle(&n0, &n1),
+        angle(&n1, &n2),
+        angle(&n2, &n3),
+        angle(&n3, &n0),
+    );
+    let solid_angle = g0 + g1 + g2 + g3 - config::PI2;
+    if solid_angle <= 1e-12 {
+        return None;
+    }
+
+    // The solid angle left of xu is the first random number of the whole
+    let au = random.0 * solid_angle - (g2 + g3);
+    let fu = (au.cos() * n0.z - n2.z) / au.sin();
+    let cu = clamp(fu.signum() / (fu * fu + n0.z * n0.z).sqrt(), -1.0, 1.0);
+    let xu = clamp(-(cu * z0) / (1.0 - cu * cu).max(0.0).sqrt(), x0, x1);
+
+    // Then uniform in the height of the projection on the unit sphere
+    let dd = (xu * xu + z0 * z0).sqrt();
+    let h0 = y0 / (dd * dd + y0 * y0).sqrt();
+    let h1 = y1 / (dd * dd + y1 * y1).sqrt();
+    let hv = h0 + random.1 * (h1 - h0);
+    let yv = if hv * hv < 1.0 - 1e-6 {
+        hv * dd / (1.0 - hv * hv).sqrt()
+    } else {
+        y1
+    };
+
+    Some((*position + x * xu + y * yv + z * z0, solid_angle))
+}
+
+// One-sided light from the front of a rectangle with perpendicular edges, as softboxes are
+pub struct RectangleLight {
+    pub rectangle: Rectangle,
+    // Full angle of the cone around the normal that light leaves in, PI for a diffuse emitter
+    pub spread: f64,
+    pub camera_visible: bool,
+}
+
+impl RectangleLight {
+    pub fn new(rectangle: Rectangle) -> RectangleLight {
+        RectangleLight {
+            rectangle,
+            spread: config::PI,
+            camera_visible: true,
+        }
+    }
+
+    pub fn with_spread(mut self, spread: f64) -> RectangleLight {
+        self.spread = spread;
+        self
+    }
+
+    pub fn with_camera_visible(mut self, camera_visible: bool) -> RectangleLight {
+        self.camera_visible = camera_visible;
+        self
+    }
+
+    fn normal(&self) -> Vector3 {
+        self.rectangle
+            .edge_u
+            .cross(&self.rectangle.edge_v)
+            .normalized()
+    }
+}
+
+impl Intersectable for RectangleLight {
+    fn aabb(&self) -> Aabb {
+        self.rectangle.aabb()
+    }
+
+    fn intersect(&self, ray: &Ray, intersection: &mut Intersection) -> bool {
+        self.rectangle.intersect(ray, intersection)
+    }
+
+    fn material(&self) -> &Material {
+        &self.rectangle.material
+    }
+
+    fn emission_toward(&self, _: &Intersection, direction: &Vector3) -> f64 {
+        spread_weight(&self.normal(), direction, self.spread)
+    }
+
+    fn visible_to_camera(&self) -> bool {
+        self.camera_visible
+    }
+
+    fn nee_available(&self) -> bool {
+        true
+    }
+
+    fn sample_on_surface(&self, random: (f64, f64)) -> Option<Surface> {
+        self.rectangle.sample_on_surface(random)
+    }
+
+    // Uniform over the solid angle, or the area for parallelograms and points in its plane
+    fn sample_from(&self, position: &Vector3, random: (f64, f64)) -> Option<Surface> {
+        let Rectangle {
+            corner,
+            edge_u,
+            edge_v,
+            ..
+        } = &self.rectangle;
+        let perpendicular = edge_u.dot(edge_v).abs() < 1e-9 * edge_u.length() * edge_v.length();
+        let sample = if perpendicular {
+            sample_spherical_rectangle(position, corner, edge_u, edge_v, random)
+        } else {
+            None
+        };
+        match sample {
+            Some((point, solid_angle)) => {
+                let normal = self.normal();
+                let to_position = *position - point;
+                let distance2 = to_position.dot(&to_position);
+                let cos = normal.dot(&to_position).abs() / distance2.sqrt();
+                Some(Surface {
+                    position: point,
+                    normal,
+                    pdf: area_pdf(solid_angle, cos, distance2),
+                })
+            }
+            None => self.rectangle.sample_on_surface(random),
+        }
+    }
+}
+
+// One-sided light from the front of a disk
+pub struct DiskLight {
+    pub disk: Disk,
+    // Full angle of the cone around the normal that light leaves in, PI for a diffuse emitter
+    pub spread: f64,
+    pub camera_visible: bool,
+}
+
+impl DiskLight {
+    pub fn new(disk: Disk) -> DiskLight {
+        DiskLight {
+            disk,
+            spread: config::PI,
+            camera_visible: true,
+        }
+    }
+
+    pub fn with_spread(mut self, spread: f64) -> DiskLight {
+        self.spread = spread;
+        self
+    }
+
+    pub fn with_camera_visible(mut self, camera_visible: bool) -> DiskLight {
+        self.camera_visible = camera_visible;
+        self
+    }
+}
+
+impl Intersectable for DiskLight {
+    fn aabb(&self) -> Aabb {
+        self.disk.aabb()
+    }
+
+    fn intersect(&self, ray: &Ray, intersection: &mut Intersection) -> bool {
+        self.disk.intersect(ray, intersection)
+    }
+
+    fn material(&self) -> &Material {
+        &self.disk.material
+    }
+
+    fn emission_toward(&self, _: &Intersection, direction: &Vector3) -> f64 {
+        spread_weight(&self.disk.normal.normalized(), direction, self.spread)
+    }
+
+    fn visible_to_camera(&self) -> bool {
+        self.camera_visible
+    }
+
+    fn nee_available(&self) -> bool {
+        true
+    }
+
+    fn sample_on_surface(&self, random: (f64, f64)) -> Option<Surface> {
+        self.disk.sample_on_surface(random)
+    }
+
+    // Uniform over the cone toward the sphere around the disk, keeping the directions that hit
+    // it. The others give no sample, adding nothing as they would not have reached the light.
+    // Points within the sphere fall back to the area
+    fn sample_from(&self, position: &Vector3, random: (f64, f64)) -> Option<Surface> {
+        let Disk {
+            center,
+            radius,
+            normal,
+            ..
+        } = &self.disk;
+        let to_center = *center - *position;
+        let distance2 = to_center.dot(&to_center);
+        if distance2 <= radius * radius {
+            return self.disk.sample_on_surface(random);
+        }
+
+        // 1 - cos of the half angle of the cone, without cancellation for far disks
+        let sin2 = radius * radius / distance2;
+        let height = sin2 / (1.0 + (1.0 - sin2).sqrt());
+        let solid_angle = config::PI2 * height;
+        let cos = 1.0 - height * random.0;
+        let sin = (1.0 - cos * cos).max(0.0).sqrt();
+        let phi = config::PI2 * random.1;
+        let axis = to_center / distance2.sqrt();
+        let (tangent, binormal) = get_tangent_space_basis_gram_schmidtd(&axis);
+        let direction = axis * cos + (tangent * phi.cos() + binormal * phi.sin()) * sin;
+
+        let normal = normal.normalized();
+        let t = to_center.dot(&normal) / direction.dot(&normal);
+        let point = *position + direction * t;
+        if !t.is_finite() || t <= 0.0 || (point - *center).length() > *radius {
+            return None;
+        }
+        Some(Surface {
+            position: point,
+            normal,
+            pdf: area_pdf(solid_angle, normal.dot(&direction).abs(), t * t),
+        })
+    }
+}
@@ -1,60 +1,938 @@
-use crate::material::Material;
+use crate::color::{gamma_to_linear, Color};
+use crate::io_util::invalid_data;
+use crate::material::{Material, SurfaceType};
 use crate::matrix::Matrix44;
-use crate::mesh::Face;
 use crate::mesh::Mesh;
-use crate::vector::Vector3;
+use crate::mesh::{triangulate_polygon, Face};
+use crate::texture::{ImageTexture, Texture};
+use crate::vector::{Vector2, Vector3};
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::sync::Arc;
 
 pub struct ObjLoader;
 
+pub struct MtlLoader;
+
+// Materials of the mtllib files read so far, by name, and the one used by following faces
+struct MaterialLibrary<'a> {
+    directory: &'a Path,
+    indexes: HashMap<String, usize>,
+    current: Option<usize>,
+}
+
+// Corner of a face, indexes of position, texture coordinate and normal
+#[derive(Clone, Copy)]
+struct FaceVertex {
+    position: usize,
+    uv: Option<usize>,
+    normal: Option<usize>,
+}
+
+fn parse_floats(tokens: &[&str], min: usize, max: usize) -> Result<Vec<f64>, String> {
+    if tokens.len() < min || tokens.len() > max {
+        return Err(format!(
+            "expected {} to {} numbers but got {}",
+            min,
+            max,
+            tokens.len()
+        ));
+    }
+    tokens
+        .iter()
+        .map(|t| t.parse::<f64>().map_err(|e| format!("{}: {}", t, e)))
+        .collect()
+}
+
+// 1-based index, or negative relative to the end of the count elements defined so far
+fn parse_index(token: &str, count: usize, name: &str) -> Result<usize, String> {
+    let index = token
+        .parse::<i64>()
+        .map_err(|e| format!("{} index {}: {}", name, token, e))?;
+    let resolved = if index > 0 {
+        index - 1
+    } else {
+        count as i64 + index
+    };
+    if index == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(format!(
+            "{} index {} is out of {} defined",
+            name, index, count
+        ));
+    }
+    Ok(resolved as usize)
+}
+
+fn empty_mesh(material: Material) -> Mesh {
+    Mesh {
+        vertexes: vec![],
+        colors: vec![],
+        normals: vec![],
+        uvs: vec![],
+        faces: vec![],
+        material,
+        materials: vec![],
+    }
+}
+
+// Keyword and arguments of a line without its comment, None for blank lines
+fn split_line(line: &str) -> Option<(&str, Vec<&str>)> {
+    let content = line.split('#').next().unwrap_or("");
+    let mut tokens = content.split_whitespace();
+    tokens.next().map(|keyword| (keyword, tokens.collect()))
+}
+
+// Gray value or r, g and b
+fn parse_color(tokens: &[&str]) -> Result<Color, String> {
+    let c = parse_floats(tokens, 1, 3)?;
+    match c.len() {
+        1 => Ok(Color::all_of(c[0])),
+        3 => Ok(Color::new(c[0], c[1], c[2])),
+        _ => Err(format!("expected 1 or 3 numbers but got {}", c.len())),
+    }
+}
+
 impl ObjLoader {
-    pub fn load(path: &str, matrix: Matrix44, material: Material) -> Mesh {
-        let mut mesh = Mesh {
-            vertexes: vec![],
-            faces: vec![],
-            material: material,
-        };
-
-        let f = File::open(path).unwrap();
-        let file = BufReader::new(&f);
-        for (_, line) in file.lines().enumerate() {
-            let l = line.unwrap();
-            let split_line: Vec<&str> = l.split(" ").collect();
-            match split_line[0] {
-                "v" => {
-                    let local_vertex = Vector3::new(
-                        split_line[1].parse::<f64>().unwrap(),
-                        split_line[2].parse::<f64>().unwrap(),
-                        split_line[3].parse::<f64>().unwrap(),
-                    );
-                    let world_vertex = matrix * local_vertex;
-                    mesh.vertexes.push(world_vertex);
+    // Material libraries are looked up relative to the directory of the file
+    pub fn load(path: &str, matrix: Matrix44, material: Material) -> io::Result<Mesh> {
+        let directory = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+        File::open(path)
+            .and_then(|f| ObjLoader::parse_in(BufReader::new(f), directory, matrix, material))
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))
+    }
+
+    // Errors are prefixed with the line number. Material libraries are looked up relative to
+    // the working directory
+    pub fn parse<R: BufRead>(reader: R, matrix: Matrix44, material: Material) -> io::Result<Mesh> {
+        ObjLoader::parse_in(reader, Path::new(""), matrix, material)
+    }
+
+    fn parse_in<R: BufRead>(
+        reader: R,
+        directory: &Path,
+        matrix: Matrix44,
+        material: Material,
+    ) -> io::Result<Mesh> {
+        let mut mesh = empty_mesh(material);
+        let mut library = MaterialLibrary {
+            directory,
+            indexes: HashMap::new(),
+            current: None,
+        };
+        // Normals are transformed by the inverse transpose to stay perpendicular
+        let normal_matrix = matrix.inverse().transposed();
+
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            ObjLoader::parse_line(&line, &matrix, &normal_matrix, &mut library, &mut mesh)
+                .map_err(|e| invalid_data(format!("line {}: {}", i + 1, e)))?;
+        }
+
+        Ok(mesh)
+    }
+
+    fn parse_line(
+        line: &str,
+        matrix: &Matrix44,
+        normal_matrix: &Matrix44,
+        library: &mut MaterialLibrary,
+        mesh: &mut Mesh,
+    ) -> Result<(), String> {
+        let (keyword, arguments) = match split_line(line) {
+            Some((keyword, arguments)) => (keyword, arguments),
+            None => return Ok(()),
+        };
+        let arguments = &arguments[..];
+
+        match keyword {
+            "v" => {
+                // Optional w and vertex colors are ignored
+                let v = parse_floats(arguments, 3, 7)?;
+                mesh.vertexes.push(*matrix * Vector3::new(v[0], v[1], v[2]));
+            }
+            "vn" => {
+                let n = parse_floats(arguments, 3, 3)?;
+                let world_normal =
+                    normal_matrix.transform_direction(&Vector3::new(n[0], n[1], n[2]));
+                mesh.normals.push(world_normal.normalized());
+            }
+            "vt" => {
+                let t = parse_floats(arguments, 1, 3)?;
+                mesh.uvs
+                    .push(Vector2::new(t[0], t.get(1).copied().unwrap_or(0.0)));
+            }
+            "f" => {
+                if arguments.len() < 3 {
+                    return Err(format!(
+                        "face needs at least 3 vertices but got {}",
+                        arguments.len()
+                    ));
                 }
-                "f" => {
-                    let v1: Vec<&str> = split_line[1].split("/").collect();
-                    let v2: Vec<&str> = split_line[2].split("/").collect();
-                    let v3: Vec<&str> = split_line[3].split("/").collect();
+                let corners = arguments
+                    .iter()
+                    .map(|token| ObjLoader::parse_face_vertex(token, mesh))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let points: Vec<Vector3> =
+                    corners.iter().map(|c| mesh.vertexes[c.position]).collect();
+
+                for triangle in triangulate_polygon(&points) {
+                    let corners = triangle.map(|i| corners[i]);
+                    let uvs = corners.map(|c| c.uv);
+                    let normals = corners.map(|c| c.normal);
                     mesh.faces.push(Face {
-                        v0: v1[0].parse::<usize>().unwrap() - 1,
-                        v1: v2[0].parse::<usize>().unwrap() - 1,
-                        v2: v3[0].parse::<usize>().unwrap() - 1,
+                        normals: if normals.iter().all(Option::is_some) {
+                            Some(normals.map(Option::unwrap))
+                        } else {
+                            None
+                        },
+                        uvs: if uvs.iter().all(Option::is_some) {
+                            Some(uvs.map(Option::unwrap))
+                        } else {
+                            None
+                        },
+                        material: library.current,
+                        ..Face::new(
+                            corners[0].position,
+                            corners[1].position,
+                            corners[2].position,
+                        )
                     });
+                }
+            }
+            // A missing library or material only loses the look of the faces, so they keep the
+            // main material. Malformed libraries are still errors
+            "mtllib" => {
+                for name in arguments {
+                    let path = library.directory.join(name);
+                    let materials = match MtlLoader::load(&path) {
+                        Ok(materials) => materials,
+                        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                            eprintln!("Warning: {}, using the main material", e);
+                            continue;
+                        }
+                        Err(e) => return Err(e.to_string()),
+                    };
+                    for (name, material) in materials {
+                        library.indexes.insert(name, mesh.materials.len());
+                        mesh.materials.push(material);
+                    }
+                }
+            }
+            "usemtl" => {
+                let name = arguments.join(" ");
+                library.current = library.indexes.get(&name).copied();
+                if library.current.is_none() {
+                    eprintln!(
+                        "Warning: unknown material {}, using the main material",
+                        name
+                    );
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // "v", "v/vt", "v//vn" or "v/vt/vn"
+    fn parse_face_vertex(token: &str, mesh: &Mesh) -> Result<FaceVertex, String> {
+        let parts: Vec<&str> = token.split('/').collect();
+        if parts.len() > 3 {
+            return Err(format!("invalid face vertex {}", token));
+        }
+        let optional = |i: usize, count: usize, name: &str| match parts.get(i) {
+            Some(part) if !part.is_empty() => parse_index(part, count, name).map(Some),
+            _ => Ok(None),
+        };
+        Ok(FaceVertex {
+            position: parse_index(parts[0], mesh.vertexes.len(), "vertex")?,
+            uv: optional(1, mesh.uvs.len(), "texture coordinate")?,
+            normal: optional(2, mesh.normals.len(), "normal")?,
+        })
+    }
+}
+
+// Statements of a material in an MTL file
+struct MtlMaterial {
+    diffuse: Color,
+    specular: Color,
+    emission: Color,
+    exponent: f64,
+    refractive_index: f64,
+    dissolve: f64,
+    illumination: u32,
+    diffuse_map: Option<ImageTexture>,
+    emission_map: Option<ImageTexture>,
+    bump_map: Option<(ImageTexture, f64)>,
+}
+
+impl MtlMaterial {
+    // Defaults of the MTL format, except a glass refractive index
+    fn new() -> MtlMaterial {
+        MtlMaterial {
+            diffuse: Color::all_of(0.8),
+            specular: Color::zero(),
+            emission: Color::zero(),
+            exponent: 0.0,
+            refractive_index: 1.5,
+            dissolve: 1.0,
+            illumination: 2,
+            diffuse_map: None,
+            emission_map: None,
+            bump_map: None,
+        }
+    }
+
+    // Our surfaces have a single lobe. Transparent materials refract, materials whose specular
+    // color is brighter than their diffuse one are GGX with the specular exponent as roughness,
+    // and the others are diffuse
+    fn into_material(self) -> Material {
+        let max = |c: Color| c.x.max(c.y).max(c.z);
+        let f0 = max(self.specular);
+        let transparent = self.dissolve < 1.0 || [4, 6, 7, 9].contains(&self.illumination);
+        let glossy = self.illumination >= 2 && f0 > 0.0 && f0 > max(self.diffuse);
+
+        let (surface, albedo_color) = if transparent {
+            (
+                SurfaceType::Refraction {
+                    refractive_index: self.refractive_index,
+                },
+                self.diffuse,
+            )
+        } else if glossy {
+            (SurfaceType::GGX { f0 }, self.specular / f0)
+        } else {
+            (SurfaceType::Diffuse, self.diffuse)
+        };
+        // Blinn-Phong exponent to GGX alpha
+        let roughness = (2.0 / (self.exponent + 2.0)).sqrt();
+
+        let material = Material::new(
+            surface,
+            Texture {
+                image_texture: self.diffuse_map,
+                color: albedo_color,
+            },
+            Texture {
+                image_texture: self.emission_map,
+                color: self.emission,
+            },
+            Texture::of_color(Color::all_of(roughness)),
+        );
+        match self.bump_map {
+            Some((image_texture, scale)) => material.with_bump(Texture {
+                image_texture: Some(image_texture),
+                color: Color::all_of(scale),
+            }),
+            None => material,
+        }
+    }
+}
+
+impl MtlLoader {
+    // Materials with their names. Texture maps are looked up relative to the directory of the
+    // file
+    pub fn load(path: &Path) -> io::Result<Vec<(String, Material)>> {
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        File::open(path)
+            .and_then(|f| MtlLoader::parse(BufReader::new(f), directory))
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+    }
+
+    // Errors are prefixed with the line number
+    pub fn parse<R: BufRead>(reader: R, directory: &Path) -> io::Result<Vec<(String, Material)>> {
+        let mut materials: Vec<(String, MtlMaterial)> = vec![];
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            MtlLoader::parse_line(&line, directory, &mut materials)
+                .map_err(|e| invalid_data(format!("line {}: {}", i + 1, e)))?;
+        }
+        Ok(materials
+            .into_iter()
+            .map(|(name, material)| (name, material.into_material()))
+            .collect())
+    }
+
+    fn parse_line(
+        line: &str,
+        directory: &Path,
+        materials: &mut Vec<(String, MtlMaterial)>,
+    ) -> Result<(), String> {
+        let (keyword, arguments) = match split_line(line) {
+            Some((keyword, arguments)) => (keyword, arguments),
+            None => return Ok(()),
+        };
+        let arguments = &arguments[..];
+
+        if keyword == "newmtl" {
+            if arguments.is_empty() {
+                return Err("newmtl needs a material name".to_string());
+            }
+            materials.push((arguments.join(" "), MtlMaterial::new()));
+            return Ok(());
+        }
+        let material = match materials.last_mut() {
+            Some((_, material)) => material,
+            None => return Err(format!("{} before newmtl", keyword)),
+        };
+
+        match keyword {
+            "Kd" => material.diffuse = parse_color(arguments)?,
+            "Ks" => material.specular = parse_color(arguments)?,
+            "Ke" => material.emission = parse_color(arguments)?,
+            "Ns" => material.exponent = parse_floats(arguments, 1, 1)?[0],
+            "Ni" => material.refractive_index = parse_floats(arguments, 1, 1)?[0],
+            "d" => material.dissolve = parse_floats(arguments, 1, 1)?[0],
+            "Tr" => material.dissolve = 1.0 - parse_floats(arguments, 1, 1)?[0],
+            "illum" => {
+                material.illumination = arguments
+                    .first()
+                    .and_then(|t| t.parse().ok())
+                    .ok_or_else(|| format!("invalid illumination model {}", arguments.join(" ")))?
+            }
+            "map_Kd" => material.diffuse_map = Some(MtlLoader::parse_map(arguments, directory)?.0),
+            "map_Ke" => material.emission_map = Some(MtlLoader::parse_map(arguments, directory)?.0),
+            "map_Bump" | "map_bump" | "bump" => {
+                material.bump_map = Some(MtlLoader::parse_map(arguments, directory)?)
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // Image of a texture map statement and its -bm bump multiplier. Other options are skipped
+    fn parse_map(arguments: &[&str], directory: &Path) -> Result<(ImageTexture, f64), String> {
+        let mut bump_multiplier = 1.0;
+        let mut i = 0;
+        while i < arguments.len() && arguments[i].starts_with('-') {
+            let option = arguments[i];
+            i += 1;
+            // Options take one to three values
+            let start = i;
+            while i < arguments.len()
+                && i - start < 3
+                && (arguments[i].parse::<f64>().is_ok() || ["on", "off"].contains(&arguments[i]))
+            {
+                i += 1;
+            }
+            if option == "-bm" {
+                bump_multiplier = parse_floats(&arguments[start..i], 1, 1)?[0];
+            }
+        }
+        if i == arguments.len() {
+            return Err("texture map needs a file name".to_string());
+        }
+
+        let path = directory.join(arguments[i..].join(" "));
+        let image = image::open(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let image = Arc::new(image);
+        Ok((ImageTexture { image }, bump_multiplier))
+    }
+}
+
+pub struct PlyLoader;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PlyScalar {
+    Int8,
+    Uint8,
+    Int16,
+    Uint16,
+    Int32,
+    Uint32,
+    Float32,
+    Float64,
+}
+
+impl PlyScalar {
+    fn parse(name: &str) -> Option<PlyScalar> {
+        match name {
+            "char" | "int8" => Some(PlyScalar::Int8),
+            "uchar" | "uint8" => Some(PlyScalar::Uint8),
+            "short" | "int16" => Some(PlyScalar::Int16),
+            "ushort" | "uint16" => Some(PlyScalar::Uint16),
+            "int" | "int32" => Some(PlyScalar::Int32),
+            "uint" | "uint32" => Some(PlyScalar::Uint32),
+            "float" | "float32" => Some(PlyScalar::Float32),
+            "double" | "float64" => Some(PlyScalar::Float64),
+            _ => None,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            PlyScalar::Int8 | PlyScalar::Uint8 => 1,
+            PlyScalar::Int16 | PlyScalar::Uint16 => 2,
+            PlyScalar::Int32 | PlyScalar::Uint32 | PlyScalar::Float32 => 4,
+            PlyScalar::Float64 => 8,
+        }
+    }
+
+    // Value of bytes of the size in the byte order of the format
+    fn decode(self, bytes: &[u8], big_endian: bool) -> f64 {
+        let mut b = [0u8; 8];
+        b[..bytes.len()].copy_from_slice(bytes);
+        if big_endian {
+            b[..bytes.len()].reverse();
+        }
+        match self {
+            PlyScalar::Int8 => b[0] as i8 as f64,
+            PlyScalar::Uint8 => b[0] as f64,
+            PlyScalar::Int16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+            PlyScalar::Uint16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+            PlyScalar::Int32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            PlyScalar::Uint32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            PlyScalar::Float32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            PlyScalar::Float64 => f64::from_le_bytes(b),
+        }
+    }
+
+    // Value of full intensity for colors of the type
+    fn color_scale(self) -> f64 {
+        match self {
+            PlyScalar::Int8 => 127.0,
+            PlyScalar::Uint8 => 255.0,
+            PlyScalar::Int16 => 32767.0,
+            PlyScalar::Uint16 => 65535.0,
+            PlyScalar::Int32 => 2_147_483_647.0,
+            PlyScalar::Uint32 => 4_294_967_295.0,
+            PlyScalar::Float32 | PlyScalar::Float64 => 1.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum PlyProperty {
+    Scalar(PlyScalar),
+    // Types of the count and of the items
+    List(PlyScalar, PlyScalar),
+}
+
+#[derive(Debug)]
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<(String, PlyProperty)>,
+}
 
-                    // For recutangular polygon
-                    if split_line.len() == 5 {
-                        let v4: Vec<&str> = split_line[4].split("/").collect();
-                        mesh.faces.push(Face {
-                            v0: v1[0].parse::<usize>().unwrap() - 1,
-                            v1: v3[0].parse::<usize>().unwrap() - 1,
-                            v2: v4[0].parse::<usize>().unwrap() - 1,
-                        });
+impl PlyElement {
+    fn index(&self, name: &str) -> Option<usize> {
+        self.properties.iter().position(|(n, _)| n == name)
+    }
+
+    // Indexes of all names, or None if any is missing
+    fn indexes(&self, names: &[&str]) -> Option<Vec<usize>> {
+        names.iter().map(|name| self.index(name)).collect()
+    }
+}
+
+// Values of the body of a PLY file in the order of the header
+struct PlyBody<R: BufRead> {
+    reader: R,
+    format: PlyFormat,
+    // Line of the header end, then of the current line of ASCII bodies
+    line: usize,
+    tokens: Vec<String>,
+}
+
+impl<R: BufRead> PlyBody<R> {
+    fn next(&mut self, scalar: PlyScalar) -> Result<f64, String> {
+        match self.format {
+            PlyFormat::Ascii => {
+                while self.tokens.is_empty() {
+                    let mut line = String::new();
+                    if self
+                        .reader
+                        .read_line(&mut line)
+                        .map_err(|e| e.to_string())?
+                        == 0
+                    {
+                        return Err("unexpected end of file".to_string());
                     }
+                    self.line += 1;
+                    self.tokens = line.split_whitespace().rev().map(String::from).collect();
                 }
-                _ => {}
+                let token = self.tokens.pop().unwrap();
+                token
+                    .parse::<f64>()
+                    .map_err(|e| format!("line {}: {}: {}", self.line, token, e))
+            }
+            PlyFormat::BinaryLittleEndian | PlyFormat::BinaryBigEndian => {
+                let mut bytes = [0u8; 8];
+                let bytes = &mut bytes[..scalar.size()];
+                self.reader.read_exact(bytes).map_err(|e| e.to_string())?;
+                Ok(scalar.decode(bytes, self.format == PlyFormat::BinaryBigEndian))
             }
         }
+    }
+
+    // Values of the properties of an element into row, lists as their length followed by
+    // their items. starts is set to where each property begins in row
+    fn read_row(
+        &mut self,
+        element: &PlyElement,
+        row: &mut Vec<f64>,
+        starts: &mut Vec<usize>,
+    ) -> Result<(), String> {
+        row.clear();
+        starts.clear();
+        for (_, property) in &element.properties {
+            starts.push(row.len());
+            match *property {
+                PlyProperty::Scalar(scalar) => row.push(self.next(scalar)?),
+                PlyProperty::List(count, item) => {
+                    let length = self.next(count)?;
+                    if length < 0.0 || length.fract() != 0.0 {
+                        return Err(format!("invalid list length {}", length));
+                    }
+                    row.push(length);
+                    for _ in 0..length as usize {
+                        row.push(self.next(item)?);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PlyLoader {
+    pub fn load(path: &str, matrix: Matrix44, material: Material) -> io::Result<Mesh> {
+        File::open(path)
+            .and_then(|f| PlyLoader::parse(BufReader::new(f), matrix, material))
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))
+    }
+
+    // Reads ASCII, binary little endian and binary big endian bodies. Vertexes may have
+    // normals, texture coordinates and colors, and faces are polygons
+    pub fn parse<R: BufRead>(
+        mut reader: R,
+        matrix: Matrix44,
+        material: Material,
+    ) -> io::Result<Mesh> {
+        let (format, elements, line) = PlyLoader::parse_header(&mut reader)
+            .map_err(|(line, e)| invalid_data(format!("line {}: {}", line, e)))?;
+        let mut body = PlyBody {
+            reader,
+            format,
+            line,
+            tokens: vec![],
+        };
+
+        let mut mesh = empty_mesh(material);
+        let normal_matrix = matrix.inverse().transposed();
+        let mut polygons = vec![];
+        let mut row = vec![];
+        let mut starts = vec![];
+        for element in &elements {
+            let vertex = PlyVertexProperties::new(element);
+            let face = ["vertex_indices", "vertex_index"]
+                .iter()
+                .find_map(|name| element.index(name));
+            if element.name == "vertex" && vertex.position.is_none() {
+                return Err(invalid_data("vertex has no x, y and z".to_string()));
+            }
+            if let Some(face) = face.filter(|_| element.name == "face") {
+                let (ref name, ref property) = element.properties[face];
+                if !matches!(property, PlyProperty::List(..)) {
+                    return Err(invalid_data(format!("face {} is not a list", name)));
+                }
+            }
+            for i in 0..element.count {
+                body.read_row(element, &mut row, &mut starts)
+                    .map_err(|e| invalid_data(format!("{} {}: {}", element.name, i, e)))?;
+                match element.name.as_str() {
+                    "vertex" => vertex.add(&row, &starts, &matrix, &normal_matrix, &mut mesh),
+                    "face" => {
+                        if let Some(face) = face {
+                            let length = row[starts[face]] as usize;
+                            let start = starts[face] + 1;
+                            polygons.push((i, row[start..start + length].to_vec()));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        for (i, polygon) in polygons {
+            PlyLoader::add_polygon(&polygon, &mut mesh)
+                .map_err(|e| invalid_data(format!("face {}: {}", i, e)))?;
+        }
+        Ok(mesh)
+    }
+
+    // Format, elements and the number of header lines. Errors have their line
+    fn parse_header<R: BufRead>(
+        reader: &mut R,
+    ) -> Result<(PlyFormat, Vec<PlyElement>, usize), (usize, String)> {
+        let mut format = None;
+        let mut elements: Vec<PlyElement> = vec![];
+        let mut number = 0;
+        loop {
+            let mut line = String::new();
+            let read = reader
+                .read_line(&mut line)
+                .map_err(|e| (number + 1, e.to_string()))?;
+            number += 1;
+            if read == 0 {
+                return Err((number, "unexpected end of header".to_string()));
+            }
+            let error = |message: String| (number, message);
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            if number == 1 {
+                if tokens != ["ply"] {
+                    return Err(error("not a PLY file".to_string()));
+                }
+                continue;
+            }
 
-        mesh
+            match tokens.as_slice() {
+                ["format", name, _version] => {
+                    format = Some(match *name {
+                        "ascii" => PlyFormat::Ascii,
+                        "binary_little_endian" => PlyFormat::BinaryLittleEndian,
+                        "binary_big_endian" => PlyFormat::BinaryBigEndian,
+                        _ => return Err(error(format!("unknown format {}", name))),
+                    })
+                }
+                ["element", name, count] => elements.push(PlyElement {
+                    name: name.to_string(),
+                    count: count
+                        .parse()
+                        .map_err(|e| error(format!("element count {}: {}", count, e)))?,
+                    properties: vec![],
+                }),
+                ["property", "list", count, item, name] => {
+                    let property = match (PlyScalar::parse(count), PlyScalar::parse(item)) {
+                        (Some(count), Some(item)) => PlyProperty::List(count, item),
+                        _ => return Err(error(format!("unknown list type {} {}", count, item))),
+                    };
+                    elements
+                        .last_mut()
+                        .ok_or_else(|| error("property before element".to_string()))?
+                        .properties
+                        .push((name.to_string(), property));
+                }
+                ["property", scalar, name] => {
+                    let scalar = PlyScalar::parse(scalar)
+                        .ok_or_else(|| error(format!("unknown type {}", scalar)))?;
+                    elements
+                        .last_mut()
+                        .ok_or_else(|| error("property before element".to_string()))?
+                        .properties
+                        .push((name.to_string(), PlyProperty::Scalar(scalar)));
+                }
+                ["end_header"] => {
+                    return match format {
+                        Some(format) => Ok((format, elements, number)),
+                        None => Err(error("no format before end_header".to_string())),
+                    }
+                }
+                ["comment", ..] | ["obj_info", ..] | [] => {}
+                _ => return Err(error(format!("invalid header line {}", line.trim()))),
+            }
+        }
+    }
+
+    fn add_polygon(polygon: &[f64], mesh: &mut Mesh) -> Result<(), String> {
+        if polygon.len() < 3 {
+            return Err(format!(
+                "face needs at least 3 vertices but got {}",
+                polygon.len()
+            ));
+        }
+        let count = mesh.vertexes.len();
+        let corners = polygon
+            .iter()
+            .map(|i| {
+                if *i >= 0.0 && (*i as usize) < count {
+                    Ok(*i as usize)
+                } else {
+                    Err(format!("vertex index {} is out of {} defined", i, count))
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let points: Vec<Vector3> = corners.iter().map(|c| mesh.vertexes[*c]).collect();
+
+        // Normals and texture coordinates are indexed as the vertexes
+        let has_normals = mesh.normals.len() == count;
+        let has_uvs = mesh.uvs.len() == count;
+        for triangle in triangulate_polygon(&points) {
+            let corners = triangle.map(|i| corners[i]);
+            mesh.faces.push(Face {
+                normals: if has_normals { Some(corners) } else { None },
+                uvs: if has_uvs { Some(corners) } else { None },
+                ..Face::new(corners[0], corners[1], corners[2])
+            });
+        }
+        Ok(())
+    }
+}
+
+// Where the attributes of a vertex are among the properties of the vertex element
+struct PlyVertexProperties {
+    position: Option<Vec<usize>>,
+    normal: Option<Vec<usize>>,
+    uv: Option<Vec<usize>>,
+    color: Option<(Vec<usize>, f64)>,
+}
+
+impl PlyVertexProperties {
+    fn new(element: &PlyElement) -> PlyVertexProperties {
+        let first =
+            |candidates: &[&[&str]]| candidates.iter().find_map(|names| element.indexes(names));
+        let color = first(&[
+            &["red", "green", "blue"],
+            &["diffuse_red", "diffuse_green", "diffuse_blue"],
+        ]);
+        PlyVertexProperties {
+            position: element.indexes(&["x", "y", "z"]),
+            normal: element.indexes(&["nx", "ny", "nz"]),
+            uv: first(&[
+                &["u", "v"],
+                &["s", "t"],
+                &["texture_u", "texture_v"],
+                &["texture_s", "texture_t"],
+            ]),
+            color: color.map(|indexes| {
+                let scale = match element.properties[indexes[0]].1 {
+                    PlyProperty::Scalar(scalar) => scalar.color_scale(),
+                    PlyProperty::List(..) => 1.0,
+                };
+                (indexes, scale)
+            }),
+        }
+    }
+
+    fn add(
+        &self,
+        row: &[f64],
+        starts: &[usize],
+        matrix: &Matrix44,
+        normal_matrix: &Matrix44,
+        mesh: &mut Mesh,
+    ) {
+        let vector = |indexes: &[usize]| {
+            Vector3::new(
+                row[starts[indexes[0]]],
+                row[starts[indexes[1]]],
+                row[starts[indexes[2]]],
+            )
+        };
+        if let Some(ref position) = self.position {
+            mesh.vertexes.push(*matrix * vector(position));
+        }
+        if let Some(ref normal) = self.normal {
+            let world_normal = normal_matrix.transform_direction(&vector(normal));
+            mesh.normals.push(world_normal.normalized());
+        }
+        if let Some(ref uv) = self.uv {
+            mesh.uvs
+                .push(Vector2::new(row[starts[uv[0]]], row[starts[uv[1]]]));
+        }
+        // Colors are sRGB encoded
+        if let Some((ref color, scale)) = self.color {
+            mesh.colors.push(gamma_to_linear(vector(color) / scale));
+        }
+    }
+}
+
+pub struct StlLoader;
+
+impl StlLoader {
+    pub fn load(path: &str, matrix: Matrix44, material: Material) -> io::Result<Mesh> {
+        File::open(path)
+            .and_then(|f| StlLoader::parse(BufReader::new(f), matrix, material))
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))
+    }
+
+    // ASCII or binary, told by the size binary files must have, as binary headers may start
+    // with "solid" too. Facet normals are not used and identical positions share a vertex
+    pub fn parse<R: Read>(mut reader: R, matrix: Matrix44, material: Material) -> io::Result<Mesh> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        let binary_count = bytes
+            .get(80..84)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize);
+
+        let mut mesh = empty_mesh(material);
+        let mut indexes = HashMap::new();
+        let mut add_triangle = |triangle: [Vector3; 3], mesh: &mut Mesh| {
+            let corners = triangle.map(|p| {
+                let key = [p.x.to_bits(), p.y.to_bits(), p.z.to_bits()];
+                *indexes.entry(key).or_insert_with(|| {
+                    mesh.vertexes.push(matrix * p);
+                    mesh.vertexes.len() - 1
+                })
+            });
+            mesh.faces
+                .push(Face::new(corners[0], corners[1], corners[2]));
+        };
+
+        match binary_count {
+            Some(count) if bytes.len() == 84 + 50 * count => {
+                for triangle in bytes[84..].chunks_exact(50) {
+                    // Normal, then the vertexes
+                    let value = |i: usize| {
+                        let b = &triangle[i * 4..i * 4 + 4];
+                        f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64
+                    };
+                    let vertex =
+                        |v: usize| Vector3::new(value(v * 3), value(v * 3 + 1), value(v * 3 + 2));
+                    add_triangle([vertex(1), vertex(2), vertex(3)], &mut mesh);
+                }
+            }
+            _ => {
+                let text = std::str::from_utf8(&bytes).map_err(|_| {
+                    invalid_data("not an ASCII STL or of a binary STL size".to_string())
+                })?;
+                let mut corners = vec![];
+                for (i, line) in text.lines().enumerate() {
+                    let facet_end = StlLoader::parse_line(line, &mut corners)
+                        .map_err(|e| invalid_data(format!("line {}: {}", i + 1, e)))?;
+                    if facet_end {
+                        add_triangle([corners[0], corners[1], corners[2]], &mut mesh);
+                        corners.clear();
+                    }
+                }
+                if !corners.is_empty() {
+                    return Err(invalid_data("unexpected end of file".to_string()));
+                }
+            }
+        }
+        Ok(mesh)
+    }
+
+    // Whether the line ends the loop of a facet, with its corners
+    fn parse_line(line: &str, corners: &mut Vec<Vector3>) -> Result<bool, String> {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.split_first() {
+            Some((&"vertex", arguments)) => {
+                let v = parse_floats(arguments, 3, 3)?;
+                corners.push(Vector3::new(v[0], v[1], v[2]));
+            }
+            Some((&"endloop", _)) if corners.len() != 3 => {
+                return Err(format!("facet needs 3 vertices but got {}", corners.len()))
+            }
+            Some((&"endloop", _)) => return Ok(true),
+            Some((keyword, _))
+                if !["solid", "facet", "outer", "endloop", "endfacet", "endsolid"]
+                    .contains(keyword) =>
+            {
+                return Err(format!("unknown keyword {}", keyword))
+            }
+            _ => {}
+        }
+        Ok(false)
     }
 }
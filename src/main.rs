@@ -2,28 +2,362 @@ mod sample_scenes;
 
 extern crate fulleffect;
 
-use fulleffect::camera::Camera;
+use fulleffect::accumulation::AccumulationBuffer;
+use fulleffect::aperture::ApertureMask;
+use fulleffect::camera::{Camera, LensShape, Projection};
+use fulleffect::checkpoint;
+use fulleffect::checkpoint::SamplingSettings;
+use fulleffect::distributed;
 use fulleffect::filter;
-use fulleffect::renderer::PathTracingRenderer;
-use fulleffect::renderer::{DebugRenderMode, DebugRenderer, Renderer};
+use fulleffect::gltf::GltfLoader;
+use fulleffect::lens;
+use fulleffect::lens::LensSystem;
+use fulleffect::renderer::{update_imgbuf, DebugRenderMode, DebugRenderer, Renderer};
+use fulleffect::renderer::{CheckpointSettings, Interval, PathTracingRenderer, ProgressSnapshot};
 use fulleffect::scene::Scene;
+use fulleffect::stereo::{Convergence, StereoLayout, StereoRig};
+use fulleffect::tile::{Region, TileOrder, Tiling};
 use fulleffect::tonemap;
+use getopts::Options;
+use image::{ImageBuffer, Rgb};
+use std::env;
+use std::net::TcpListener;
+use std::process;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
 use stopwatch::Stopwatch;
 
+fn save_image(
+    accumulation: &AccumulationBuffer,
+    imgbuf: ImageBuffer<Rgb<u8>, Vec<u8>>,
+    crop: bool,
+) {
+    let region = accumulation.region;
+    let output = if crop {
+        imgbuf
+    } else {
+        // Keep the whole frame, leaving outside of the region black
+        let mut frame = ImageBuffer::new(accumulation.width, accumulation.height);
+        image::imageops::replace(&mut frame, &imgbuf, region.x as i64, region.y as i64);
+        frame
+    };
+    let _ = image::DynamicImage::ImageRgb8(output).save("result.png");
+}
+
+fn save_accumulated_image(
+    filter: filter::PixelArrayFilterFn,
+    tonemap: tonemap::TonemapFn,
+    accumulation: &AccumulationBuffer,
+    crop: bool,
+) {
+    let region = accumulation.region;
+    let mut imgbuf = ImageBuffer::new(region.width, region.height);
+    update_imgbuf(filter, tonemap, accumulation, &mut imgbuf);
+    save_image(accumulation, imgbuf, crop);
+}
+
 fn render_and_save_image<R: Renderer>(
     renderer: &mut R,
-    width: u32,
-    height: u32,
     camera: &Camera,
-    scene: Scene,
+    scene: &Scene,
+    accumulation: &mut AccumulationBuffer,
+    crop: bool,
 ) -> u32 {
-    let mut imgbuf = image::ImageBuffer::new(width, height);
-    let sampled = renderer.render(&scene, camera, &mut imgbuf);
-    let _ = image::DynamicImage::ImageRgb8(imgbuf).save("result.png");
+    let region = accumulation.region;
+    let mut imgbuf = ImageBuffer::new(region.width, region.height);
+    let sampled = renderer.accumulate(scene, camera, accumulation, &mut imgbuf);
+    save_image(accumulation, imgbuf, crop);
     sampled
 }
 
+// Sums up the checkpoints given after merge, checked against the scene if its hash is given
+fn merge_checkpoints(
+    matches: &getopts::Matches,
+    renderer: &PathTracingRenderer,
+    width: u32,
+    height: u32,
+    region: Region,
+    scene_hash: Option<u64>,
+) {
+    let paths = &matches.free[1..];
+    let (scene_hash, accumulation) =
+        checkpoint::merge(paths, scene_hash, width, height, region).unwrap_or_else(|e| {
+            eprintln!("Failed to merge: {}", e);
+            process::exit(1);
+        });
+
+    save_accumulated_image(
+        renderer.filter,
+        renderer.tonemap,
+        &accumulation,
+        matches.opt_present("crop"),
+    );
+    if let Some(path) = matches.opt_str("sampling-map") {
+        let _ = image::DynamicImage::ImageRgb8(accumulation.sampling_map()).save(path);
+    }
+    if let Some(path) = matches.opt_str("checkpoint") {
+        let settings = SamplingSettings::of(renderer);
+        if let Err(e) = checkpoint::save(&path, scene_hash, &settings, &accumulation) {
+            eprintln!("Failed to save merged buffer {}: {}", path, e);
+        }
+    }
+
+    let samples_per_pixel = accumulation.total_sampling() as f64 / region.num_of_pixel() as f64;
+    println!(
+        "Merged {} buffers ({:.2} samples per pixel on average)",
+        paths.len(),
+        samples_per_pixel
+    );
+}
+
+// First Ctrl-C finishes the current sampling and saves, second one aborts
+fn install_interrupt_handler(renderer: &PathTracingRenderer) {
+    let interrupted = renderer.interrupted.clone();
+    ctrlc::set_handler(move || {
+        if interrupted.swap(true, Ordering::SeqCst) {
+            process::exit(130);
+        }
+    })
+    .expect("Failed to set Ctrl-C handler");
+}
+
+fn parse_opt_maybe<T: std::str::FromStr>(matches: &getopts::Matches, name: &str) -> Option<T> {
+    matches.opt_str(name).map(|value| {
+        value
+            .parse()
+            .unwrap_or_else(|_| panic!("Invalid value for --{}: {}", name, value))
+    })
+}
+
+fn parse_opt<T: std::str::FromStr>(matches: &getopts::Matches, name: &str, default: T) -> T {
+    parse_opt_maybe(matches, name).unwrap_or(default)
+}
+
+// Every sampling unless either is given
+fn parse_interval(matches: &getopts::Matches, every_name: &str, interval_name: &str) -> Interval {
+    let every_duration = parse_opt_maybe(matches, interval_name).map(Duration::from_secs_f64);
+    let every_sampling = match parse_opt_maybe(matches, every_name) {
+        None if every_duration.is_none() => Some(1),
+        n => n,
+    };
+    Interval {
+        every_sampling,
+        every_duration,
+    }
+}
+
 fn main() {
+    let args: Vec<String> = env::args().collect();
+    let mut opts = Options::new();
+    opts.optopt("s", "samples", "maximum samplings per pixel", "SPP");
+    opts.optopt(
+        "",
+        "min-samples",
+        "samplings per pixel before adaptive sampling starts",
+        "SPP",
+    );
+    opts.optopt(
+        "",
+        "error-threshold",
+        "stop sampling pixels whose relative error is below this",
+        "ERROR",
+    );
+    opts.optopt(
+        "",
+        "tile-size",
+        "render in tiles of this size instead of all pixels at once",
+        "PIXELS",
+    );
+    opts.optopt(
+        "",
+        "tile-order",
+        "order of tiles: scanline, spiral or hilbert (default scanline)",
+        "ORDER",
+    );
+    opts.optopt(
+        "",
+        "region",
+        "render only this part of the frame",
+        "X,Y,WIDTH,HEIGHT",
+    );
+    opts.optflag(
+        "",
+        "crop",
+        "write only the region instead of the whole frame",
+    );
+    opts.optopt(
+        "",
+        "sampling-map",
+        "write the per-pixel sampling count map",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "time-limit",
+        "stop after the sampling which would exceed this budget",
+        "SECONDS",
+    );
+    opts.optopt(
+        "",
+        "target-error",
+        "stop once the mean relative error of pixels is below this",
+        "ERROR",
+    );
+    opts.optopt(
+        "",
+        "snapshot",
+        "periodically write the image in progress",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "snapshot-hdr",
+        "with --snapshot, also write the HDR radiance in progress (.hdr)",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "snapshot-every",
+        "write snapshots every N samplings (default 1)",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "snapshot-interval",
+        "write snapshots every N seconds",
+        "SECONDS",
+    );
+    opts.optopt(
+        "",
+        "checkpoint",
+        "periodically save the accumulation to resume from or merge later",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "checkpoint-every",
+        "save checkpoints every N samplings (default 1)",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "checkpoint-interval",
+        "save checkpoints every N seconds",
+        "SECONDS",
+    );
+    opts.optopt(
+        "",
+        "seed-offset",
+        "offset of sampling seeds, give runs to merge disjoint ranges e.g. 0, 100, 200",
+        "N",
+    );
+    opts.optflag(
+        "",
+        "resume",
+        "continue accumulating from the --checkpoint file",
+    );
+    opts.optopt(
+        "",
+        "serve",
+        "coordinate a distributed render, handing out jobs to workers",
+        "ADDRESS:PORT",
+    );
+    opts.optopt(
+        "",
+        "job-samples",
+        "samplings per distributed job, tiles are split by --tile-size",
+        "SPP",
+    );
+    opts.optopt(
+        "",
+        "worker-timeout",
+        "seconds to wait for a job or, once all workers are gone, a new one (default 600)",
+        "SECONDS",
+    );
+    opts.optopt(
+        "",
+        "worker",
+        "render jobs for the coordinator at this address",
+        "ADDRESS:PORT",
+    );
+    opts.optopt(
+        "",
+        "stereo",
+        "render left and right eyes into one image: side-by-side or top-bottom",
+        "LAYOUT",
+    );
+    opts.optopt(
+        "",
+        "interaxial",
+        "distance between the eyes (default 1/30 of the convergence distance)",
+        "DISTANCE",
+    );
+    opts.optopt(
+        "",
+        "convergence",
+        "distance of zero parallax (default the focus distance)",
+        "DISTANCE",
+    );
+    opts.optflag(
+        "",
+        "toe-in",
+        "rotate the eyes toward the convergence point instead of shifting off-axis",
+    );
+    opts.optopt(
+        "",
+        "gltf",
+        "render this glTF or GLB scene through its first camera instead of the sample scene",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "lens",
+        "trace through the lens prescription of this table instead of a thin lens",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "sensor-width",
+        "sensor width behind the --lens in mm (default 36)",
+        "MM",
+    );
+    opts.optopt(
+        "",
+        "sensor-height",
+        "sensor height behind the --lens in mm (default 24)",
+        "MM",
+    );
+    opts.optopt(
+        "",
+        "lens-scale",
+        "scene units per mm of the --lens (default 0.001)",
+        "UNITS",
+    );
+    opts.optopt(
+        "",
+        "aperture-mask",
+        "image whose luminance is the transmittance of the thin lens aperture, not with --lens",
+        "PATH",
+    );
+    opts.optflag(
+        "",
+        "check-scene",
+        "with merge, load the scene and check that the checkpoints were rendered from it",
+    );
+    opts.optflag("h", "help", "print this help");
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => panic!("{}", f),
+    };
+    if matches.opt_present("h") {
+        let brief = format!(
+            "Usage: {0} [options]\n       {0} merge [options] CHECKPOINT...",
+            args[0]
+        );
+        print!("{}", opts.usage(&brief));
+        return;
+    }
+
     println!("Start rendering...");
 
     let width = 640u32;
@@ -32,20 +366,287 @@ fn main() {
     // let width = 1920u32;
     // let height = 1080u32;
 
-    let (camera, scene) = sample_scenes::simple_scene_mesh::sample_scene();
+    let frame = Region::new(0, 0, width, height);
+    let region = parse_opt(&matches, "region", frame);
+    if !frame.contains(&region) {
+        eprintln!("Region {:?} is outside of the frame {:?}", region, frame);
+        process::exit(1);
+    }
 
     let mut _renderer = DebugRenderer {
         filter: filter::identity_filter,
         tonemap: tonemap::none,
         mode: DebugRenderMode::Shading,
     };
+    let sampling = parse_opt(&matches, "samples", 10);
     let mut path_tracing_renderer =
-        PathTracingRenderer::new(10, filter::identity_filter, tonemap::none);
+        PathTracingRenderer::new(sampling, filter::identity_filter, tonemap::none);
+    path_tracing_renderer.min_sampling = parse_opt(&matches, "min-samples", sampling);
+    path_tracing_renderer.error_threshold = parse_opt(&matches, "error-threshold", 0.0);
+    path_tracing_renderer.tiling = parse_opt_maybe(&matches, "tile-size").map(|size| Tiling {
+        size,
+        order: parse_opt(&matches, "tile-order", TileOrder::Scanline),
+    });
+    path_tracing_renderer.time_limit =
+        parse_opt_maybe(&matches, "time-limit").map(Duration::from_secs_f64);
+    path_tracing_renderer.target_error = parse_opt_maybe(&matches, "target-error");
+    path_tracing_renderer.sampling_offset = parse_opt(&matches, "seed-offset", 0);
+    if matches.opt_present("snapshot-hdr") && !matches.opt_present("snapshot") {
+        eprintln!("--snapshot-hdr needs --snapshot");
+        process::exit(1);
+    }
+    if matches.opt_present("resume") && !matches.opt_present("checkpoint") {
+        eprintln!("--resume needs --checkpoint");
+        process::exit(1);
+    }
+    // A lens system replaces the thin lens the mask would shape
+    if matches.opt_present("aperture-mask") && matches.opt_present("lens") {
+        eprintln!("--aperture-mask is not supported with --lens");
+        process::exit(1);
+    }
+    path_tracing_renderer.snapshot = matches.opt_str("snapshot").map(|path| ProgressSnapshot {
+        path,
+        hdr_path: matches.opt_str("snapshot-hdr"),
+        interval: parse_interval(&matches, "snapshot-every", "snapshot-interval"),
+    });
+
+    let merging = matches.free.first().map(String::as_str) == Some("merge");
+    if merging && !matches.opt_present("check-scene") {
+        merge_checkpoints(&matches, &path_tracing_renderer, width, height, region, None);
+        return;
+    }
+
+    let (camera, scene) = match matches.opt_str("gltf") {
+        Some(path) => {
+            let gltf =
+                GltfLoader::load(&path, width as f64 / height as f64).unwrap_or_else(|e| {
+                    eprintln!("Failed to load glTF: {}", e);
+                    process::exit(1);
+                });
+            match gltf.cameras.into_iter().next() {
+                Some(camera) => (camera, gltf.scene),
+                None => {
+                    eprintln!("{} has no camera", path);
+                    process::exit(1);
+                }
+            }
+        }
+        None => sample_scenes::simple_scene_mesh::sample_scene(width as f64 / height as f64),
+    };
+    let camera = match matches.opt_str("lens") {
+        Some(path) => {
+            let elements = lens::read_lens_table(&path).unwrap_or_else(|e| {
+                eprintln!("Failed to read lens: {}", e);
+                process::exit(1);
+            });
+            let lens = LensSystem::new(
+                elements,
+                parse_opt(&matches, "sensor-width", 36.0),
+                parse_opt(&matches, "sensor-height", 24.0),
+                width as f64 / height as f64,
+                parse_opt(&matches, "lens-scale", 0.001),
+                camera.focus_distance,
+            )
+            .unwrap_or_else(|e| {
+                eprintln!("Invalid lens {}: {}", path, e);
+                process::exit(1);
+            });
+            Camera {
+                projection: Projection::Lens(lens),
+                ..camera
+            }
+        }
+        None => camera,
+    };
+    let camera = match matches.opt_str("aperture-mask") {
+        Some(path) => {
+            let mask = ApertureMask::new(&path).unwrap_or_else(|e| {
+                eprintln!("Failed to read aperture mask: {}", e);
+                process::exit(1);
+            });
+            Camera {
+                lens_shape: LensShape::Mask(mask),
+                ..camera
+            }
+        }
+        None => camera,
+    };
+
+    if let Some(layout) = parse_opt_maybe::<StereoLayout>(&matches, "stereo") {
+        // Snapshots would be of a single eye, not the composed layout
+        let unsupported = [
+            "region",
+            "checkpoint",
+            "resume",
+            "snapshot",
+            "serve",
+            "worker",
+        ];
+        if let Some(name) = unsupported.iter().find(|name| matches.opt_present(name)) {
+            eprintln!("--{} is not supported with --stereo", name);
+            process::exit(1);
+        }
+        if !matches.free.is_empty() {
+            eprintln!("{} is not supported with --stereo", matches.free[0]);
+            process::exit(1);
+        }
+
+        let convergence_distance = parse_opt(&matches, "convergence", camera.focus_distance);
+        let rig = StereoRig {
+            interaxial: parse_opt(&matches, "interaxial", convergence_distance / 30.0),
+            convergence_distance,
+            convergence: if matches.opt_present("toe-in") {
+                Convergence::ToeIn
+            } else {
+                Convergence::OffAxis
+            },
+        };
+
+        install_interrupt_handler(&path_tracing_renderer);
+        let mut stopwatch = Stopwatch::start_new();
+        let mut imgbufs = vec![ImageBuffer::new(width, height); 2];
+        let sampled = path_tracing_renderer.render_views(&scene, &rig.eyes(&camera), &mut imgbufs);
+        stopwatch.stop();
+
+        let output = layout.compose(&imgbufs[0], &imgbufs[1]);
+        let _ = image::DynamicImage::ImageRgb8(output).save("result.png");
+        println!("\nRendered {:?} with {:?} samples", rig, sampled);
+        println!("Done rendering in {} sec", stopwatch.elapsed().as_secs());
+        return;
+    }
+
+    let scene_hash = checkpoint::scene_hash(&scene, &camera);
+
+    if let Some(address) = matches.opt_str("worker") {
+        let tiling = path_tracing_renderer.tiling;
+        let error_threshold = path_tracing_renderer.error_threshold;
+        let result = distributed::work(&address, &scene, &camera, scene_hash, |job| {
+            println!("\nRendering {:?}", job);
+            let mut renderer =
+                PathTracingRenderer::new(job.sampling, filter::identity_filter, tonemap::none);
+            renderer.sampling_offset = job.sampling_offset;
+            renderer.error_threshold = error_threshold;
+            renderer.tiling = tiling;
+            renderer
+        });
+        match result {
+            Ok(num_of_job) => println!("\nDone {} jobs for {}", num_of_job, address),
+            Err(e) => {
+                eprintln!("\nWorker failed: {}", e);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(address) = matches.opt_str("serve") {
+        let listener = TcpListener::bind(&address).unwrap_or_else(|e| {
+            eprintln!("Failed to listen on {}: {}", address, e);
+            process::exit(1);
+        });
+        let jobs = distributed::split_jobs(
+            width,
+            height,
+            region,
+            path_tracing_renderer.tiling,
+            sampling,
+            parse_opt(&matches, "job-samples", sampling),
+        );
+        println!("Waiting for workers on {} for {} jobs", address, jobs.len());
+
+        let mut stopwatch = Stopwatch::start_new();
+        let accumulation = AccumulationBuffer::with_region(width, height, region);
+        let worker_timeout = parse_opt_maybe(&matches, "worker-timeout")
+            .map(Duration::from_secs_f64)
+            .unwrap_or(distributed::DEFAULT_WORKER_TIMEOUT);
+        let accumulation =
+            distributed::serve(listener, scene_hash, accumulation, jobs, worker_timeout)
+                .unwrap_or_else(|e| {
+                    eprintln!("Distributed rendering failed: {}", e);
+                    process::exit(1);
+                });
+        stopwatch.stop();
+
+        save_accumulated_image(
+            path_tracing_renderer.filter,
+            path_tracing_renderer.tonemap,
+            &accumulation,
+            matches.opt_present("crop"),
+        );
+        println!("\nDone rendering in {} sec", stopwatch.elapsed().as_secs());
+        return;
+    }
+
+    if merging {
+        merge_checkpoints(
+            &matches,
+            &path_tracing_renderer,
+            width,
+            height,
+            region,
+            Some(scene_hash),
+        );
+        return;
+    }
+
+    path_tracing_renderer.checkpoint =
+        matches
+            .opt_str("checkpoint")
+            .map(|path| CheckpointSettings {
+                path,
+                scene_hash,
+                interval: parse_interval(&matches, "checkpoint-every", "checkpoint-interval"),
+            });
+
+    let resume_path = matches
+        .opt_str("checkpoint")
+        .filter(|_| matches.opt_present("resume"));
+    let mut accumulation = if let Some(path) = resume_path {
+        let settings = SamplingSettings::of(&path_tracing_renderer);
+        match checkpoint::load(&path, scene_hash, &settings, width, height, region) {
+            Ok(accumulation) => {
+                println!(
+                    "Resuming from {} with {} samples",
+                    path,
+                    accumulation.max_sampling()
+                );
+                accumulation
+            }
+            Err(e) => {
+                eprintln!("Failed to resume from {}: {}", path, e);
+                process::exit(1);
+            }
+        }
+    } else {
+        AccumulationBuffer::with_region(width, height, region)
+    };
+
+    install_interrupt_handler(&path_tracing_renderer);
 
     let mut stopwatch = Stopwatch::start_new();
-    let sampled = render_and_save_image(&mut path_tracing_renderer, width, height, &camera, scene);
+    let sampled = render_and_save_image(
+        &mut path_tracing_renderer,
+        &camera,
+        &scene,
+        &mut accumulation,
+        matches.opt_present("crop"),
+    );
     stopwatch.stop();
 
-    println!("Rendered with {} samples", sampled);
+    if let Some(path) = matches.opt_str("sampling-map") {
+        let _ = image::DynamicImage::ImageRgb8(accumulation.sampling_map()).save(path);
+    }
+    if let Some(path) = matches.opt_str("checkpoint") {
+        let settings = SamplingSettings::of(&path_tracing_renderer);
+        if let Err(e) = checkpoint::save(&path, scene_hash, &settings, &accumulation) {
+            eprintln!("Failed to save checkpoint {}: {}", path, e);
+        }
+    }
+
+    let samples_per_pixel = accumulation.total_sampling() as f64 / region.num_of_pixel() as f64;
+    println!(
+        "Rendered with {} samples ({:.2} samples per pixel on average)",
+        sampled, samples_per_pixel
+    );
     println!("Done rendering in {} sec", stopwatch.elapsed().as_secs());
 }
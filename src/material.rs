@@ -16,17 +16,44 @@ pub enum SurfaceType {
 }
 
 pub struct SampleResult {
+    // Time of the ray is set by the caller
     pub ray: Ray,
 
     pub reflectance: f64,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Material {
     pub surface: SurfaceType,
     pub albedo: Texture,
     pub emission: Texture,
     pub roughness: Texture,
+    // Height along the normal in scene units, perturbing the shading normal of elements
+    // with texture coordinates. Constant heights leave it as is
+    pub bump: Texture,
+}
+
+impl Material {
+    // Material without bumps, see with_bump
+    pub fn new(
+        surface: SurfaceType,
+        albedo: Texture,
+        emission: Texture,
+        roughness: Texture,
+    ) -> Material {
+        Material {
+            surface,
+            albedo,
+            emission,
+            roughness,
+            bump: Texture::black(),
+        }
+    }
+
+    pub fn with_bump(mut self, bump: Texture) -> Material {
+        self.bump = bump;
+        self
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -52,6 +79,7 @@ impl PointMaterial {
                 ray: Ray {
                     origin: *position + *normal * config::OFFSET,
                     direction: importance_sample_diffuse(random, normal),
+                    time: 0.0,
                 },
                 reflectance: 1.0,
             }),
@@ -59,6 +87,7 @@ impl PointMaterial {
                 ray: Ray {
                     origin: *position + *normal * config::OFFSET,
                     direction: ray.reflect(normal),
+                    time: 0.0,
                 },
                 reflectance: 1.0,
             }),
@@ -89,6 +118,7 @@ impl PointMaterial {
                         ray: Ray {
                             origin: *position + *normal * config::OFFSET,
                             direction: next_direction,
+                            time: 0.0,
                         },
                         reflectance: f * saturate(g * v_dot_h / (h_dot_n * v_dot_n)),
                     })
@@ -3,7 +3,7 @@ use crate::config;
 use crate::material::SampleResult;
 use crate::vector::Vector3;
 
-fn get_tangent_space_basis_gram_schmidtd(normal: &Vector3) -> (Vector3, Vector3) {
+pub fn get_tangent_space_basis_gram_schmidtd(normal: &Vector3) -> (Vector3, Vector3) {
     let up = if normal.x.abs() > config::EPS {
         Vector3::new(0.0, 1.0, 0.0)
     } else {
@@ -44,6 +44,7 @@ pub fn sample_refraction(
             ray: Ray {
                 origin: *position + config::OFFSET * oriented_normal,
                 direction: reflect_direction,
+                time: 0.0,
             },
             reflectance: 1.0,
         })
@@ -65,6 +66,7 @@ pub fn sample_refraction(
                 ray: Ray {
                     origin: *position + config::OFFSET * oriented_normal,
                     direction: reflect_direction,
+                    time: 0.0,
                 },
                 reflectance: 1.0,
             })
@@ -74,6 +76,7 @@ pub fn sample_refraction(
                 ray: Ray {
                     origin: *position - config::OFFSET * oriented_normal,
                     direction: refract_direction,
+                    time: 0.0,
                 },
                 reflectance: nnt * nnt,
             })
@@ -42,3 +42,54 @@ pub fn det(a: &Vector3, b: &Vector3, c: &Vector3) -> f64 {
 pub fn mix(x: &Vector3, y: &Vector3, a: f64) -> Vector3 {
     *x * (1.0 - a) + *y * a
 }
+
+// Real roots in [min, max] of the polynomial with coefficients from the constant term up, in
+// increasing order. Roots of the derivative split the range into monotonic intervals each
+// bisected for a root, so double roots touching zero without crossing are missed
+pub fn polynomial_roots(coefficients: &[f64], min: f64, max: f64) -> Vec<f64> {
+    let evaluate = |x: f64| coefficients.iter().rev().fold(0.0, |sum, c| sum * x + c);
+    let mut bounds = vec![min];
+    if coefficients.len() > 2 {
+        let derivative: Vec<f64> = coefficients
+            .iter()
+            .enumerate()
+            .skip(1)
+            .map(|(i, c)| i as f64 * c)
+            .collect();
+        bounds.extend(polynomial_roots(&derivative, min, max));
+    }
+    bounds.push(max);
+
+    let mut roots: Vec<f64> = vec![];
+    for interval in bounds.windows(2) {
+        let (mut a, mut b) = (interval[0], interval[1]);
+        let (fa, fb) = (evaluate(a), evaluate(b));
+        if fa == 0.0 {
+            if roots.last() != Some(&a) {
+                roots.push(a);
+            }
+            continue;
+        }
+        if fb == 0.0 {
+            roots.push(b);
+            continue;
+        }
+        if fa.signum() == fb.signum() {
+            continue;
+        }
+        // Sign of the polynomial at a stays that of fa
+        for _ in 0..100 {
+            let mid = 0.5 * (a + b);
+            if mid <= a || mid >= b {
+                break;
+            }
+            if evaluate(mid).signum() == fa.signum() {
+                a = mid;
+            } else {
+                b = mid;
+            }
+        }
+        roots.push(0.5 * (a + b));
+    }
+    roots
+}
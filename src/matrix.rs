@@ -183,6 +183,25 @@ impl Matrix44 {
         return ret;
     }
 
+    pub fn transposed(&self) -> Matrix44 {
+        let mut ret = Matrix44::identity();
+        for i in 0..4 {
+            for j in 0..4 {
+                ret[i][j] = self[j][i];
+            }
+        }
+        ret
+    }
+
+    // Applies only the linear part, for directions and normals
+    pub fn transform_direction(&self, v: &Vector3) -> Vector3 {
+        Vector3 {
+            x: v.x * self[0][0] + v.y * self[0][1] + v.z * self[0][2],
+            y: v.x * self[1][0] + v.y * self[1][1] + v.z * self[1][2],
+            z: v.x * self[2][0] + v.y * self[2][1] + v.z * self[2][2],
+        }
+    }
+
     pub fn det(&self) -> f64 {
         (self[0][0] * self[1][1]
             + self[2][2] * self[3][3]
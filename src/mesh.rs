@@ -1,11 +1,12 @@
 use crate::aabb::Aabb;
 use crate::camera::Ray;
-use crate::config::INF;
+use crate::color::Color;
+use crate::config;
 use crate::material::Material;
 use crate::math::det;
 use crate::rayintersectable::Intersectable;
 use crate::rayintersectable::Intersection;
-use crate::scene::Surface;
+use crate::texture::Texture;
 use crate::vector::Vector2;
 use crate::vector::Vector3;
 
@@ -23,26 +24,240 @@ pub struct Face {
     pub v0: usize,
     pub v1: usize,
     pub v2: usize,
+    // Indexes of normals and uvs of the mesh for v0, v1 and v2 if given
+    pub normals: Option<[usize; 3]>,
+    pub uvs: Option<[usize; 3]>,
+    // Index of the material in materials of the mesh, None for its main material
+    pub material: Option<usize>,
+}
+
+impl Face {
+    pub fn new(v0: usize, v1: usize, v2: usize) -> Face {
+        Face {
+            v0,
+            v1,
+            v2,
+            normals: None,
+            uvs: None,
+            material: None,
+        }
+    }
 }
 
 pub struct Mesh {
     pub vertexes: Vec<Vector3>,
+    // Linear colors of the vertexes, or empty
+    pub colors: Vec<Color>,
+    pub normals: Vec<Vector3>,
+    pub uvs: Vec<Vector2>,
     pub faces: Vec<Face>,
     pub material: Material,
+    pub materials: Vec<Material>,
+}
+
+impl Mesh {
+    // Gives faces without normals smooth ones, averaging normals of faces around each vertex
+    // weighted by their angles there
+    pub fn with_generated_normals(mut self) -> Mesh {
+        let mut vertex_normals = vec![Vector3::zero(); self.vertexes.len()];
+        for face in &self.faces {
+            let indexes = [face.v0, face.v1, face.v2];
+            let p = indexes.map(|i| self.vertexes[i]);
+            let cross = (p[1] - p[0]).cross(&(p[2] - p[0]));
+            if cross.length() == 0.0 {
+                continue;
+            }
+            let normal = cross.normalized();
+            for k in 0..3 {
+                let a = (p[(k + 1) % 3] - p[k]).normalized();
+                let b = (p[(k + 2) % 3] - p[k]).normalized();
+                vertex_normals[indexes[k]] += normal * a.dot(&b).clamp(-1.0, 1.0).acos();
+            }
+        }
+
+        let offset = self.normals.len();
+        self.normals
+            .extend(vertex_normals.iter().map(|n| n.normalized()));
+        for face in self.faces.iter_mut().filter(|f| f.normals.is_none()) {
+            face.normals = Some([offset + face.v0, offset + face.v1, offset + face.v2]);
+        }
+        self
+    }
+
+    fn triangle(&self, face: &Face) -> Triangle {
+        Triangle {
+            v0: self.vertexes[face.v0],
+            v1: self.vertexes[face.v1],
+            v2: self.vertexes[face.v2],
+        }
+    }
+
+    fn intersect_face(&self, face: &Face, ray: &Ray, intersection: &mut Intersection) -> bool {
+        if !triangle_intesected_with_ray(&self.triangle(face), ray, intersection) {
+            return false;
+        }
+
+        // Barycentric coordinates of the hit
+        let (u, v) = (intersection.uv.x, intersection.uv.y);
+        let weights = [1.0 - u - v, u, v];
+        if let Some(normals) = face.normals {
+            let normal = normals
+                .iter()
+                .zip(&weights)
+                .fold(Vector3::zero(), |sum, (i, w)| sum + self.normals[*i] * *w);
+            // Degenerate vertex normals keep the face normal
+            if normal.length() > 0.0 {
+                let normal = normal.normalized();
+                // On the side of the face normal, which tells the inside of a surface
+                intersection.normal = if normal.dot(&intersection.normal) < 0.0 {
+                    -normal
+                } else {
+                    normal
+                };
+            }
+        }
+        if let Some(uvs) = face.uvs {
+            intersection.uv = uvs
+                .iter()
+                .zip(&weights)
+                .fold(Vector2::zero(), |sum, (i, w)| sum + self.uvs[*i] * *w);
+            let bump = &self.material_of(face.material).bump;
+            if bump.image_texture.is_some() {
+                intersection.normal = self.bumped_normal(face, uvs, bump, intersection);
+            }
+        }
+        intersection.material_index = face.material;
+        intersection.vertex_color = if self.colors.is_empty() {
+            Color::one()
+        } else {
+            [face.v0, face.v1, face.v2]
+                .iter()
+                .zip(&weights)
+                .fold(Color::zero(), |sum, (i, w)| sum + self.colors[*i] * *w)
+        };
+        true
+    }
+
+    fn material_of(&self, index: Option<usize>) -> &Material {
+        index.map_or(&self.material, |i| &self.materials[i])
+    }
+
+    // Shading normal of the surface displaced by the bump heights, with the changes of the
+    // position along u and v given by the texture coordinates of the face
+    fn bumped_normal(
+        &self,
+        face: &Face,
+        uvs: [usize; 3],
+        bump: &Texture,
+        intersection: &Intersection,
+    ) -> Vector3 {
+        let normal = intersection.normal;
+        let p = self.triangle(face);
+        let t = uvs.map(|i| self.uvs[i]);
+        let (e1, e2) = (p.v1 - p.v0, p.v2 - p.v0);
+        let (d1, d2) = (t[1] - t[0], t[2] - t[0]);
+        let det = d1.x * d2.y - d2.x * d1.y;
+        let image = match bump.image_texture {
+            Some(ref texture) if det.abs() > config::EPS => &texture.image,
+            _ => return normal,
+        };
+        let dpdu = (e1 * d2.y - e2 * d1.y) / det;
+        let dpdv = (e2 * d1.x - e1 * d2.x) / det;
+
+        // Finite differences over a texel of gray heights
+        let du = (image.width() as f64).recip();
+        let dv = (image.height() as f64).recip();
+        let uv = intersection.uv;
+        let height = bump.sample_linear(uv).x;
+        let dhdu = (bump.sample_linear(uv + Vector2::new(du, 0.0)).x - height) / du;
+        let dhdv = (bump.sample_linear(uv + Vector2::new(0.0, dv)).x - height) / dv;
+
+        let bumped = (dpdu + normal * dhdu).cross(&(dpdv + normal * dhdv));
+        if bumped.length() == 0.0 {
+            return normal;
+        }
+        let bumped = bumped.normalized();
+        if bumped.dot(&normal) < 0.0 {
+            -bumped
+        } else {
+            bumped
+        }
+    }
+}
+
+// Triangles of a simple polygon by ear clipping, as indexes of points in the same winding.
+// Falls back to a fan if the polygon is degenerate
+pub fn triangulate_polygon(points: &[Vector3]) -> Vec<[usize; 3]> {
+    let fan = |indexes: &[usize]| -> Vec<[usize; 3]> {
+        (1..indexes.len().saturating_sub(1))
+            .map(|i| [indexes[0], indexes[i], indexes[i + 1]])
+            .collect()
+    };
+    let mut remaining: Vec<usize> = (0..points.len()).collect();
+    if points.len() <= 3 {
+        return fan(&remaining);
+    }
+
+    // Newell's normal, which is robust for non planar polygons
+    let normal = (0..points.len()).fold(Vector3::zero(), |sum, i| {
+        sum + points[i].cross(&points[(i + 1) % points.len()])
+    });
+    if normal.length() == 0.0 {
+        return fan(&remaining);
+    }
+    // Whether c is on the left of a to b seen from the side of the normal
+    let left_of = |a: usize, b: usize, c: usize| {
+        (points[b] - points[a])
+            .cross(&(points[c] - points[a]))
+            .dot(&normal)
+    };
+
+    let mut triangles = vec![];
+    while remaining.len() > 3 {
+        let n = remaining.len();
+        // From the second point, so that convex polygons end up as the fan from the first
+        let ear = (1..=n).map(|i| i % n).find(|&i| {
+            let (a, b, c) = (
+                remaining[(i + n - 1) % n],
+                remaining[i],
+                remaining[(i + 1) % n],
+            );
+            left_of(a, b, c) > 0.0
+                && remaining.iter().all(|&p| {
+                    p == a
+                        || p == b
+                        || p == c
+                        || left_of(a, b, p) < 0.0
+                        || left_of(b, c, p) < 0.0
+                        || left_of(c, a, p) < 0.0
+                })
+        });
+        match ear {
+            Some(i) => {
+                triangles.push([
+                    remaining[(i + n - 1) % n],
+                    remaining[i],
+                    remaining[(i + 1) % n],
+                ]);
+                remaining.remove(i);
+            }
+            None => break,
+        }
+    }
+    triangles.extend(fan(&remaining));
+    triangles
 }
 
 impl Intersectable for Mesh {
+    fn aabb(&self) -> Aabb {
+        self.vertexes.iter().fold(Aabb::empty(), |aabb, v| {
+            aabb.merged(&Aabb { min: *v, max: *v })
+        })
+    }
+
     fn intersect(&self, ray: &Ray, intersection: &mut Intersection) -> bool {
         for face in &self.faces {
-            if triangle_intesected_with_ray(
-                &Triangle {
-                    v0: self.vertexes[face.v0],
-                    v1: self.vertexes[face.v1],
-                    v2: self.vertexes[face.v2],
-                },
-                ray,
-                intersection,
-            ) {
+            if self.intersect_face(face, ray, intersection) {
                 return true;
             }
         }
@@ -53,12 +268,16 @@ impl Intersectable for Mesh {
         &self.material
     }
 
-    fn nee_available(&self) -> bool {
-        false
+    fn material_at(&self, intersection: &Intersection) -> &Material {
+        self.material_of(intersection.material_index)
     }
 
-    fn sample_on_surface(&self, _: (f64, f64)) -> Surface {
-        unimplemented!()
+    fn vertex_color(&self, intersection: &Intersection) -> Color {
+        intersection.vertex_color
+    }
+
+    fn nee_available(&self) -> bool {
+        false
     }
 }
 
@@ -73,74 +292,58 @@ pub struct BvhNode {
     pub indexes: Vec<usize>,
 }
 
-fn node_from_mesh_with_indexes(mesh: &Mesh, face_indexes: &mut Vec<usize>) -> BvhNode {
+// Splits the items at the median of the centers of their bounds along the longest axis, for
+// the faces of meshes and the elements of scenes
+pub(crate) fn node_from_aabbs(aabbs: &[Aabb], indexes: &mut Vec<usize>) -> BvhNode {
     let mut node = BvhNode::empty();
-    node.set_aabb_from_mesh(mesh, face_indexes);
+    for i in indexes.iter() {
+        node.aabb = node.aabb.merged(&aabbs[*i]);
+    }
 
-    let mid = face_indexes.len() / 2;
+    let mid = indexes.len() / 2;
     if mid <= 2 {
         // set leaf node
-        node.indexes = face_indexes.clone();
-    } else {
-        // set intermediate node
-        let lx = node.aabb.max.x - node.aabb.min.x;
-        let ly = node.aabb.max.y - node.aabb.min.y;
-        let lz = node.aabb.max.z - node.aabb.min.z;
-
-        if lx > ly && lx > lz {
-            face_indexes.sort_by(|a, b| {
-                let a_face = &mesh.faces[*a];
-                let b_face = &mesh.faces[*b];
-                let a_sum = mesh.vertexes[a_face.v0].x
-                    + mesh.vertexes[a_face.v1].x
-                    + mesh.vertexes[a_face.v2].x;
-                let b_sum = mesh.vertexes[b_face.v0].x
-                    + mesh.vertexes[b_face.v1].x
-                    + mesh.vertexes[b_face.v2].x;
-                a_sum.partial_cmp(&b_sum).unwrap()
-            });
-        } else if ly > lx && ly > lz {
-            face_indexes.sort_by(|a, b| {
-                let a_face = &mesh.faces[*a];
-                let b_face = &mesh.faces[*b];
-                let a_sum = mesh.vertexes[a_face.v0].y
-                    + mesh.vertexes[a_face.v1].y
-                    + mesh.vertexes[a_face.v2].y;
-                let b_sum = mesh.vertexes[b_face.v0].y
-                    + mesh.vertexes[b_face.v1].y
-                    + mesh.vertexes[b_face.v2].y;
-                a_sum.partial_cmp(&b_sum).unwrap()
-            });
+        node.indexes = indexes.clone();
+        return node;
+    }
+
+    // set intermediate node
+    let size = node.aabb.max - node.aabb.min;
+    let center = |i: usize| {
+        let sum = aabbs[i].min + aabbs[i].max;
+        if size.x > size.y && size.x > size.z {
+            sum.x
+        } else if size.y > size.z {
+            sum.y
         } else {
-            face_indexes.sort_by(|a, b| {
-                let a_face = &mesh.faces[*a];
-                let b_face = &mesh.faces[*b];
-                let a_sum = mesh.vertexes[a_face.v0].z
-                    + mesh.vertexes[a_face.v1].z
-                    + mesh.vertexes[a_face.v2].z;
-                let b_sum = mesh.vertexes[b_face.v0].z
-                    + mesh.vertexes[b_face.v1].z
-                    + mesh.vertexes[b_face.v2].z;
-                a_sum.partial_cmp(&b_sum).unwrap()
-            });
+            sum.z
         }
+    };
+    indexes.sort_by(|a, b| center(*a).partial_cmp(&center(*b)).unwrap());
 
-        let mut left_face_indexes = face_indexes.split_off(mid);
-        node.children
-            .push(Box::new(node_from_mesh_with_indexes(mesh, face_indexes)));
-        node.children.push(Box::new(node_from_mesh_with_indexes(
-            mesh,
-            &mut left_face_indexes,
-        )));
-    }
-
+    let mut upper_indexes = indexes.split_off(mid);
+    node.children
+        .push(Box::new(node_from_aabbs(aabbs, indexes)));
+    node.children
+        .push(Box::new(node_from_aabbs(aabbs, &mut upper_indexes)));
     node
 }
 
 impl From<&Mesh> for BvhNode {
     fn from(mesh: &Mesh) -> Self {
+        let aabbs: Vec<Aabb> = mesh
+            .faces
+            .iter()
+            .map(|face| {
+                Aabb::from(Triangle {
+                    v0: mesh.vertexes[face.v0],
+                    v1: mesh.vertexes[face.v1],
+                    v2: mesh.vertexes[face.v2],
+                })
+            })
+            .collect();
         let mut face_indexes: Vec<usize> = (0..mesh.faces.len()).collect();
-        node_from_mesh_with_indexes(mesh, &mut face_indexes)
+        node_from_aabbs(&aabbs, &mut face_indexes)
     }
 }
 
@@ -154,16 +357,7 @@ impl IntersectWith<Mesh> for BvhNode {
         if self.children.is_empty() {
             // leaf node
             for face_index in &self.indexes {
-                let face = &mesh.faces[*face_index];
-                if triangle_intesected_with_ray(
-                    &Triangle {
-                        v0: mesh.vertexes[face.v0],
-                        v1: mesh.vertexes[face.v1],
-                        v2: mesh.vertexes[face.v2],
-                    },
-                    ray,
-                    intersection,
-                ) {
+                if mesh.intersect_face(&mesh.faces[*face_index], ray, intersection) {
                     any_hit = true;
                 }
             }
@@ -183,28 +377,11 @@ impl IntersectWith<Mesh> for BvhNode {
 impl BvhNode {
     pub fn empty() -> BvhNode {
         BvhNode {
-            aabb: Aabb {
-                min: Vector3::new(INF, INF, INF),
-                max: Vector3::new(-INF, -INF, -INF),
-            },
+            aabb: Aabb::empty(),
             children: vec![],
             indexes: vec![],
         }
     }
-
-    fn set_aabb_from_mesh(&mut self, mesh: &Mesh, face_indexes: &Vec<usize>) {
-        for face_index in face_indexes {
-            let face = &mesh.faces[*face_index];
-            let v0 = &mesh.vertexes[face.v0];
-            let v1 = &mesh.vertexes[face.v1];
-            let v2 = &mesh.vertexes[face.v2];
-            self.aabb = self.aabb.merged(&Aabb::from(Triangle {
-                v0: *v0,
-                v1: *v1,
-                v2: *v2,
-            }));
-        }
-    }
 }
 
 pub struct BvhMesh {
@@ -220,18 +397,24 @@ impl From<Mesh> for BvhMesh {
 }
 
 impl Intersectable for BvhMesh {
+    fn aabb(&self) -> Aabb {
+        self.bvh.aabb
+    }
     fn intersect(&self, ray: &Ray, intersection: &mut Intersection) -> bool {
         self.bvh.intersect_with(&self.mesh, ray, intersection)
     }
     fn material(&self) -> &Material {
         &self.mesh.material
     }
+    fn material_at(&self, intersection: &Intersection) -> &Material {
+        self.mesh.material_at(intersection)
+    }
+    fn vertex_color(&self, intersection: &Intersection) -> Color {
+        self.mesh.vertex_color(intersection)
+    }
     fn nee_available(&self) -> bool {
         false
     }
-    fn sample_on_surface(&self, _: (f64, f64)) -> Surface {
-        unimplemented!()
-    }
 }
 
 fn triangle_intesected_with_ray(
@@ -0,0 +1,155 @@
+use crate::aabb::Aabb;
+use crate::camera::Ray;
+use crate::color::Color;
+use crate::material::Material;
+use crate::math::mix;
+use crate::matrix::Matrix44;
+use crate::quaternion::Quaternion;
+use crate::rayintersectable::{Intersectable, Intersection};
+use crate::transform::intersect_transformed;
+use crate::vector::Vector3;
+
+// Samples per keyframe interval to bound the swept volume
+const SWEEP_STEPS: usize = 32;
+
+// Scale, then rotation, then translation
+#[derive(Clone, Copy, Debug)]
+pub struct TransformKeyframe {
+    pub time: f64,
+    pub translation: Vector3,
+    pub rotation: Quaternion,
+    pub scale: Vector3,
+}
+
+impl TransformKeyframe {
+    pub fn matrix(&self) -> Matrix44 {
+        let t = self.translation;
+        let s = self.scale;
+        Matrix44::translate(t.x, t.y, t.z)
+            * self.rotation.to_matrix()
+            * Matrix44::scale(s.x, s.y, s.z)
+    }
+
+    pub fn inverse_matrix(&self) -> Matrix44 {
+        let t = self.translation;
+        let s = self.scale;
+        Matrix44::scale(s.x.recip(), s.y.recip(), s.z.recip())
+            * self.rotation.conjugate().to_matrix()
+            * Matrix44::translate(-t.x, -t.y, -t.z)
+    }
+
+    // Rotation is interpolated on the sphere so that spinning objects keep their shape
+    pub fn interpolated(&self, other: &TransformKeyframe, t: f64) -> TransformKeyframe {
+        TransformKeyframe {
+            time: self.time + (other.time - self.time) * t,
+            translation: mix(&self.translation, &other.translation, t),
+            rotation: self.rotation.slerp(&other.rotation, t),
+            scale: mix(&self.scale, &other.scale, t),
+        }
+    }
+}
+
+// Element moving by keyframed transforms, interpolated by the time of rays
+pub struct Moving {
+    pub element: Box<dyn Intersectable>,
+    keyframes: Vec<TransformKeyframe>,
+    // Bounds of the element over all the keyframes
+    aabb: Aabb,
+}
+
+impl Moving {
+    pub fn new(element: Box<dyn Intersectable>, mut keyframes: Vec<TransformKeyframe>) -> Moving {
+        assert!(!keyframes.is_empty(), "Moving element needs a keyframe");
+        keyframes.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+
+        let local = element.aabb();
+        let mut aabb = local.transformed(&keyframes[0].matrix());
+        for pair in keyframes.windows(2) {
+            // Corners move along arcs between samples, pad by the longest chord
+            let mut previous = local.corners();
+            let mut step_aabb = Aabb::empty();
+            let mut margin: f64 = 0.0;
+            for step in 0..=SWEEP_STEPS {
+                let matrix = pair[0]
+                    .interpolated(&pair[1], step as f64 / SWEEP_STEPS as f64)
+                    .matrix();
+                let corners = local.corners();
+                for (i, corner) in corners.iter().enumerate() {
+                    let p = matrix * *corner;
+                    if step > 0 {
+                        margin = margin.max((p - previous[i]).length());
+                    }
+                    previous[i] = p;
+                    step_aabb = step_aabb.merged(&Aabb { min: p, max: p });
+                }
+            }
+            aabb = aabb.merged(&step_aabb.expanded(margin));
+        }
+
+        Moving {
+            element,
+            keyframes,
+            aabb,
+        }
+    }
+
+    // Clamped to the first and last keyframes
+    pub fn keyframe_at(&self, time: f64) -> TransformKeyframe {
+        let first = &self.keyframes[0];
+        let last = &self.keyframes[self.keyframes.len() - 1];
+        if time <= first.time {
+            return *first;
+        }
+        if time >= last.time {
+            return *last;
+        }
+
+        let next = self.keyframes.partition_point(|k| k.time <= time);
+        let (a, b) = (&self.keyframes[next - 1], &self.keyframes[next]);
+        a.interpolated(b, (time - a.time) / (b.time - a.time))
+    }
+}
+
+impl Intersectable for Moving {
+    fn aabb(&self) -> Aabb {
+        self.aabb
+    }
+
+    fn intersect(&self, ray: &Ray, intersection: &mut Intersection) -> bool {
+        if !self.aabb.intersect_with_ray(ray).0 {
+            return false;
+        }
+
+        let keyframe = self.keyframe_at(ray.time);
+        let inverse = keyframe.inverse_matrix();
+        intersect_transformed(
+            self.element.as_ref(),
+            &keyframe.matrix(),
+            &inverse,
+            &inverse.transposed(),
+            ray,
+            intersection,
+        )
+    }
+
+    fn material(&self) -> &Material {
+        self.element.material()
+    }
+
+    fn material_at(&self, intersection: &Intersection) -> &Material {
+        self.element.material_at(intersection)
+    }
+
+    fn vertex_color(&self, intersection: &Intersection) -> Color {
+        self.element.vertex_color(intersection)
+    }
+
+    fn visible_to_camera(&self) -> bool {
+        self.element.visible_to_camera()
+    }
+
+    // Light samples have no time to move with
+    fn nee_available(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,101 @@
+use crate::matrix::Matrix44;
+use crate::vector::Vector3;
+
+// Unit quaternion for rotations
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quaternion {
+    pub w: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Quaternion {
+    pub fn identity() -> Quaternion {
+        Quaternion {
+            w: 1.0,
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        }
+    }
+
+    // Counterclockwise around the axis in radians, as Matrix44::rotate_*
+    pub fn from_axis_angle(axis: &Vector3, angle: f64) -> Quaternion {
+        let axis = axis.normalized();
+        let sin = (angle * 0.5).sin();
+        Quaternion {
+            w: (angle * 0.5).cos(),
+            x: axis.x * sin,
+            y: axis.y * sin,
+            z: axis.z * sin,
+        }
+    }
+
+    // Inverse rotation
+    pub fn conjugate(&self) -> Quaternion {
+        Quaternion {
+            w: self.w,
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+
+    pub fn dot(&self, other: &Quaternion) -> f64 {
+        self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn normalized(&self) -> Quaternion {
+        let inv_len = self.dot(self).sqrt().recip();
+        Quaternion {
+            w: self.w * inv_len,
+            x: self.x * inv_len,
+            y: self.y * inv_len,
+            z: self.z * inv_len,
+        }
+    }
+
+    // Spherical linear interpolation along the shorter arc
+    pub fn slerp(&self, other: &Quaternion, t: f64) -> Quaternion {
+        let mut cos = self.dot(other);
+        let mut other = *other;
+        if cos < 0.0 {
+            cos = -cos;
+            other = Quaternion {
+                w: -other.w,
+                x: -other.x,
+                y: -other.y,
+                z: -other.z,
+            };
+        }
+
+        let (a, b) = if cos > 0.9995 {
+            // Nearly parallel, lerp to avoid dividing by sin of tiny angle
+            (1.0 - t, t)
+        } else {
+            let theta = cos.acos();
+            let sin = theta.sin();
+            (((1.0 - t) * theta).sin() / sin, (t * theta).sin() / sin)
+        };
+        Quaternion {
+            w: a * self.w + b * other.w,
+            x: a * self.x + b * other.x,
+            y: a * self.y + b * other.y,
+            z: a * self.z + b * other.z,
+        }
+        .normalized()
+    }
+
+    #[rustfmt::skip]
+    pub fn to_matrix(&self) -> Matrix44 {
+        let Quaternion { w, x, y, z } = *self;
+        Matrix44 {
+            elements: [
+                [1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y - w * z),       2.0 * (x * z + w * y),       0.0],
+                [2.0 * (x * y + w * z),       1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z - w * x),       0.0],
+                [2.0 * (x * z - w * y),       2.0 * (y * z + w * x),       1.0 - 2.0 * (x * x + y * y), 0.0],
+                [0.0,                         0.0,                         0.0,                         1.0]]
+        }
+    }
+}
@@ -3,7 +3,8 @@ use crate::camera::Ray;
 use crate::color::Color;
 use crate::config;
 use crate::material::{Material, PointMaterial, SurfaceType};
-use crate::math::equals_eps;
+use crate::material_utils::get_tangent_space_basis_gram_schmidtd;
+use crate::math::{equals_eps, modulo, polynomial_roots};
 use crate::scene::Surface;
 use crate::vector::{Vector2, Vector3};
 
@@ -13,6 +14,10 @@ pub struct Intersection {
     pub distance: f64,
     pub normal: Vector3,
     pub uv: Vector2,
+    // Material of the element hit for elements with several, None for its main one
+    pub material_index: Option<usize>,
+    // Interpolated vertex color of the element hit for elements with them
+    pub vertex_color: Color,
     pub material: PointMaterial,
 }
 
@@ -23,6 +28,8 @@ impl Intersection {
             distance: config::INF,
             normal: Vector3::zero(),
             uv: Vector2::zero(),
+            material_index: None,
+            vertex_color: Color::one(),
             material: PointMaterial {
                 surface: SurfaceType::Diffuse,
                 albedo: Color::one(),
@@ -34,11 +41,39 @@ impl Intersection {
 }
 
 pub trait Intersectable: Sync {
+    // Bounds of everything the element can intersect
+    fn aabb(&self) -> Aabb;
     fn intersect(&self, ray: &Ray, intersection: &mut Intersection) -> bool;
     fn material(&self) -> &Material;
+    // Material at an intersection with the element
+    fn material_at(&self, _intersection: &Intersection) -> &Material {
+        self.material()
+    }
+    // Color the albedo is tinted by at an intersection with the element
+    fn vertex_color(&self, _intersection: &Intersection) -> Color {
+        Color::one()
+    }
+
+    // Share of the emission at an intersection with the element leaving toward direction
+    fn emission_toward(&self, _intersection: &Intersection, _direction: &Vector3) -> f64 {
+        1.0
+    }
+    // Whether rays from the camera hit the element, which still lights the scene otherwise
+    fn visible_to_camera(&self) -> bool {
+        true
+    }
 
     fn nee_available(&self) -> bool;
-    fn sample_on_surface(&self, random: (f64, f64)) -> Surface;
+    // Uniform point on the surface, None for elements that can't be sampled such as unbounded
+    // or moving ones
+    fn sample_on_surface(&self, _random: (f64, f64)) -> Option<Surface> {
+        None
+    }
+    // Sample for lighting position, which elements may pick by the solid angle they cover
+    // there. Densities are still over the area of the element
+    fn sample_from(&self, _position: &Vector3, random: (f64, f64)) -> Option<Surface> {
+        self.sample_on_surface(random)
+    }
 }
 
 pub struct Sphere {
@@ -48,6 +83,13 @@ pub struct Sphere {
 }
 
 impl Intersectable for Sphere {
+    fn aabb(&self) -> Aabb {
+        Aabb {
+            min: self.center - Vector3::all_of(self.radius),
+            max: self.center + Vector3::all_of(self.radius),
+        }
+    }
+
     fn intersect(&self, ray: &Ray, intersection: &mut Intersection) -> bool {
         let a: Vector3 = ray.origin - self.center;
         let b = a.dot(&ray.direction);
@@ -79,7 +121,7 @@ impl Intersectable for Sphere {
     }
 
     // http://apollon.issp.u-tokyo.ac.jp/~watanabe/pdf/prob.pdf
-    fn sample_on_surface(&self, random: (f64, f64)) -> Surface {
+    fn sample_on_surface(&self, random: (f64, f64)) -> Option<Surface> {
         let theta = config::PI2 * random.0;
         let unit_z = 1.0 - 2.0 * random.1;
         let a = (1.0 - unit_z * unit_z).sqrt();
@@ -87,11 +129,11 @@ impl Intersectable for Sphere {
         let normal = Vector3::new(a * theta.cos(), a * theta.sin(), unit_z);
         let position = self.center + (self.radius + config::OFFSET) * normal;
         let pdf = (4.0 * config::PI * self.radius * self.radius).recip();
-        Surface {
+        Some(Surface {
             position,
             normal,
             pdf,
-        }
+        })
     }
 }
 
@@ -101,6 +143,10 @@ pub struct Cuboid {
 }
 
 impl Intersectable for Cuboid {
+    fn aabb(&self) -> Aabb {
+        self.aabb
+    }
+
     fn intersect(&self, ray: &Ray, intersection: &mut Intersection) -> bool {
         let (hit, distance) = self.aabb.intersect_with_ray(ray);
         if hit && distance < intersection.distance {
@@ -141,8 +187,452 @@ impl Intersectable for Cuboid {
     fn nee_available(&self) -> bool {
         false
     }
+}
+
+// Normal of one-sided surfaces such as planes and open tubes, turned toward the ray so that
+// both sides are lit and shaded alike
+fn facing(normal: Vector3, ray: &Ray) -> Vector3 {
+    if normal.dot(&ray.direction) > 0.0 {
+        -normal
+    } else {
+        normal
+    }
+}
+
+// Angle around the y axis, from 0 to 1
+fn azimuth(x: f64, z: f64) -> f64 {
+    z.atan2(x) / config::PI2 + 0.5
+}
+
+// Infinite plane through point, with textures repeating every scene unit along it
+pub struct Plane {
+    pub point: Vector3,
+    pub normal: Vector3,
+    pub material: Material,
+}
+
+impl Intersectable for Plane {
+    fn aabb(&self) -> Aabb {
+        Aabb {
+            min: Vector3::all_of(-config::INF),
+            max: Vector3::all_of(config::INF),
+        }
+    }
+
+    fn intersect(&self, ray: &Ray, intersection: &mut Intersection) -> bool {
+        let normal = self.normal.normalized();
+        let t = (self.point - ray.origin).dot(&normal) / normal.dot(&ray.direction);
+        if !(0.0 < t && t < intersection.distance) {
+            return false;
+        }
+
+        intersection.position = ray.origin + ray.direction * t;
+        intersection.distance = t;
+        intersection.normal = facing(normal, ray);
+        let (tangent, binormal) = get_tangent_space_basis_gram_schmidtd(&normal);
+        let p = intersection.position - self.point;
+        intersection.uv = Vector2::new(modulo(p.dot(&tangent), 1.0), modulo(p.dot(&binormal), 1.0));
+        true
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn nee_available(&self) -> bool {
+        false
+    }
+}
+
+// Disk facing normal, with u around the center and v from the center out to the rim
+pub struct Disk {
+    pub center: Vector3,
+    pub normal: Vector3,
+    pub radius: f64,
+    pub material: Material,
+}
+
+impl Intersectable for Disk {
+    fn aabb(&self) -> Aabb {
+        // Extent along each axis of a circle perpendicular to the normal
+        let n = self.normal.normalized();
+        let extent = Vector3::new(
+            (1.0 - n.x * n.x).max(0.0).sqrt(),
+            (1.0 - n.y * n.y).max(0.0).sqrt(),
+            (1.0 - n.z * n.z).max(0.0).sqrt(),
+        ) * self.radius;
+        Aabb {
+            min: self.center - extent,
+            max: self.center + extent,
+        }
+    }
+
+    fn intersect(&self, ray: &Ray, intersection: &mut Intersection) -> bool {
+        let normal = self.normal.normalized();
+        let t = (self.center - ray.origin).dot(&normal) / normal.dot(&ray.direction);
+        if !(0.0 < t && t < intersection.distance) {
+            return false;
+        }
+        let position = ray.origin + ray.direction * t;
+        let p = position - self.center;
+        let r = p.length();
+        if r > self.radius {
+            return false;
+        }
+
+        intersection.position = position;
+        intersection.distance = t;
+        intersection.normal = facing(normal, ray);
+        let (tangent, binormal) = get_tangent_space_basis_gram_schmidtd(&normal);
+        intersection.uv = Vector2::new(azimuth(p.dot(&tangent), p.dot(&binormal)), r / self.radius);
+        true
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn nee_available(&self) -> bool {
+        true
+    }
+
+    fn sample_on_surface(&self, random: (f64, f64)) -> Option<Surface> {
+        let normal = self.normal.normalized();
+        let (tangent, binormal) = get_tangent_space_basis_gram_schmidtd(&normal);
+        let r = self.radius * random.0.sqrt();
+        let theta = config::PI2 * random.1;
+        Some(Surface {
+            position: self.center + (tangent * theta.cos() + binormal * theta.sin()) * r,
+            normal,
+            pdf: (config::PI * self.radius * self.radius).recip(),
+        })
+    }
+}
+
+// Parallelogram from corner along both edges, usually perpendicular, facing
+// edge_u x edge_v. The edges are the directions of u and v
+pub struct Rectangle {
+    pub corner: Vector3,
+    pub edge_u: Vector3,
+    pub edge_v: Vector3,
+    pub material: Material,
+}
+
+impl Intersectable for Rectangle {
+    fn aabb(&self) -> Aabb {
+        [self.edge_u, self.edge_v, self.edge_u + self.edge_v]
+            .iter()
+            .fold(
+                Aabb {
+                    min: self.corner,
+                    max: self.corner,
+                },
+                |aabb, edge| {
+                    let p = self.corner + *edge;
+                    aabb.merged(&Aabb { min: p, max: p })
+                },
+            )
+    }
 
-    fn sample_on_surface(&self, _: (f64, f64)) -> Surface {
-        unimplemented!()
+    fn intersect(&self, ray: &Ray, intersection: &mut Intersection) -> bool {
+        let cross = self.edge_u.cross(&self.edge_v);
+        let t = (self.corner - ray.origin).dot(&cross) / cross.dot(&ray.direction);
+        if !(0.0 < t && t < intersection.distance) {
+            return false;
+        }
+        let position = ray.origin + ray.direction * t;
+        let p = position - self.corner;
+        let area2 = cross.dot(&cross);
+        let u = p.cross(&self.edge_v).dot(&cross) / area2;
+        let v = self.edge_u.cross(&p).dot(&cross) / area2;
+        if !((0.0..=1.0).contains(&u) && (0.0..=1.0).contains(&v)) {
+            return false;
+        }
+
+        intersection.position = position;
+        intersection.distance = t;
+        intersection.normal = facing(cross.normalized(), ray);
+        intersection.uv = Vector2::new(u, v);
+        true
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn nee_available(&self) -> bool {
+        true
+    }
+
+    fn sample_on_surface(&self, random: (f64, f64)) -> Option<Surface> {
+        let cross = self.edge_u.cross(&self.edge_v);
+        Some(Surface {
+            position: self.corner + self.edge_u * random.0 + self.edge_v * random.1,
+            normal: cross.normalized(),
+            pdf: cross.length().recip(),
+        })
+    }
+}
+
+// Open tube along the y axis from center up to height, with u around the axis and v along it
+pub struct Cylinder {
+    pub center: Vector3,
+    pub radius: f64,
+    pub height: f64,
+    pub material: Material,
+}
+
+impl Intersectable for Cylinder {
+    fn aabb(&self) -> Aabb {
+        Aabb {
+            min: self.center - Vector3::new(self.radius, 0.0, self.radius),
+            max: self.center + Vector3::new(self.radius, self.height, self.radius),
+        }
+    }
+
+    fn intersect(&self, ray: &Ray, intersection: &mut Intersection) -> bool {
+        let o = ray.origin - self.center;
+        let d = ray.direction;
+        let a = d.x * d.x + d.z * d.z;
+        let b = o.x * d.x + o.z * d.z;
+        let c = o.x * o.x + o.z * o.z - self.radius * self.radius;
+        let discriminant = b * b - a * c;
+        if a == 0.0 || discriminant < 0.0 {
+            return false;
+        }
+
+        // Nearer side first, then the inside of the far one
+        for &t in &[
+            (-b - discriminant.sqrt()) / a,
+            (-b + discriminant.sqrt()) / a,
+        ] {
+            let p = o + d * t;
+            if 0.0 < t && t < intersection.distance && 0.0 <= p.y && p.y <= self.height {
+                intersection.position = ray.origin + d * t;
+                intersection.distance = t;
+                intersection.normal = facing(Vector3::new(p.x, 0.0, p.z).normalized(), ray);
+                intersection.uv = Vector2::new(azimuth(p.x, p.z), p.y / self.height);
+                return true;
+            }
+        }
+        false
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn nee_available(&self) -> bool {
+        true
+    }
+
+    fn sample_on_surface(&self, random: (f64, f64)) -> Option<Surface> {
+        let theta = config::PI2 * random.0;
+        let normal = Vector3::new(theta.cos(), 0.0, theta.sin());
+        Some(Surface {
+            position: self.center
+                + normal * self.radius
+                + Vector3::new(0.0, self.height * random.1, 0.0),
+            normal,
+            pdf: (config::PI2 * self.radius * self.height).recip(),
+        })
+    }
+}
+
+// Open lateral surface narrowing from a base of radius around center to an apex height above
+// it, with u around the y axis and v up to the apex
+pub struct Cone {
+    pub center: Vector3,
+    pub radius: f64,
+    pub height: f64,
+    pub material: Material,
+}
+
+impl Cone {
+    // Outward normal at a point relative to the center, the axis at the apex where the
+    // surface has none
+    fn normal_at(&self, p: &Vector3) -> Vector3 {
+        let slope = self.radius / self.height;
+        let normal = Vector3::new(p.x, slope * slope * (self.height - p.y), p.z);
+        if normal == Vector3::zero() {
+            Vector3::new(0.0, 1.0, 0.0)
+        } else {
+            normal.normalized()
+        }
+    }
+}
+
+impl Intersectable for Cone {
+    fn aabb(&self) -> Aabb {
+        Aabb {
+            min: self.center - Vector3::new(self.radius, 0.0, self.radius),
+            max: self.center + Vector3::new(self.radius, self.height, self.radius),
+        }
+    }
+
+    fn intersect(&self, ray: &Ray, intersection: &mut Intersection) -> bool {
+        // x^2 + z^2 = (slope * w)^2 with w the height below the apex
+        let slope2 = (self.radius / self.height).powi(2);
+        let o = ray.origin - self.center;
+        let d = ray.direction;
+        let w = self.height - o.y;
+        let a = d.x * d.x + d.z * d.z - slope2 * d.y * d.y;
+        let b = o.x * d.x + o.z * d.z + slope2 * w * d.y;
+        let c = o.x * o.x + o.z * o.z - slope2 * w * w;
+        let ts = if a.abs() < 1e-12 {
+            // Parallel to the slope, crossing the cone once
+            if b == 0.0 {
+                return false;
+            }
+            vec![-c / (2.0 * b)]
+        } else {
+            let discriminant = b * b - a * c;
+            if discriminant < 0.0 {
+                return false;
+            }
+            let (t0, t1) = (
+                (-b - discriminant.sqrt()) / a,
+                (-b + discriminant.sqrt()) / a,
+            );
+            vec![t0.min(t1), t0.max(t1)]
+        };
+
+        for t in ts {
+            let p = o + d * t;
+            if 0.0 < t && t < intersection.distance && 0.0 <= p.y && p.y <= self.height {
+                intersection.position = ray.origin + d * t;
+                intersection.distance = t;
+                intersection.normal = facing(self.normal_at(&p), ray);
+                intersection.uv = Vector2::new(azimuth(p.x, p.z), p.y / self.height);
+                return true;
+            }
+        }
+        false
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn nee_available(&self) -> bool {
+        true
+    }
+
+    // Areas grow linearly with the distance from the apex
+    fn sample_on_surface(&self, random: (f64, f64)) -> Option<Surface> {
+        let s = random.0.sqrt();
+        let theta = config::PI2 * random.1;
+        let p = Vector3::new(
+            self.radius * s * theta.cos(),
+            self.height * (1.0 - s),
+            self.radius * s * theta.sin(),
+        );
+        let slant = (self.radius * self.radius + self.height * self.height).sqrt();
+        Some(Surface {
+            position: self.center + p,
+            normal: self.normal_at(&p),
+            pdf: (config::PI * self.radius * slant).recip(),
+        })
+    }
+}
+
+// Ring around the y axis through center, the tube of minor_radius sweeping a circle of
+// major_radius. u runs around the y axis and v around the tube from its outer rim
+pub struct Torus {
+    pub center: Vector3,
+    pub major_radius: f64,
+    pub minor_radius: f64,
+    pub material: Material,
+}
+
+impl Intersectable for Torus {
+    fn aabb(&self) -> Aabb {
+        let outer = self.major_radius + self.minor_radius;
+        let extent = Vector3::new(outer, self.minor_radius, outer);
+        Aabb {
+            min: self.center - extent,
+            max: self.center + extent,
+        }
+    }
+
+    fn intersect(&self, ray: &Ray, intersection: &mut Intersection) -> bool {
+        let (major2, minor2) = (self.major_radius.powi(2), self.minor_radius.powi(2));
+        // Roots are searched from where the ray enters the bounding sphere, measured from
+        // there to keep the coefficients small
+        let o = ray.origin - self.center;
+        let d = ray.direction;
+        let b = o.dot(&d);
+        let c = o.dot(&o) - (self.major_radius + self.minor_radius).powi(2);
+        let discriminant = b * b - c;
+        if discriminant < 0.0 {
+            return false;
+        }
+        let start = (-b - discriminant.sqrt()).max(0.0);
+        let end = (-b + discriminant.sqrt()).min(intersection.distance);
+        if start >= end {
+            return false;
+        }
+
+        // (|p|^2 + R^2 - r^2)^2 = 4 R^2 (x^2 + z^2) along p = o + t d
+        let o = o + d * start;
+        let b = o.dot(&d);
+        let k = o.dot(&o) + major2 - minor2;
+        let coefficients = [
+            k * k - 4.0 * major2 * (o.x * o.x + o.z * o.z),
+            4.0 * b * k - 8.0 * major2 * (o.x * d.x + o.z * d.z),
+            4.0 * b * b + 2.0 * k - 4.0 * major2 * (d.x * d.x + d.z * d.z),
+            4.0 * b,
+            1.0,
+        ];
+        let t = match polynomial_roots(&coefficients, 0.0, end - start)
+            .into_iter()
+            .find(|t| start + t > 0.0)
+        {
+            Some(t) => t,
+            None => return false,
+        };
+
+        let p = o + d * t;
+        let ring = Vector3::new(p.x, 0.0, p.z).normalized() * self.major_radius;
+        let normal = (p - ring).normalized();
+        intersection.position = self.center + p;
+        intersection.distance = start + t;
+        intersection.normal = normal;
+        intersection.uv = Vector2::new(
+            azimuth(p.x, p.z),
+            modulo(
+                normal.y.atan2(normal.dot(&ring) / self.major_radius),
+                config::PI2,
+            ) / config::PI2,
+        );
+        true
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn nee_available(&self) -> bool {
+        true
+    }
+
+    // Areas grow with the distance from the axis, R + r cos(phi) around the tube, whose
+    // distribution is inverted by Newton's method
+    fn sample_on_surface(&self, random: (f64, f64)) -> Option<Surface> {
+        let (major, minor) = (self.major_radius, self.minor_radius);
+        let theta = config::PI2 * random.0;
+        let target = config::PI2 * major * random.1;
+        let mut phi = config::PI2 * random.1;
+        for _ in 0..8 {
+            phi -= (major * phi + minor * phi.sin() - target) / (major + minor * phi.cos());
+        }
+
+        let outward = Vector3::new(theta.cos(), 0.0, theta.sin());
+        let normal = outward * phi.cos() + Vector3::new(0.0, phi.sin(), 0.0);
+        Some(Surface {
+            position: self.center + outward * major + normal * minor,
+            normal,
+            pdf: (config::PI2 * config::PI2 * major * minor).recip(),
+        })
     }
 }
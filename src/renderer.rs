@@ -1,14 +1,23 @@
 extern crate image;
 
 use crate::material::PointMaterial;
-use image::{ImageBuffer, Rgb};
+use image::{ImageBuffer, ImageFormat, ImageResult, Rgb};
 use rand::{Rng, SeedableRng, StdRng};
 use rayon::prelude::*;
+use std::fs;
+use std::fs::File;
+use std::io;
 use std::io::stdout;
-use std::io::Write;
+use std::io::{BufWriter, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 use stopwatch::Stopwatch;
 
+use crate::accumulation::AccumulationBuffer;
 use crate::camera::{Camera, Ray};
+use crate::checkpoint;
+use crate::checkpoint::SamplingSettings;
 use crate::color::{color_to_rgb, linear_to_gamma, Color};
 use crate::config;
 use crate::filter;
@@ -17,6 +26,7 @@ use crate::tonemap;
 use crate::vector::{Vector2, Vector3};
 
 use crate::scene::Illuminable;
+use crate::tile::{Region, Tiling};
 
 macro_rules! b_f_1 {
     ($fn_: ident) => {
@@ -24,19 +34,17 @@ macro_rules! b_f_1 {
     };
 }
 
-fn update_imgbuf(
+pub fn update_imgbuf(
     filter: filter::PixelArrayFilterFn,
     ldr_from_hdr: tonemap::TonemapFn,
-    accumulation_buf: &Vec<Vector3>,
-    sampling: u32,
+    accumulation: &AccumulationBuffer,
     imgbuf: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
 ) {
-    let scale = ((sampling * config::SUPER_SAMPLING * config::SUPER_SAMPLING) as f64).recip();
-
-    let mut tmp: Vec<_> = accumulation_buf
+    let mut tmp: Vec<_> = accumulation
+        .pixels
         .par_iter()
         .map(|pixel| {
-            let hdr = *pixel * scale;
+            let hdr = pixel.radiance();
             let ldr = ldr_from_hdr(&hdr);
             let gamma = linear_to_gamma(ldr);
             gamma
@@ -52,9 +60,59 @@ fn update_imgbuf(
     }
 }
 
+// Write to a temporary file first so that readers never see a partial file
+pub fn replace_atomically<F, E>(path: &str, write: F) -> Result<(), E>
+where
+    F: FnOnce(&str) -> Result<(), E>,
+    E: From<io::Error>,
+{
+    let tmp_path = format!("{}.tmp", path);
+    write(&tmp_path)?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+pub struct Interval {
+    pub every_sampling: Option<u32>,
+    pub every_duration: Option<Duration>,
+}
+
+impl Interval {
+    // An interval of 0 samplings is never due by sampling
+    pub(crate) fn is_due(&self, sampling: u32, elapsed: Duration) -> bool {
+        let by_sampling = self
+            .every_sampling
+            .is_some_and(|n| sampling.checked_rem(n) == Some(0));
+        let by_duration = self.every_duration.is_some_and(|d| elapsed >= d);
+        by_sampling || by_duration
+    }
+}
+
+pub struct ProgressSnapshot {
+    pub path: String,
+    pub hdr_path: Option<String>,
+    pub interval: Interval,
+}
+
+pub struct CheckpointSettings {
+    pub path: String,
+    pub scene_hash: u64,
+    pub interval: Interval,
+}
+
 pub trait Renderer: Sync {
     fn max_sampling(&self) -> u32;
 
+    // Every pixel gets at least this many samplings before adaptive sampling kicks in
+    fn min_sampling(&self) -> u32 {
+        self.max_sampling()
+    }
+
+    // Pixels whose relative error is under this threshold stop being sampled
+    fn error_threshold(&self) -> f64 {
+        0.0
+    }
+
     fn calc_pixel(
         &self,
         scene: &dyn Illuminable,
@@ -64,11 +122,25 @@ pub trait Renderer: Sync {
         sampling: u32,
     ) -> Color;
 
+    fn tiling(&self) -> Option<Tiling> {
+        None
+    }
+
+    // Added to sampling indexes, so that renders with different offsets use different samples
+    fn sampling_offset(&self) -> u32 {
+        0
+    }
+
+    // Called once before the first sampling
+    fn begin_render(&mut self) {}
+
+    // Called once per sampling with the views sampled in it, a single one unless rendering
+    // several views. Returns true to stop rendering after this sampling
     fn report_progress(
         &mut self,
-        accumulation_buf: &Vec<Vector3>,
+        accumulations: &[&AccumulationBuffer],
         sampling: u32,
-        imgbuf: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
+        imgbufs: &mut [&mut ImageBuffer<Rgb<u8>, Vec<u8>>],
     ) -> bool;
 
     fn filter(&self) -> filter::PixelArrayFilterFn;
@@ -81,30 +153,96 @@ pub trait Renderer: Sync {
         camera: &Camera,
         imgbuf: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
     ) -> u32 {
-        let resolution = Vector2::new(imgbuf.width() as f64, imgbuf.height() as f64);
-        let num_of_pixel = imgbuf.width() * imgbuf.height();
-        let mut accumulation_buf = vec![Vector3::zero(); num_of_pixel as usize];
+        let mut accumulation = AccumulationBuffer::new(imgbuf.width(), imgbuf.height());
+        self.accumulate(scene, camera, &mut accumulation, imgbuf)
+    }
+
+    // Renders the scene from each camera into the image of the same index, e.g. eyes of a
+    // stereo rig, sharing the scene and its BVH. Returns samplings of each view. Samplings are
+    // interleaved between the views and reported together, so that the stop conditions see all
+    // of them and they stop together
+    fn render_views(
+        &mut self,
+        scene: &dyn Illuminable,
+        cameras: &[Camera],
+        imgbufs: &mut [ImageBuffer<Rgb<u8>, Vec<u8>>],
+    ) -> Vec<u32> {
+        let mut accumulations: Vec<_> = imgbufs
+            .iter()
+            .map(|imgbuf| AccumulationBuffer::new(imgbuf.width(), imgbuf.height()))
+            .collect();
         let emissions = scene.emissions();
+        let tiles: Vec<_> = accumulations
+            .iter()
+            .map(|accumulation| self.tiles(accumulation.region))
+            .collect();
+        let mut sampled = vec![0; cameras.len()];
+        let mut converged = vec![false; cameras.len()];
+
+        self.begin_render();
 
         for sampling in 1..=self.max_sampling() {
-            accumulation_buf
-                .par_iter_mut()
-                .enumerate()
-                .for_each(|(i, pixel)| {
-                    let y = i as u32 / imgbuf.width();
-                    let x = i as u32 - y * imgbuf.width();
-                    let frag_coord = Vector2::new(x as f64, (imgbuf.height() - y) as f64);
-                    *pixel += self.supersampling(
-                        scene,
-                        camera,
-                        &emissions,
-                        &frag_coord,
-                        &resolution,
-                        sampling,
-                    );
-                });
-
-            if self.report_progress(&accumulation_buf, sampling, imgbuf) {
+            for (i, camera) in cameras.iter().enumerate() {
+                if converged[i] {
+                    continue;
+                }
+                let accumulation = &mut accumulations[i];
+                if self.sample_pass(scene, camera, &emissions, &tiles[i], accumulation) == 0 {
+                    converged[i] = true;
+                    continue;
+                }
+                sampled[i] = sampling;
+            }
+            if converged.iter().all(|c| *c) {
+                break;
+            }
+
+            let views: Vec<_> = accumulations
+                .iter()
+                .zip(&converged)
+                .filter(|(_, c)| !**c)
+                .map(|(accumulation, _)| accumulation)
+                .collect();
+            let mut view_imgbufs: Vec<_> = imgbufs
+                .iter_mut()
+                .zip(&converged)
+                .filter(|(_, c)| !**c)
+                .map(|(imgbuf, _)| imgbuf)
+                .collect();
+            if self.report_progress(&views, sampling, &mut view_imgbufs) {
+                break;
+            }
+        }
+
+        sampled
+    }
+
+    fn accumulate(
+        &mut self,
+        scene: &dyn Illuminable,
+        camera: &Camera,
+        accumulation: &mut AccumulationBuffer,
+        imgbuf: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
+    ) -> u32 {
+        let emissions = scene.emissions();
+        let tiles = self.tiles(accumulation.region);
+
+        self.begin_render();
+
+        // Resumed buffers continue from the samplings already accumulated
+        let first_sampling = accumulation.max_sampling() + 1;
+        if first_sampling > self.max_sampling() {
+            update_imgbuf(self.filter(), self.tonemap(), accumulation, imgbuf);
+            return accumulation.max_sampling();
+        }
+
+        for sampling in first_sampling..=self.max_sampling() {
+            if self.sample_pass(scene, camera, &emissions, &tiles, accumulation) == 0 {
+                // Every pixel converged
+                return sampling - 1;
+            }
+
+            if self.report_progress(&[accumulation], sampling, &mut [&mut *imgbuf]) {
                 return sampling;
             }
         }
@@ -112,6 +250,77 @@ pub trait Renderer: Sync {
         self.max_sampling()
     }
 
+    fn tiles(&self, region: Region) -> Vec<Region> {
+        match self.tiling() {
+            Some(tiling) => tiling.tiles(region.width, region.height),
+            None => vec![],
+        }
+    }
+
+    // Adds a sampling to every pixel not converged yet, returns how many of them were sampled
+    fn sample_pass(
+        &self,
+        scene: &dyn Illuminable,
+        camera: &Camera,
+        emissions: &Vec<&Box<dyn Intersectable>>,
+        tiles: &[Region],
+        accumulation: &mut AccumulationBuffer,
+    ) -> usize {
+        let resolution = Vector2::new(accumulation.width as f64, accumulation.height as f64);
+        let region = accumulation.region;
+        let min_sampling = self.min_sampling();
+        let error_threshold = self.error_threshold();
+        let sampling_offset = self.sampling_offset();
+
+        let pixels = &accumulation.pixels;
+        let sample_pixel = |i: usize| {
+            let pixel = &pixels[i];
+            if pixel.sampling >= min_sampling && pixel.relative_error() <= error_threshold {
+                return None;
+            }
+
+            let x = region.x + i as u32 % region.width;
+            let y = region.y + i as u32 / region.width;
+            let frag_coord = Vector2::new(x as f64, (accumulation.height - y) as f64);
+            // Seed by the pixel's own sampling index so skipped passes don't matter
+            Some((
+                i,
+                self.supersampling(
+                    scene,
+                    camera,
+                    emissions,
+                    &frag_coord,
+                    &resolution,
+                    sampling_offset + pixel.sampling + 1,
+                ),
+            ))
+        };
+
+        let samples: Vec<(usize, Color)> = if tiles.is_empty() {
+            (0..pixels.len())
+                .into_par_iter()
+                .filter_map(sample_pixel)
+                .collect()
+        } else {
+            // Each tile is rendered by a single thread, tiles are picked up in order
+            tiles
+                .par_iter()
+                .flat_map_iter(|tile| {
+                    tile.pixel_indexes(region.width)
+                        .filter_map(sample_pixel)
+                        .collect::<Vec<_>>()
+                })
+                .collect()
+        };
+        let sampled_pixels = samples.len();
+
+        for (i, color) in samples {
+            accumulation.pixels[i].add(color);
+        }
+
+        sampled_pixels
+    }
+
     fn supersampling(
         &self,
         scene: &dyn Illuminable,
@@ -137,9 +346,24 @@ pub trait Renderer: Sync {
         accumulator
     }
 
-    //    fn save_progress_image(path: &str, accumulation_buf: &Vec<Vector3>, sampling: u32, imgbuf: &mut ImageBuffer<Rgb<u8>, Vec<u8>>) {
-    //        // TODO
-    //    }
+    fn save_progress_image(
+        &self,
+        snapshot: &ProgressSnapshot,
+        accumulation: &AccumulationBuffer,
+        imgbuf: &ImageBuffer<Rgb<u8>, Vec<u8>>,
+    ) -> ImageResult<()> {
+        replace_atomically(&snapshot.path, |tmp_path| {
+            imgbuf.save_with_format(tmp_path, ImageFormat::from_path(&snapshot.path)?)
+        })?;
+
+        if let Some(ref hdr_path) = snapshot.hdr_path {
+            replace_atomically(hdr_path, |tmp_path| {
+                accumulation.write_hdr(BufWriter::new(File::create(tmp_path)?))
+            })?;
+        }
+
+        Ok(())
+    }
 }
 
 pub enum DebugRenderMode {
@@ -168,9 +392,12 @@ impl Renderer for DebugRenderer {
         normalized_coord: &Vector2,
         _sampling: u32,
     ) -> Color {
-        let ray = camera.ray(&normalized_coord);
+        let ray = match camera.ray(&normalized_coord) {
+            Some(ray) => ray,
+            None => return Color::zero(),
+        };
         let light_direction = Vector3::new(1.0, 2.0, -1.0).normalized();
-        let (hit, intersection) = scene.intersect(&ray);
+        let (hit, intersection) = scene.intersect_from_camera(&ray);
 
         if hit {
             match self.mode {
@@ -178,6 +405,7 @@ impl Renderer for DebugRenderer {
                     let shadow_ray = Ray {
                         origin: intersection.position + intersection.normal * config::OFFSET,
                         direction: light_direction,
+                        time: ray.time,
                     };
                     let (shadow_hit, _) = scene.intersect(&shadow_ray);
                     let shadow = if shadow_hit { 0.5 } else { 1.0 };
@@ -195,17 +423,13 @@ impl Renderer for DebugRenderer {
 
     fn report_progress(
         &mut self,
-        accumulation_buf: &Vec<Vector3>,
-        sampling: u32,
-        imgbuf: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
+        accumulations: &[&AccumulationBuffer],
+        _sampling: u32,
+        imgbufs: &mut [&mut ImageBuffer<Rgb<u8>, Vec<u8>>],
     ) -> bool {
-        update_imgbuf(
-            self.filter(),
-            self.tonemap(),
-            accumulation_buf,
-            sampling,
-            imgbuf,
-        );
+        for (accumulation, imgbuf) in accumulations.iter().zip(imgbufs.iter_mut()) {
+            update_imgbuf(self.filter(), self.tonemap(), accumulation, imgbuf);
+        }
         true
     }
 
@@ -220,10 +444,26 @@ impl Renderer for DebugRenderer {
 
 pub struct PathTracingRenderer {
     sampling: u32,
+    pub min_sampling: u32,
+    pub error_threshold: f64,
     pub filter: filter::PixelArrayFilterFn,
     pub tonemap: tonemap::TonemapFn,
 
+    // Stop conditions, checked after every sampling
+    pub time_limit: Option<Duration>,
+    pub target_error: Option<f64>,
+    pub interrupted: Arc<AtomicBool>,
+
+    pub tiling: Option<Tiling>,
+    pub sampling_offset: u32,
+
+    pub snapshot: Option<ProgressSnapshot>,
+    pub checkpoint: Option<CheckpointSettings>,
+
     stopwatch: Stopwatch,
+    total_stopwatch: Stopwatch,
+    snapshot_stopwatch: Stopwatch,
+    checkpoint_stopwatch: Stopwatch,
 }
 
 impl Renderer for PathTracingRenderer {
@@ -231,6 +471,22 @@ impl Renderer for PathTracingRenderer {
         self.sampling
     }
 
+    fn min_sampling(&self) -> u32 {
+        self.min_sampling
+    }
+
+    fn error_threshold(&self) -> f64 {
+        self.error_threshold
+    }
+
+    fn tiling(&self) -> Option<Tiling> {
+        self.tiling
+    }
+
+    fn sampling_offset(&self) -> u32 {
+        self.sampling_offset
+    }
+
     fn calc_pixel(
         &self,
         scene: &dyn Illuminable,
@@ -243,14 +499,21 @@ impl Renderer for PathTracingRenderer {
         let t = ((4.0 + normalized_coord.y) * 100304.0) as usize;
         let seed: &[_] = &[8700304, sampling as usize, s, t];
         let mut rng: StdRng = SeedableRng::from_seed(seed);
-        let mut ray = camera.ray_with_dof(normalized_coord, &mut rng);
+        let (mut ray, weight) = match camera.sample_ray(normalized_coord, &mut rng) {
+            Some(sample) => sample,
+            None => return Color::zero(),
+        };
 
         let mut accumulation = Color::zero();
-        let mut reflectance = Color::one();
+        let mut reflectance = Color::all_of(weight);
 
-        for _ in 1..config::PATHTRACING_BOUNCE_LIMIT {
+        for bounce in 1..config::PATHTRACING_BOUNCE_LIMIT {
             let random = rng.gen::<(f64, f64)>();
-            let (hit, intersection) = scene.intersect(&ray);
+            let (hit, intersection) = if bounce == 1 {
+                scene.intersect_from_camera(&ray)
+            } else {
+                scene.intersect(&ray)
+            };
             let mut current_reflectance = 1.0;
 
             if hit {
@@ -266,6 +529,7 @@ impl Renderer for PathTracingRenderer {
                             * PathTracingRenderer::next_event_estimation(
                                 random,
                                 &result.ray.origin,
+                                ray.time,
                                 view,
                                 &intersection.normal,
                                 scene,
@@ -274,7 +538,10 @@ impl Renderer for PathTracingRenderer {
                             );
                     }
 
-                    ray = result.ray;
+                    ray = Ray {
+                        time: ray.time,
+                        ..result.ray
+                    };
                     current_reflectance = result.reflectance;
                 } else {
                     // Nothing sampled, break path tracing interations
@@ -295,27 +562,37 @@ impl Renderer for PathTracingRenderer {
 
     fn report_progress(
         &mut self,
-        accumulation_buf: &Vec<Vector3>,
+        accumulations: &[&AccumulationBuffer],
         sampling: u32,
-        imgbuf: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
+        imgbufs: &mut [&mut ImageBuffer<Rgb<u8>, Vec<u8>>],
     ) -> bool {
+        // Passes of all the views in this sampling
+        let elapsed = self.stopwatch.elapsed();
         print!(
             "rendering: {}-th sampling done. Elapsed {} ms\r",
             sampling,
-            self.stopwatch.elapsed_ms()
+            elapsed.as_millis()
         );
         let _ = stdout().flush();
 
-        update_imgbuf(
-            self.filter(),
-            self.tonemap(),
-            accumulation_buf,
-            sampling,
-            imgbuf,
-        );
+        for (accumulation, imgbuf) in accumulations.iter().zip(imgbufs.iter_mut()) {
+            update_imgbuf(self.filter(), self.tonemap(), accumulation, imgbuf);
+        }
         self.stopwatch.restart();
 
-        false
+        // Several views have no layout here to snapshot them in
+        if let ([accumulation], [imgbuf]) = (accumulations, imgbufs) {
+            self.save_progress(accumulation, sampling, imgbuf);
+        }
+
+        self.should_stop(accumulations, elapsed)
+    }
+
+    fn begin_render(&mut self) {
+        self.stopwatch.restart();
+        self.total_stopwatch.restart();
+        self.snapshot_stopwatch.restart();
+        self.checkpoint_stopwatch.restart();
     }
 
     fn filter(&self) -> filter::PixelArrayFilterFn {
@@ -335,15 +612,100 @@ impl PathTracingRenderer {
     ) -> PathTracingRenderer {
         PathTracingRenderer {
             sampling,
+            min_sampling: sampling,
+            error_threshold: 0.0,
             filter,
             tonemap,
+            time_limit: None,
+            target_error: None,
+            interrupted: Arc::new(AtomicBool::new(false)),
+            tiling: None,
+            sampling_offset: 0,
+            snapshot: None,
+            checkpoint: None,
             stopwatch: Stopwatch::new(),
+            total_stopwatch: Stopwatch::new(),
+            snapshot_stopwatch: Stopwatch::new(),
+            checkpoint_stopwatch: Stopwatch::new(),
+        }
+    }
+
+    // Snapshot and checkpoint of a single view, when due
+    fn save_progress(
+        &mut self,
+        accumulation: &AccumulationBuffer,
+        sampling: u32,
+        imgbuf: &ImageBuffer<Rgb<u8>, Vec<u8>>,
+    ) {
+        if let Some(ref snapshot) = self.snapshot {
+            if snapshot
+                .interval
+                .is_due(sampling, self.snapshot_stopwatch.elapsed())
+            {
+                if let Err(e) = self.save_progress_image(snapshot, accumulation, imgbuf) {
+                    println!("\nFailed to save progress image: {}", e);
+                }
+                self.snapshot_stopwatch.restart();
+            }
+        }
+
+        if let Some(ref settings) = self.checkpoint {
+            if settings
+                .interval
+                .is_due(sampling, self.checkpoint_stopwatch.elapsed())
+            {
+                let sampling_settings = SamplingSettings::of(self);
+                if let Err(e) = checkpoint::save(
+                    &settings.path,
+                    settings.scene_hash,
+                    &sampling_settings,
+                    accumulation,
+                ) {
+                    println!("\nFailed to save checkpoint: {}", e);
+                }
+                self.checkpoint_stopwatch.restart();
+            }
+        }
+    }
+
+    // last_sampling is the time of the passes of all the views in the last sampling
+    pub(crate) fn should_stop(
+        &self,
+        accumulations: &[&AccumulationBuffer],
+        last_sampling: Duration,
+    ) -> bool {
+        if self.interrupted.load(Ordering::SeqCst) {
+            println!("\nInterrupted, stopping with current samplings");
+            return true;
+        }
+
+        // Stop if the next sampling is expected to exceed the budget
+        if let Some(time_limit) = self.time_limit {
+            if self.total_stopwatch.elapsed() + last_sampling > time_limit {
+                println!("\nTime limit reached, stopping");
+                return true;
+            }
+        }
+
+        // Every view has to reach the target
+        if let Some(target_error) = self.target_error {
+            if accumulations
+                .iter()
+                .all(|accumulation| accumulation.mean_relative_error() <= target_error)
+            {
+                println!("\nTarget error reached, stopping");
+                return true;
+            }
         }
+
+        false
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn next_event_estimation(
         random: (f64, f64),
         position: &Vector3,
+        time: f64,
         view: &Vector3,
         normal: &Vector3,
         scene: &dyn Illuminable,
@@ -355,12 +717,16 @@ impl PathTracingRenderer {
         let mut accumulation = Vector3::zero();
 
         for emission in emissions {
-            let surface = emission.sample_on_surface(random);
+            let surface = match emission.sample_from(position, random) {
+                Some(surface) => surface,
+                None => continue,
+            };
             let shadow_vec = surface.position - *position;
             let shadow_dir = shadow_vec.normalized();
             let shadow_ray = Ray {
                 origin: *position,
                 direction: shadow_dir,
+                time,
             };
             let (shadow_hit, shadow_intersection) = scene.intersect(&shadow_ray);
 
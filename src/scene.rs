@@ -1,5 +1,7 @@
+use crate::aabb::Aabb;
 use crate::camera::Ray;
 use crate::color::Color;
+use crate::mesh::{node_from_aabbs, BvhNode};
 use crate::rayintersectable::{Intersectable, Intersection};
 use crate::texture::Texture;
 use crate::vector::Vector3;
@@ -12,37 +14,94 @@ pub struct Surface {
 
 pub trait Illuminable: Sync {
     fn intersect(&self, ray: &Ray) -> (bool, Intersection);
+    // Same as intersect, passing through elements hidden from the camera
+    fn intersect_from_camera(&self, ray: &Ray) -> (bool, Intersection) {
+        self.intersect(ray)
+    }
     fn emissions(&self) -> Vec<&Box<dyn Intersectable>>;
 }
 
 pub struct Scene {
-    pub elements: Vec<Box<dyn Intersectable>>,
+    elements: Vec<Box<dyn Intersectable>>,
     pub skybox: Skybox,
+    // Over the bounds of the elements, so that rays only test the elements near them
+    bvh: BvhNode,
+    // Elements without bounds, such as planes, tested by every ray
+    unbounded: Vec<usize>,
 }
 
-impl Illuminable for Scene {
-    fn intersect(&self, ray: &Ray) -> (bool, Intersection) {
+// Calls intersect_element with the elements in the nodes the ray passes through
+fn intersect_node<F: FnMut(usize)>(node: &BvhNode, ray: &Ray, intersect_element: &mut F) {
+    if !node.aabb.intersect_with_ray(ray).0 {
+        return;
+    }
+    for i in &node.indexes {
+        intersect_element(*i);
+    }
+    for child in &node.children {
+        intersect_node(child, ray, intersect_element);
+    }
+}
+
+impl Scene {
+    pub fn new(elements: Vec<Box<dyn Intersectable>>, skybox: Skybox) -> Scene {
+        let aabbs: Vec<Aabb> = elements.iter().map(|e| e.aabb()).collect();
+        let (mut bounded, unbounded): (Vec<usize>, Vec<usize>) =
+            (0..elements.len()).partition(|i| aabbs[*i].is_bounded());
+        Scene {
+            bvh: node_from_aabbs(&aabbs, &mut bounded),
+            unbounded,
+            elements,
+            skybox,
+        }
+    }
+
+    pub fn elements(&self) -> &[Box<dyn Intersectable>] {
+        &self.elements
+    }
+
+    fn intersect_elements(&self, ray: &Ray, from_camera: bool) -> (bool, Intersection) {
         let mut intersection = Intersection::empty();
         let mut nearest: Option<&Box<dyn Intersectable>> = None;
 
-        for e in &self.elements {
+        let mut intersect_element = |i: usize| {
+            let e = &self.elements[i];
+            if from_camera && !e.visible_to_camera() {
+                return;
+            }
             if e.intersect(&ray, &mut intersection) {
-                nearest = Some(&e);
+                nearest = Some(e);
             }
+        };
+        for i in &self.unbounded {
+            intersect_element(*i);
         }
+        intersect_node(&self.bvh, ray, &mut intersect_element);
 
         if let Some(element) = nearest {
-            let material = element.material();
+            let material = element.material_at(&intersection);
             intersection.material.surface = material.surface.clone();
-            intersection.material.albedo = material.albedo.sample(intersection.uv);
-            intersection.material.emission = material.emission.sample(intersection.uv);
-            intersection.material.roughness = material.roughness.sample(intersection.uv).x;
+            intersection.material.albedo =
+                material.albedo.sample(intersection.uv) * element.vertex_color(&intersection);
+            intersection.material.emission = material.emission.sample(intersection.uv)
+                * element.emission_toward(&intersection, &-ray.direction);
+            intersection.material.roughness = material.roughness.sample_linear(intersection.uv).x;
             (true, intersection)
         } else {
             intersection.material.emission = self.skybox.sample(&ray.direction);
             (false, intersection)
         }
     }
+}
+
+impl Illuminable for Scene {
+    fn intersect(&self, ray: &Ray) -> (bool, Intersection) {
+        self.intersect_elements(ray, false)
+    }
+
+    fn intersect_from_camera(&self, ray: &Ray) -> (bool, Intersection) {
+        self.intersect_elements(ray, true)
+    }
 
     fn emissions(&self) -> Vec<&Box<dyn Intersectable>> {
         self.elements
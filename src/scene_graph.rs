@@ -0,0 +1,78 @@
+use crate::instance::Instance;
+use crate::material::Material;
+use crate::matrix::Matrix44;
+use crate::mesh::BvhMesh;
+use crate::rayintersectable::Intersectable;
+use crate::transform::Transformed;
+use std::sync::Arc;
+
+// Node of a scene hierarchy, placed by the matrices of all the groups above it
+pub enum SceneNode {
+    Group(Group),
+    // Shared mesh, with the material of its faces that have none of their own
+    Mesh {
+        mesh: Arc<BvhMesh>,
+        material: Arc<Material>,
+    },
+    // Primitive of its own, such as a sphere or cuboid
+    Element(Box<dyn Intersectable>),
+}
+
+impl SceneNode {
+    pub fn mesh(mesh: Arc<BvhMesh>, material: Arc<Material>) -> SceneNode {
+        SceneNode::Mesh { mesh, material }
+    }
+
+    fn flatten_into(self, parent: &Matrix44, elements: &mut Vec<Box<dyn Intersectable>>) {
+        match self {
+            SceneNode::Group(group) => group.flatten_into(parent, elements),
+            SceneNode::Mesh { mesh, material } => {
+                elements.push(Box::new(Instance::new(mesh, *parent, material)))
+            }
+            SceneNode::Element(element) => {
+                elements.push(Box::new(Transformed::new(element, *parent)))
+            }
+        }
+    }
+}
+
+impl From<Group> for SceneNode {
+    fn from(group: Group) -> SceneNode {
+        SceneNode::Group(group)
+    }
+}
+
+// Children placed by matrix in the space of the parent of the group
+pub struct Group {
+    pub matrix: Matrix44,
+    pub children: Vec<SceneNode>,
+}
+
+impl Group {
+    pub fn new(matrix: Matrix44) -> Group {
+        Group {
+            matrix,
+            children: vec![],
+        }
+    }
+
+    pub fn with_child<T: Into<SceneNode>>(mut self, child: T) -> Group {
+        self.children.push(child.into());
+        self
+    }
+
+    // Elements of the scene in world space, each mesh an instance of its shared BVH and each
+    // primitive transformed, so that the hierarchy costs nothing when rendering
+    pub fn flatten(self) -> Vec<Box<dyn Intersectable>> {
+        let mut elements = vec![];
+        self.flatten_into(&Matrix44::identity(), &mut elements);
+        elements
+    }
+
+    fn flatten_into(self, parent: &Matrix44, elements: &mut Vec<Box<dyn Intersectable>>) {
+        let matrix = *parent * self.matrix;
+        for child in self.children {
+            child.flatten_into(&matrix, elements);
+        }
+    }
+}
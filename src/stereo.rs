@@ -0,0 +1,140 @@
+use image::{ImageBuffer, Rgb};
+use std::str::FromStr;
+
+use crate::camera::{Camera, CameraKeyframe, Projection};
+use crate::vector::Vector3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+impl Eye {
+    // Side of the rig center the eye is on, along right
+    fn side(&self) -> f64 {
+        match *self {
+            Eye::Left => -1.0,
+            Eye::Right => 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Convergence {
+    // Parallel eyes with image planes shifted toward each other, no vertical parallax
+    OffAxis,
+    // Eyes rotated toward the convergence point, with keystone distortion at the edges
+    ToeIn,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct StereoRig {
+    // Distance between the eyes
+    pub interaxial: f64,
+    // Distance from the rig where the eyes have zero parallax
+    pub convergence_distance: f64,
+    pub convergence: Convergence,
+}
+
+impl StereoRig {
+    // Camera of an eye of the rig centered at camera. Equirectangular cameras become
+    // omni-directional stereo, which converges at infinity. Orthographic and fisheye eyes
+    // stay parallel for off-axis convergence
+    pub fn eye(&self, camera: &Camera, eye: Eye) -> Camera {
+        let offset = eye.side() * self.interaxial * 0.5;
+        if let Projection::Equirectangular { .. } = camera.projection {
+            return Camera {
+                projection: Projection::Equirectangular { eye_offset: offset },
+                ..camera.clone()
+            };
+        }
+
+        let (position, target) = self.eye_pose(
+            camera.position,
+            camera.position + camera.forward,
+            camera.y_up,
+            offset,
+        );
+        let mut eye_camera = camera.looking(position, target);
+        eye_camera.keyframes = camera
+            .keyframes
+            .iter()
+            .map(|keyframe| {
+                let (position, target) =
+                    self.eye_pose(keyframe.position, keyframe.target, camera.y_up, offset);
+                CameraKeyframe {
+                    time: keyframe.time,
+                    position,
+                    target,
+                }
+            })
+            .collect();
+
+        if let (Convergence::OffAxis, Projection::Perspective) =
+            (self.convergence, &camera.projection)
+        {
+            // Center of the rig at the convergence distance projects to the image center
+            let half_tangent = camera.plane_half_right.length() / camera.focus_distance;
+            eye_camera.shift.x -= offset / (self.convergence_distance * half_tangent);
+        }
+        eye_camera
+    }
+
+    pub fn eyes(&self, camera: &Camera) -> [Camera; 2] {
+        [self.eye(camera, Eye::Left), self.eye(camera, Eye::Right)]
+    }
+
+    // Position and target of an eye offset to the right of the rig at position
+    fn eye_pose(
+        &self,
+        position: Vector3,
+        target: Vector3,
+        y_up: Vector3,
+        offset: f64,
+    ) -> (Vector3, Vector3) {
+        let forward = (target - position).normalized();
+        let right = forward.cross(&y_up).normalized();
+        let eye_position = position + right * offset;
+        match self.convergence {
+            Convergence::OffAxis => (eye_position, target + right * offset),
+            Convergence::ToeIn => (eye_position, position + forward * self.convergence_distance),
+        }
+    }
+}
+
+// Arrangement of the left and right eye images in one image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StereoLayout {
+    SideBySide,
+    TopBottom,
+}
+
+impl StereoLayout {
+    pub fn compose(
+        &self,
+        left: &ImageBuffer<Rgb<u8>, Vec<u8>>,
+        right: &ImageBuffer<Rgb<u8>, Vec<u8>>,
+    ) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+        let (width, height) = left.dimensions();
+        let (mut output, x, y) = match *self {
+            StereoLayout::SideBySide => (ImageBuffer::new(width * 2, height), width, 0),
+            StereoLayout::TopBottom => (ImageBuffer::new(width, height * 2), 0, height),
+        };
+        image::imageops::replace(&mut output, left, 0, 0);
+        image::imageops::replace(&mut output, right, x as i64, y as i64);
+        output
+    }
+}
+
+impl FromStr for StereoLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "side-by-side" => Ok(StereoLayout::SideBySide),
+            "top-bottom" => Ok(StereoLayout::TopBottom),
+            _ => Err(format!("Unknown stereo layout {}", s)),
+        }
+    }
+}
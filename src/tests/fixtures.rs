@@ -0,0 +1,80 @@
+#![cfg(test)]
+
+// Helpers shared by the tests of elements and scenes
+
+use super::super::camera::Ray;
+use super::super::color::Color;
+use super::super::material::{Material, SurfaceType};
+use super::super::rayintersectable::{Intersectable, Intersection};
+use super::super::scene::{Scene, Skybox};
+use super::super::texture::Texture;
+use super::super::vector::Vector3;
+
+pub fn material() -> Material {
+    diffuse(Color::one())
+}
+
+pub fn diffuse(albedo: Color) -> Material {
+    Material::new(
+        SurfaceType::Diffuse,
+        Texture::of_color(albedo),
+        Texture::black(),
+        Texture::of_color(Color::all_of(0.5)),
+    )
+}
+
+pub fn emissive(emission: Color) -> Material {
+    Material::new(
+        SurfaceType::Diffuse,
+        Texture::black(),
+        Texture::of_color(emission),
+        Texture::white(),
+    )
+}
+
+pub fn assert_near(a: Vector3, b: Vector3) {
+    assert!((a - b).length() < 1e-9, "{:?} != {:?}", a, b);
+}
+
+pub fn intersect(
+    element: &dyn Intersectable,
+    origin: Vector3,
+    direction: Vector3,
+) -> Option<Intersection> {
+    intersect_at(element, origin, direction, 0.0)
+}
+
+pub fn intersect_at(
+    element: &dyn Intersectable,
+    origin: Vector3,
+    direction: Vector3,
+    time: f64,
+) -> Option<Intersection> {
+    let ray = Ray {
+        origin,
+        direction: direction.normalized(),
+        time,
+    };
+    let mut intersection = Intersection::empty();
+    if element.intersect(&ray, &mut intersection) {
+        Some(intersection)
+    } else {
+        None
+    }
+}
+
+// Lit by the elements alone
+pub fn scene(elements: Vec<Box<dyn Intersectable>>) -> Scene {
+    Scene::new(
+        elements,
+        Skybox {
+            px_texture: Texture::black(),
+            nx_texture: Texture::black(),
+            py_texture: Texture::black(),
+            ny_texture: Texture::black(),
+            pz_texture: Texture::black(),
+            nz_texture: Texture::black(),
+            intensity: Vector3::zero(),
+        },
+    )
+}
@@ -1,2 +1,22 @@
+mod fixtures;
 mod test_vector;
-mod test_matrix;
\ No newline at end of file
+mod test_matrix;
+mod test_adaptive;
+mod test_checkpoint;
+mod test_tile;
+mod test_distributed;
+mod test_camera;
+mod test_aperture;
+mod test_motion;
+mod test_stereo;
+mod test_lens;
+mod test_mesh;
+mod test_loader;
+mod test_gltf;
+mod test_instance;
+mod test_scene_graph;
+mod test_transform;
+mod test_primitives;
+mod test_light;
+mod test_renderer;
+mod test_texture;
\ No newline at end of file
@@ -0,0 +1,194 @@
+#![cfg(test)]
+
+use super::super::accumulation::{AccumulatedPixel, AccumulationBuffer};
+use super::super::camera::{Camera, FieldOfView, LensShape};
+use super::super::color::Color;
+use super::super::config;
+use super::super::filter;
+use super::super::rayintersectable::Intersectable;
+use super::super::renderer::Renderer;
+use super::super::scene::{Illuminable, Scene};
+use super::super::tonemap;
+use super::super::vector::{Vector2, Vector3};
+use super::fixtures::scene;
+use image::{ImageBuffer, Rgb};
+
+// Left half of the frame is flat, right half alternates between black and white by sampling
+struct SplitRenderer {
+    max_sampling: u32,
+    min_sampling: u32,
+    error_threshold: f64,
+    reported: Vec<u32>,
+}
+
+impl SplitRenderer {
+    fn new(max_sampling: u32, min_sampling: u32, error_threshold: f64) -> SplitRenderer {
+        SplitRenderer {
+            max_sampling,
+            min_sampling,
+            error_threshold,
+            reported: vec![],
+        }
+    }
+}
+
+impl Renderer for SplitRenderer {
+    fn max_sampling(&self) -> u32 {
+        self.max_sampling
+    }
+
+    fn min_sampling(&self) -> u32 {
+        self.min_sampling
+    }
+
+    fn error_threshold(&self) -> f64 {
+        self.error_threshold
+    }
+
+    fn calc_pixel(
+        &self,
+        _scene: &dyn Illuminable,
+        _camera: &Camera,
+        _emissions: &Vec<&Box<dyn Intersectable>>,
+        normalized_coord: &Vector2,
+        sampling: u32,
+    ) -> Color {
+        if normalized_coord.x < 0.0 {
+            Color::all_of(0.5)
+        } else if sampling % 2 == 1 {
+            Color::one()
+        } else {
+            Color::zero()
+        }
+    }
+
+    fn report_progress(
+        &mut self,
+        _accumulations: &[&AccumulationBuffer],
+        sampling: u32,
+        _imgbufs: &mut [&mut ImageBuffer<Rgb<u8>, Vec<u8>>],
+    ) -> bool {
+        self.reported.push(sampling);
+        false
+    }
+
+    fn filter(&self) -> filter::PixelArrayFilterFn {
+        filter::identity_filter
+    }
+
+    fn tonemap(&self) -> tonemap::TonemapFn {
+        tonemap::none
+    }
+}
+
+fn empty_scene() -> (Camera, Scene) {
+    let camera = Camera::new(
+        Vector3::new(0.0, 0.0, 5.0),
+        Vector3::zero(),
+        Vector3::new(0.0, 1.0, 0.0),
+        FieldOfView::Vertical(60.0),
+        1.0,
+        LensShape::Circle,
+        0.0,
+        5.0,
+    );
+    let scene = scene(vec![]);
+    (camera, scene)
+}
+
+fn accumulate(renderer: &mut SplitRenderer) -> (u32, AccumulationBuffer) {
+    let (camera, scene) = empty_scene();
+    let mut accumulation = AccumulationBuffer::new(4, 2);
+    let mut imgbuf = ImageBuffer::new(4, 2);
+    let samplings = renderer.accumulate(&scene, &camera, &mut accumulation, &mut imgbuf);
+    (samplings, accumulation)
+}
+
+#[test]
+fn test_relative_error() {
+    let mut pixel = AccumulatedPixel::empty();
+    assert_eq!(pixel.relative_error(), config::INF);
+    pixel.add(Color::all_of(4.0));
+    assert_eq!(pixel.relative_error(), config::INF);
+
+    // Luminances k and 3k have a mean of 2k and a standard error of k
+    pixel.add(Color::all_of(12.0));
+    assert!((pixel.relative_error() - 0.5).abs() < 1e-9);
+
+    let mut flat = AccumulatedPixel::empty();
+    for _ in 0..3 {
+        flat.add(Color::all_of(2.0));
+    }
+    assert_eq!(flat.relative_error(), 0.0);
+
+    // Black pixels are relative to the minimum luminance rather than dividing by zero
+    let mut dark = AccumulatedPixel::empty();
+    dark.add(Color::zero());
+    dark.add(Color::zero());
+    assert_eq!(dark.relative_error(), 0.0);
+}
+
+#[test]
+fn test_sampling_map() {
+    let mut accumulation = AccumulationBuffer::new(3, 1);
+    for _ in 0..4 {
+        accumulation.pixels[0].add(Color::one());
+    }
+    for _ in 0..2 {
+        accumulation.pixels[1].add(Color::one());
+    }
+
+    let map = accumulation.sampling_map();
+    assert_eq!(map.dimensions(), (3, 1));
+    assert_eq!(map.get_pixel(0, 0).0, [255; 3]);
+    assert_eq!(map.get_pixel(1, 0).0, [127; 3]);
+    assert_eq!(map.get_pixel(2, 0).0, [0; 3]);
+
+    // Nothing sampled is black rather than divided by zero
+    let empty = AccumulationBuffer::new(2, 1).sampling_map();
+    assert!(empty.pixels().all(|p| p.0 == [0; 3]));
+}
+
+#[test]
+fn test_min_sampling_is_honoured() {
+    // Every pixel is under this threshold as soon as it has any estimate
+    let mut renderer = SplitRenderer::new(10, 3, config::INF);
+    let (samplings, accumulation) = accumulate(&mut renderer);
+
+    assert!(accumulation.pixels.iter().all(|p| p.sampling == 3));
+    // The fourth sampling found nothing to do, so the render stopped after the third
+    assert_eq!(samplings, 3);
+    assert_eq!(renderer.reported, vec![1, 2, 3]);
+}
+
+#[test]
+fn test_converged_pixels_stop_sampling() {
+    let mut renderer = SplitRenderer::new(8, 2, 0.01);
+    let (samplings, accumulation) = accumulate(&mut renderer);
+
+    for (i, pixel) in accumulation.pixels.iter().enumerate() {
+        let expected = if i % 4 < 2 { 2 } else { 8 };
+        assert_eq!(pixel.sampling, expected, "pixel {}", i);
+    }
+    assert_eq!(samplings, 8);
+    assert_eq!(accumulation.total_sampling(), 4 * 2 + 4 * 8);
+}
+
+#[test]
+fn test_early_return_counts_completed_samplings() {
+    // A resumed buffer whose pixels all converged gets no further samplings
+    let mut renderer = SplitRenderer::new(8, 2, 0.01);
+    let (camera, scene) = empty_scene();
+    let mut accumulation = AccumulationBuffer::new(4, 2);
+    for pixel in &mut accumulation.pixels {
+        for _ in 0..5 {
+            pixel.add(Color::all_of(2.0));
+        }
+    }
+    let mut imgbuf = ImageBuffer::new(4, 2);
+    let samplings = renderer.accumulate(&scene, &camera, &mut accumulation, &mut imgbuf);
+
+    assert_eq!(samplings, 5);
+    assert!(renderer.reported.is_empty());
+    assert!(accumulation.pixels.iter().all(|p| p.sampling == 5));
+}
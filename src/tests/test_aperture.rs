@@ -0,0 +1,91 @@
+#![cfg(test)]
+
+use super::super::aperture::{concentric_disk, ApertureMask, PolygonAperture};
+use super::super::config;
+use super::super::vector::Vector2;
+use image::{DynamicImage, ImageBuffer, Luma};
+
+// Stratified over the unit square, so that tests are deterministic
+fn stratified(n: u32) -> impl Iterator<Item = (f64, f64)> {
+    (0..n * n).map(move |i| {
+        (
+            ((i % n) as f64 + 0.5) / n as f64,
+            ((i / n) as f64 + 0.5) / n as f64,
+        )
+    })
+}
+
+#[test]
+fn test_concentric_disk() {
+    let samples: Vec<Vector2> = stratified(64).map(|(u, v)| concentric_disk(u, v)).collect();
+    assert!(samples.iter().all(|p| p.length() <= 1.0));
+
+    // Uniform by area
+    let inner = samples.iter().filter(|p| p.length() < 0.5).count();
+    assert!((inner as f64 / samples.len() as f64 - 0.25).abs() < 0.01);
+}
+
+#[test]
+fn test_polygon_aperture() {
+    let blades = 6;
+    let aperture = PolygonAperture::new(blades, 0.0, 0.0);
+    let samples: Vec<Vector2> = stratified(128).map(|uv| aperture.sample(uv)).collect();
+
+    // Inside every edge
+    let apothem = (config::PI / blades as f64).cos();
+    for k in 0..blades {
+        let angle = (k as f64 + 0.5) * config::PI2 / blades as f64;
+        let normal = Vector2::new(angle.cos(), angle.sin());
+        assert!(samples.iter().all(|p| p.dot(normal) <= apothem + 1e-9));
+    }
+
+    // Uniform by area, the inscribed circle covers pi / (2 sqrt 3) of a hexagon
+    let inner = samples.iter().filter(|p| p.length() < apothem).count();
+    let expected = config::PI / (2.0 * 3f64.sqrt());
+    assert!((inner as f64 / samples.len() as f64 - expected).abs() < 0.01);
+
+    let mean = samples
+        .iter()
+        .fold(Vector2::new(0.0, 0.0), |sum, p| sum + *p);
+    assert!((mean / samples.len() as f64).length() < 1e-3);
+}
+
+#[test]
+fn test_curved_polygon_aperture_is_circle() {
+    let aperture = PolygonAperture::new(5, 18.0, 1.0);
+    let samples: Vec<Vector2> = stratified(128).map(|uv| aperture.sample(uv)).collect();
+    assert!(samples.iter().all(|p| p.length() <= 1.0 + 1e-9));
+
+    let inner = samples.iter().filter(|p| p.length() < 0.5).count();
+    assert!((inner as f64 / samples.len() as f64 - 0.25).abs() < 0.01);
+}
+
+#[test]
+fn test_aperture_mask() {
+    // Only the right half of the middle rows is open
+    let image = ImageBuffer::from_fn(4, 4, |x, y| {
+        if x >= 2 && (1..3).contains(&y) {
+            Luma([255u8])
+        } else {
+            Luma([0u8])
+        }
+    });
+    let mask = ApertureMask::from_image(&DynamicImage::ImageLuma8(image)).unwrap();
+    let samples: Vec<Vector2> = stratified(32).map(|uv| mask.sample(uv)).collect();
+
+    assert!(samples
+        .iter()
+        .all(|p| (0.0..=1.0).contains(&p.x) && (-0.5..=0.5).contains(&p.y)));
+    let mean = samples
+        .iter()
+        .fold(Vector2::new(0.0, 0.0), |sum, p| sum + *p);
+    let mean = mean / samples.len() as f64;
+    assert!((mean.x - 0.5).abs() < 1e-9 && mean.y.abs() < 1e-9);
+}
+
+#[test]
+fn test_aperture_mask_errors() {
+    let black = ImageBuffer::from_pixel(4, 4, Luma([0u8]));
+    assert!(ApertureMask::from_image(&DynamicImage::ImageLuma8(black)).is_err());
+    assert!(ApertureMask::new("no/such/aperture.png").is_err());
+}
@@ -0,0 +1,293 @@
+#![cfg(test)]
+
+use super::super::camera::{
+    Camera, CameraKeyframe, FieldOfView, FisheyeMapping, LensShape, Shutter,
+};
+use super::super::vector::{Vector2, Vector3};
+use super::fixtures::assert_near;
+use rand::{SeedableRng, StdRng};
+
+#[test]
+fn test_perspective_rays_start_at_eye() {
+    let camera = Camera::new(
+        Vector3::new(0.0, 0.0, 5.0),
+        Vector3::zero(),
+        Vector3::new(0.0, 1.0, 0.0),
+        FieldOfView::Vertical(90.0),
+        1.0,
+        LensShape::Circle,
+        0.0,
+        5.0,
+    );
+
+    let center = camera.ray(&Vector2::new(0.0, 0.0)).unwrap();
+    assert_near(center.origin, Vector3::new(0.0, 0.0, 5.0));
+    assert_near(center.direction, Vector3::new(0.0, 0.0, -1.0));
+
+    let corner = camera.ray(&Vector2::new(1.0, 1.0)).unwrap();
+    assert_near(corner.origin, Vector3::new(0.0, 0.0, 5.0));
+    assert!(corner.direction.x > 0.0 && corner.direction.y > 0.0);
+}
+
+#[test]
+fn test_orthographic_rays_are_parallel() {
+    let camera = Camera::orthographic(
+        Vector3::new(0.0, 0.0, 5.0),
+        Vector3::zero(),
+        Vector3::new(0.0, 1.0, 0.0),
+        4.0,
+        1.0,
+        LensShape::Circle,
+        0.0,
+        5.0,
+    );
+
+    let center = camera.ray(&Vector2::new(0.0, 0.0)).unwrap();
+    assert_near(center.origin, Vector3::new(0.0, 0.0, 5.0));
+    assert_near(center.direction, Vector3::new(0.0, 0.0, -1.0));
+
+    // Shorter side of the image spans [-1, 1], which is the view width
+    let corner = camera.ray(&Vector2::new(1.0, -1.0)).unwrap();
+    assert_near(corner.origin, Vector3::new(2.0, -2.0, 5.0));
+    assert_near(corner.direction, Vector3::new(0.0, 0.0, -1.0));
+}
+
+#[test]
+fn test_equirectangular_covers_sphere_in_2_to_1() {
+    let camera = Camera::equirectangular(
+        Vector3::zero(),
+        Vector3::new(0.0, 0.0, -1.0),
+        Vector3::new(0.0, 1.0, 0.0),
+    );
+
+    let direction = |x, y| camera.ray(&Vector2::new(x, y)).unwrap().direction;
+    assert_near(direction(0.0, 0.0), Vector3::new(0.0, 0.0, -1.0));
+    assert_near(direction(1.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+    assert_near(direction(2.0, 0.0), Vector3::new(0.0, 0.0, 1.0));
+    assert_near(direction(-2.0, 0.0), Vector3::new(0.0, 0.0, 1.0));
+    assert_near(direction(0.0, 1.0), Vector3::new(0.0, 1.0, 0.0));
+    assert_near(direction(0.0, -1.0), Vector3::new(0.0, -1.0, 0.0));
+
+    // Beyond the sphere in wider or taller images
+    assert!(camera.ray(&Vector2::new(2.5, 0.0)).is_none());
+    assert!(camera.ray(&Vector2::new(0.0, 1.5)).is_none());
+}
+
+#[test]
+fn test_fisheye_mappings() {
+    let fisheye = |mapping| {
+        Camera::fisheye(
+            Vector3::zero(),
+            Vector3::new(0.0, 0.0, -1.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            180.0,
+            mapping,
+        )
+    };
+    let angle = |camera: &Camera, r: f64| {
+        let ray = camera.ray(&Vector2::new(0.0, r)).unwrap();
+        ray.direction.dot(&camera.forward).acos().to_degrees()
+    };
+
+    let equidistant = fisheye(FisheyeMapping::Equidistant);
+    assert!((angle(&equidistant, 0.5) - 45.0).abs() < 1e-9);
+    assert!((angle(&equidistant, 1.0) - 90.0).abs() < 1e-9);
+    assert_near(
+        equidistant.ray(&Vector2::new(1.0, 0.0)).unwrap().direction,
+        Vector3::new(1.0, 0.0, 0.0),
+    );
+
+    let equisolid = fisheye(FisheyeMapping::Equisolid);
+    let expected = 2.0 * (0.5 * 45f64.to_radians().sin()).asin().to_degrees();
+    assert!((angle(&equisolid, 0.5) - expected).abs() < 1e-9);
+    assert!((angle(&equisolid, 1.0) - 90.0).abs() < 1e-9);
+
+    // Outside of the image circle
+    assert!(equidistant.ray(&Vector2::new(0.8, 0.8)).is_none());
+}
+
+#[test]
+fn test_lens_rays_converge_on_focal_plane() {
+    let mut camera = Camera::new(
+        Vector3::new(0.0, 0.0, 5.0),
+        Vector3::zero(),
+        Vector3::new(0.0, 1.0, 0.0),
+        FieldOfView::Vertical(60.0),
+        1.0,
+        LensShape::Circle,
+        0.5,
+        4.0,
+    );
+    camera.cat_eye = 0.5;
+    let seed: &[_] = &[1, 2, 3];
+    let mut rng: StdRng = SeedableRng::from_seed(seed);
+
+    for coord in &[Vector2::new(0.0, 0.0), Vector2::new(0.7, -0.4)] {
+        let pinhole = camera.ray(coord).unwrap();
+        let focus = pinhole.origin + pinhole.direction * (4.0 / pinhole.direction.z.abs());
+        for _ in 0..16 {
+            let ray = camera.ray_with_dof(coord, &mut rng).unwrap();
+            assert!((ray.origin - camera.position).length() <= 0.25 + 1e-9);
+            let t = (ray.origin.z - focus.z) / -ray.direction.z;
+            assert_near(ray.origin + ray.direction * t, focus);
+        }
+    }
+}
+
+#[test]
+fn test_field_of_view() {
+    let aspect_ratio = 16.0 / 9.0;
+    let angles = |fov: FieldOfView| {
+        let (h, v) = fov.half_tangents(aspect_ratio);
+        (
+            2.0 * h.atan().to_degrees(),
+            2.0 * v.atan().to_degrees(),
+            2.0 * h.hypot(v).atan().to_degrees(),
+        )
+    };
+    let near = |a: f64, b: f64| (a - b).abs() < 1e-9;
+
+    let (_, vertical, _) = angles(FieldOfView::Vertical(40.0));
+    assert!(near(vertical, 40.0));
+    let (horizontal, _, _) = angles(FieldOfView::Horizontal(70.0));
+    assert!(near(horizontal, 70.0));
+    let (_, _, diagonal) = angles(FieldOfView::Diagonal(80.0));
+    assert!(near(diagonal, 80.0));
+
+    // 50mm on a full frame sensor is 39.6 degrees horizontally for a 3:2 image
+    let full_frame = FieldOfView::FocalLength {
+        focal_length: 50.0,
+        sensor_width: 36.0,
+        sensor_height: 24.0,
+    };
+    let (h, v) = full_frame.half_tangents(1.5);
+    assert!(near(
+        2.0 * h.atan().to_degrees(),
+        2.0 * (18f64 / 50.0).atan().to_degrees()
+    ));
+    assert!(near(v, 12.0 / 50.0));
+    // Narrower images crop the sides of the sensor
+    let (h, v) = full_frame.half_tangents(1.0);
+    assert!(near(h, 12.0 / 50.0) && near(v, 12.0 / 50.0));
+}
+
+#[test]
+fn test_vertical_fov_is_independent_of_orientation() {
+    let camera = |aspect_ratio| {
+        Camera::new(
+            Vector3::zero(),
+            Vector3::new(0.0, 0.0, -1.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            FieldOfView::Vertical(60.0),
+            aspect_ratio,
+            LensShape::Circle,
+            0.0,
+            1.0,
+        )
+    };
+    let top_angle = |camera: &Camera, top: f64| {
+        let ray = camera.ray(&Vector2::new(0.0, top)).unwrap();
+        ray.direction.dot(&camera.forward).acos().to_degrees()
+    };
+
+    // The shorter side spans [-1, 1] in normalized coordinates
+    assert!((top_angle(&camera(4.0 / 3.0), 1.0) - 30.0).abs() < 1e-9);
+    assert!((top_angle(&camera(3.0 / 4.0), 4.0 / 3.0) - 30.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_camera_motion() {
+    let mut camera = Camera::new(
+        Vector3::zero(),
+        Vector3::new(0.0, 0.0, -1.0),
+        Vector3::new(0.0, 1.0, 0.0),
+        FieldOfView::Vertical(60.0),
+        1.0,
+        LensShape::Circle,
+        0.0,
+        1.0,
+    );
+    camera.keyframes = vec![
+        CameraKeyframe {
+            time: 0.0,
+            position: Vector3::zero(),
+            target: Vector3::new(0.0, 0.0, -1.0),
+        },
+        CameraKeyframe {
+            time: 1.0,
+            position: Vector3::new(2.0, 0.0, 0.0),
+            target: Vector3::new(2.0, 0.0, -1.0),
+        },
+        CameraKeyframe {
+            time: 2.0,
+            position: Vector3::new(2.0, 0.0, 0.0),
+            target: Vector3::new(3.0, 0.0, 0.0),
+        },
+    ];
+    let center = Vector2::new(0.0, 0.0);
+
+    let ray = camera.ray_at(&center, 0.5).unwrap();
+    assert_near(ray.origin, Vector3::new(1.0, 0.0, 0.0));
+    assert_near(ray.direction, Vector3::new(0.0, 0.0, -1.0));
+    assert_eq!(ray.time, 0.5);
+
+    // Looking to the right at the end, clamped after the last keyframe
+    let ray = camera.ray_at(&center, 3.0).unwrap();
+    assert_near(ray.origin, Vector3::new(2.0, 0.0, 0.0));
+    assert_near(ray.direction, Vector3::new(1.0, 0.0, 0.0));
+    let ray = camera.ray_at(&Vector2::new(0.0, 1.0), 2.0).unwrap();
+    assert!(ray.direction.y > 0.0 && ray.direction.z.abs() < 1e-9);
+
+    // Primary rays are spread over the shutter interval
+    camera.shutter = Shutter {
+        open: 0.25,
+        close: 0.75,
+    };
+    let seed: &[_] = &[4, 5, 6];
+    let mut rng: StdRng = SeedableRng::from_seed(seed);
+    let times: Vec<f64> = (0..64)
+        .map(|_| camera.ray_with_dof(&center, &mut rng).unwrap().time)
+        .collect();
+    assert!(times.iter().all(|t| (0.25..=0.75).contains(t)));
+    assert!(times.iter().any(|t| *t < 0.4) && times.iter().any(|t| *t > 0.6));
+    for ray in (0..8).map(|_| camera.ray_with_dof(&center, &mut rng).unwrap()) {
+        assert_near(ray.origin, Vector3::new(ray.time * 2.0, 0.0, 0.0));
+    }
+}
+
+#[test]
+fn test_cat_eye_vignetting() {
+    let mut camera = Camera::new(
+        Vector3::new(0.0, 0.0, 5.0),
+        Vector3::zero(),
+        Vector3::new(0.0, 1.0, 0.0),
+        FieldOfView::Vertical(60.0),
+        1.0,
+        LensShape::Circle,
+        0.5,
+        4.0,
+    );
+    camera.cat_eye = 0.5;
+    let seed: &[_] = &[4, 5, 6];
+    let mut rng: StdRng = SeedableRng::from_seed(seed);
+    let weight = |camera: &Camera, coord: Vector2, rng: &mut StdRng| {
+        camera.sample_ray(&coord, rng).unwrap().1
+    };
+
+    // Light falls off with the share of the aperture squeezed away
+    assert_eq!(weight(&camera, Vector2::new(0.0, 0.0), &mut rng), 1.0);
+    assert!((weight(&camera, Vector2::new(0.6, 0.8), &mut rng) - 0.5).abs() < 1e-12);
+    assert_eq!(weight(&camera, Vector2::new(3.0, 0.0), &mut rng), 0.0);
+
+    // Squeezed radially, the aperture is half as wide along the radius at distance 1
+    let along: f64 = (0..64)
+        .map(|i| {
+            let uv = ((i % 8) as f64 / 7.0, (i / 8) as f64 / 7.0);
+            camera.sample_on_lens(&Vector2::new(1.0, 0.0), uv).x.abs()
+        })
+        .fold(0.0, f64::max);
+    assert!(along <= 0.5 + 1e-9);
+
+    camera.lens_radius = 0.0;
+    assert_eq!(weight(&camera, Vector2::new(0.6, 0.8), &mut rng), 1.0);
+}
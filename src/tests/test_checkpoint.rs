@@ -0,0 +1,179 @@
+#![cfg(test)]
+
+use super::super::accumulation::AccumulationBuffer;
+use super::super::aperture::PolygonAperture;
+use super::super::camera::{Camera, FieldOfView, LensShape};
+use super::super::checkpoint;
+use super::super::checkpoint::SamplingSettings;
+use super::super::color::Color;
+use super::super::config;
+use super::super::tile::Region;
+use super::super::vector::Vector3;
+use super::fixtures::scene;
+use std::env;
+
+const SETTINGS: SamplingSettings = SamplingSettings {
+    max_sampling: 64,
+    min_sampling: 16,
+    error_threshold: 0.01,
+};
+
+fn checkpoint_path(name: &str) -> String {
+    env::temp_dir()
+        .join(format!("fulleffect_test_{}.fecp", name))
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+#[test]
+fn test_save_and_load() {
+    let mut accumulation = AccumulationBuffer::new(3, 2);
+    accumulation.pixels[1].add(Color::new(1.0, 2.0, 3.0));
+    accumulation.pixels[1].add(Color::new(0.5, 0.5, 0.5));
+    accumulation.pixels[4].add(Color::one());
+
+    let path = checkpoint_path("save_and_load");
+    checkpoint::save(&path, 42, &SETTINGS, &accumulation).unwrap();
+    let loaded = checkpoint::load(&path, 42, &SETTINGS, 3, 2, Region::new(0, 0, 3, 2)).unwrap();
+
+    assert_eq!(loaded.width, 3);
+    assert_eq!(loaded.height, 2);
+    assert_eq!(loaded.max_sampling(), 2);
+    for (a, b) in accumulation.pixels.iter().zip(&loaded.pixels) {
+        assert_eq!(a.color, b.color);
+        assert_eq!(a.squared_luminance, b.squared_luminance);
+        assert_eq!(a.sampling, b.sampling);
+    }
+}
+
+#[test]
+fn test_load_mismatch() {
+    let path = checkpoint_path("mismatch");
+    checkpoint::save(&path, 42, &SETTINGS, &AccumulationBuffer::new(3, 2)).unwrap();
+
+    let region = Region::new(0, 0, 3, 2);
+    assert!(checkpoint::load(&path, 42, &SETTINGS, 3, 2, region).is_ok());
+    assert!(checkpoint::load(&path, 43, &SETTINGS, 3, 2, region).is_err());
+    assert!(checkpoint::load(&path, 42, &SETTINGS, 2, 3, region).is_err());
+    assert!(checkpoint::load(&path, 42, &SETTINGS, 3, 2, Region::new(1, 0, 2, 2)).is_err());
+
+    let more_samples = SamplingSettings {
+        max_sampling: 128,
+        ..SETTINGS
+    };
+    let lower_threshold = SamplingSettings {
+        error_threshold: 0.001,
+        ..SETTINGS
+    };
+    assert!(checkpoint::load(&path, 42, &more_samples, 3, 2, region).is_err());
+    assert!(checkpoint::load(&path, 42, &lower_threshold, 3, 2, region).is_err());
+}
+
+#[test]
+fn test_read_corrupt_size() {
+    let mut bytes = Vec::new();
+    let accumulation = AccumulationBuffer::with_region(3, 2, Region::new(1, 0, 2, 2));
+    checkpoint::write_to(&mut bytes, 42, &SETTINGS, &accumulation).unwrap();
+    assert!(checkpoint::read_from(&mut bytes.as_slice(), 42, 3, 2).is_ok());
+
+    // Width and height follow the magic, version, super sampling, bounce limit, hash and settings
+    let size_offset = 4 + 4 + 4 + 4 + 8 + 4 + 4 + 8;
+    let mut huge = bytes.clone();
+    huge[size_offset..size_offset + 8].copy_from_slice(&[0xff; 8]);
+    assert!(checkpoint::read_from(&mut huge.as_slice(), 42, 3, 2).is_err());
+
+    // Region out of the image
+    let region_offset = size_offset + 8;
+    let mut outside = bytes;
+    outside[region_offset..region_offset + 4].copy_from_slice(&2u32.to_le_bytes());
+    assert!(checkpoint::read_from(&mut outside.as_slice(), 42, 3, 2).is_err());
+}
+
+#[test]
+fn test_merge() {
+    let mut left = AccumulationBuffer::with_region(4, 2, Region::new(0, 0, 2, 2));
+    left.pixels[1].add(Color::one());
+    let mut whole = AccumulationBuffer::new(4, 2);
+    whole.pixels[1].add(Color::all_of(3.0));
+    whole.pixels[1].add(Color::all_of(3.0));
+    whole.pixels[2].add(Color::one());
+
+    let left_path = checkpoint_path("merge_left");
+    let whole_path = checkpoint_path("merge_whole");
+    checkpoint::save(&left_path, 42, &SETTINGS, &left).unwrap();
+    checkpoint::save(&whole_path, 42, &SETTINGS, &whole).unwrap();
+
+    let paths = vec![left_path, whole_path];
+    let (hash, merged) =
+        checkpoint::merge(&paths, Some(42), 4, 2, Region::new(0, 0, 4, 2)).unwrap();
+    assert_eq!(hash, 42);
+    assert_eq!(merged.pixels[1].sampling, 3);
+    assert_eq!(merged.pixels[1].color, Color::all_of(7.0));
+    assert_eq!(merged.pixels[2].sampling, 1);
+    assert_eq!(merged.total_sampling(), 4);
+
+    // Weighted by samplings
+    let expected =
+        Color::all_of(7.0 / 3.0 / (config::SUPER_SAMPLING * config::SUPER_SAMPLING) as f64);
+    assert!((merged.pixels[1].radiance() - expected).length() < 1e-12);
+
+    assert!(checkpoint::merge(&paths, Some(43), 4, 2, Region::new(0, 0, 4, 2)).is_err());
+    assert!(checkpoint::merge(&paths, Some(42), 4, 2, Region::new(1, 0, 3, 2)).is_err());
+}
+
+#[test]
+fn test_merge_without_scene() {
+    let mut accumulation = AccumulationBuffer::new(2, 2);
+    accumulation.pixels[0].add(Color::one());
+    let first_path = checkpoint_path("merge_without_scene_first");
+    let second_path = checkpoint_path("merge_without_scene_second");
+    let other_path = checkpoint_path("merge_without_scene_other");
+    checkpoint::save(&first_path, 42, &SETTINGS, &accumulation).unwrap();
+    checkpoint::save(&second_path, 42, &SETTINGS, &accumulation).unwrap();
+    checkpoint::save(&other_path, 43, &SETTINGS, &accumulation).unwrap();
+
+    // The hash of the first buffer is taken as the scene of all of them
+    let region = Region::new(0, 0, 2, 2);
+    let paths = vec![first_path.clone(), second_path];
+    let (hash, merged) = checkpoint::merge(&paths, None, 2, 2, region).unwrap();
+    assert_eq!(hash, 42);
+    assert_eq!(merged.pixels[0].sampling, 2);
+
+    let paths = vec![first_path, other_path];
+    assert!(checkpoint::merge(&paths, None, 2, 2, region).is_err());
+    assert!(checkpoint::merge(&[], None, 2, 2, region).is_err());
+}
+
+#[test]
+fn test_scene_hash_follows_camera_parameters() {
+    let scene = scene(vec![]);
+    let camera = Camera::new(
+        Vector3::new(0.0, 0.0, 5.0),
+        Vector3::zero(),
+        Vector3::new(0.0, 1.0, 0.0),
+        FieldOfView::Vertical(60.0),
+        1.0,
+        LensShape::Polygon(PolygonAperture::new(6, 0.0, 0.0)),
+        0.1,
+        5.0,
+    );
+    let hash = checkpoint::scene_hash(&scene, &camera);
+    assert_eq!(checkpoint::scene_hash(&scene, &camera.clone()), hash);
+
+    let rotated = Camera {
+        lens_shape: LensShape::Polygon(PolygonAperture::new(6, 15.0, 0.0)),
+        ..camera.clone()
+    };
+    assert_ne!(checkpoint::scene_hash(&scene, &rotated), hash);
+    let cat_eye = Camera {
+        cat_eye: 0.2,
+        ..camera.clone()
+    };
+    assert_ne!(checkpoint::scene_hash(&scene, &cat_eye), hash);
+    let circle = Camera {
+        lens_shape: LensShape::Circle,
+        ..camera
+    };
+    assert_ne!(checkpoint::scene_hash(&scene, &circle), hash);
+}
@@ -0,0 +1,245 @@
+#![cfg(test)]
+
+use super::super::accumulation::AccumulationBuffer;
+use super::super::camera::{Camera, FieldOfView, LensShape};
+use super::super::checkpoint;
+use super::super::color::Color;
+use super::super::config;
+use super::super::distributed;
+use super::super::filter;
+use super::super::io_util;
+use super::super::material::{Material, SurfaceType};
+use super::super::rayintersectable::Sphere;
+use super::super::renderer::{PathTracingRenderer, Renderer};
+use super::super::scene::Scene;
+use super::super::texture::Texture;
+use super::super::tile::{Region, TileOrder, Tiling};
+use super::super::tonemap;
+use super::super::vector::Vector3;
+use super::fixtures::scene;
+use image::ImageBuffer;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+fn sample_scene() -> (Camera, Scene) {
+    let camera = Camera::new(
+        Vector3::new(0.0, 1.0, 5.0),
+        Vector3::new(0.0, 0.5, 0.0),
+        Vector3::new(0.0, 1.0, 0.0),
+        FieldOfView::Vertical(60.0),
+        1.5,
+        LensShape::Circle,
+        0.0,
+        5.0,
+    );
+    let scene = scene(vec![
+        Box::new(Sphere {
+            center: Vector3::new(0.0, 0.5, 0.0),
+            radius: 0.5,
+            material: Material::new(
+                SurfaceType::Diffuse,
+                Texture::white(),
+                Texture::black(),
+                Texture::of_color(Color::all_of(0.99)),
+            ),
+        }),
+        Box::new(Sphere {
+            center: Vector3::new(1.0, 2.0, 1.0),
+            radius: 0.2,
+            material: Material::new(
+                SurfaceType::Diffuse,
+                Texture::black(),
+                Texture::of_color(Color::all_of(50.0)),
+                Texture::white(),
+            ),
+        }),
+    ]);
+    (camera, scene)
+}
+
+#[test]
+fn test_split_jobs() {
+    let region = Region::new(2, 1, 10, 6);
+    let tiling = Tiling {
+        size: 4,
+        order: TileOrder::Scanline,
+    };
+    let jobs = distributed::split_jobs(16, 8, region, Some(tiling), 5, 2);
+
+    // 3x2 tiles times samplings [1, 2], [3, 4] and [5]
+    assert_eq!(jobs.len(), 18);
+    let mut samplings = AccumulationBuffer::with_region(16, 8, region);
+    for job in &jobs {
+        assert!(region.contains(&job.region));
+        let mut accumulation = AccumulationBuffer::with_region(16, 8, job.region);
+        for pixel in &mut accumulation.pixels {
+            pixel.sampling = job.sampling;
+        }
+        samplings.merge(&accumulation).unwrap();
+    }
+    assert!(samplings.pixels.iter().all(|p| p.sampling == 5));
+}
+
+#[test]
+fn test_distributed_matches_local() {
+    let (width, height, sampling) = (12, 8, 4);
+    let region = Region::new(2, 1, 9, 6);
+    let (camera, scene) = sample_scene();
+    let scene_hash = checkpoint::scene_hash(&scene, &camera);
+
+    let mut local = AccumulationBuffer::with_region(width, height, region);
+    let mut imgbuf = ImageBuffer::new(region.width, region.height);
+    PathTracingRenderer::new(sampling, filter::identity_filter, tonemap::none).accumulate(
+        &scene,
+        &camera,
+        &mut local,
+        &mut imgbuf,
+    );
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let tiling = Tiling {
+        size: 4,
+        order: TileOrder::Hilbert,
+    };
+    let jobs = distributed::split_jobs(width, height, region, Some(tiling), sampling, 3);
+    let coordinator = thread::spawn(move || {
+        let accumulation = AccumulationBuffer::with_region(width, height, region);
+        distributed::serve(
+            listener,
+            scene_hash,
+            accumulation,
+            jobs,
+            distributed::DEFAULT_WORKER_TIMEOUT,
+        )
+        .unwrap()
+    });
+
+    let workers: Vec<_> = (0..2)
+        .map(|_| {
+            thread::spawn(move || {
+                let (camera, scene) = sample_scene();
+                distributed::work(address, &scene, &camera, scene_hash, |job| {
+                    let mut renderer = PathTracingRenderer::new(
+                        job.sampling,
+                        filter::identity_filter,
+                        tonemap::none,
+                    );
+                    renderer.sampling_offset = job.sampling_offset;
+                    renderer
+                })
+                .unwrap()
+            })
+        })
+        .collect();
+    let num_of_job: u32 = workers.into_iter().map(|w| w.join().unwrap()).sum();
+    let distributed = coordinator.join().unwrap();
+
+    assert_eq!(num_of_job, 6 * 2);
+    assert_eq!(distributed.region, region);
+    for (a, b) in local.pixels.iter().zip(&distributed.pixels) {
+        assert_eq!(a.sampling, b.sampling);
+        assert!((a.color - b.color).length() <= 1e-9 * (1.0 + a.color.length()));
+    }
+}
+
+#[test]
+fn test_worker_with_different_scene_is_rejected() {
+    let (camera, scene) = sample_scene();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let region = Region::new(0, 0, 4, 4);
+    let jobs = distributed::split_jobs(4, 4, region, None, 1, 1);
+    let coordinator = thread::spawn(move || {
+        distributed::serve(
+            listener,
+            1,
+            AccumulationBuffer::new(4, 4),
+            jobs,
+            distributed::DEFAULT_WORKER_TIMEOUT,
+        )
+        .unwrap()
+    });
+
+    let rejected = distributed::work(address, &scene, &camera, 2, |job| {
+        PathTracingRenderer::new(job.sampling, filter::identity_filter, tonemap::none)
+    });
+    assert!(rejected.is_err());
+
+    let done = distributed::work(address, &scene, &camera, 1, |job| {
+        PathTracingRenderer::new(job.sampling, filter::identity_filter, tonemap::none)
+    });
+    assert_eq!(done.unwrap(), 1);
+    assert_eq!(coordinator.join().unwrap().max_sampling(), 1);
+}
+
+// Worker that passes the handshake and reads its first job, but never replies to it
+fn stalled_worker(address: SocketAddr, scene_hash: u64) -> TcpStream {
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(b"FEDR").unwrap();
+    io_util::write_u32(&mut stream, 1).unwrap();
+    io_util::write_u32(&mut stream, config::SUPER_SAMPLING).unwrap();
+    io_util::write_u32(&mut stream, config::PATHTRACING_BOUNCE_LIMIT).unwrap();
+    io_util::write_u64(&mut stream, scene_hash).unwrap();
+    // Message type and the 8 fields of the job
+    let mut job = [0u8; 36];
+    stream.read_exact(&mut job).unwrap();
+    stream
+}
+
+#[test]
+fn test_timed_out_job_is_handed_out_again() {
+    let (camera, scene) = sample_scene();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let jobs = distributed::split_jobs(4, 4, Region::new(0, 0, 4, 4), None, 1, 1);
+    let coordinator = thread::spawn(move || {
+        let accumulation = AccumulationBuffer::new(4, 4);
+        distributed::serve(listener, 1, accumulation, jobs, Duration::from_millis(500))
+    });
+
+    let stalled = stalled_worker(address, 1);
+    let done = distributed::work(address, &scene, &camera, 1, |job| {
+        PathTracingRenderer::new(job.sampling, filter::identity_filter, tonemap::none)
+    });
+    assert_eq!(done.unwrap(), 1);
+    assert_eq!(coordinator.join().unwrap().unwrap().max_sampling(), 1);
+    drop(stalled);
+}
+
+#[test]
+fn test_serve_fails_when_all_workers_are_gone() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let jobs = distributed::split_jobs(4, 4, Region::new(0, 0, 4, 4), None, 2, 1);
+    let coordinator = thread::spawn(move || {
+        let accumulation = AccumulationBuffer::new(4, 4);
+        distributed::serve(listener, 1, accumulation, jobs, Duration::from_millis(500))
+    });
+
+    drop(stalled_worker(address, 1));
+    assert!(coordinator.join().unwrap().is_err());
+}
+
+#[test]
+fn test_new_worker_takes_over_after_all_are_gone() {
+    let (camera, scene) = sample_scene();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let jobs = distributed::split_jobs(4, 4, Region::new(0, 0, 4, 4), None, 2, 1);
+    let coordinator = thread::spawn(move || {
+        let accumulation = AccumulationBuffer::new(4, 4);
+        distributed::serve(listener, 1, accumulation, jobs, Duration::from_secs(5))
+    });
+
+    // The coordinator keeps listening while no worker is connected
+    drop(stalled_worker(address, 1));
+    thread::sleep(Duration::from_millis(500));
+    let done = distributed::work(address, &scene, &camera, 1, |job| {
+        PathTracingRenderer::new(job.sampling, filter::identity_filter, tonemap::none)
+    });
+    assert_eq!(done.unwrap(), 2);
+    assert_eq!(coordinator.join().unwrap().unwrap().max_sampling(), 2);
+}
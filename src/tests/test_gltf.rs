@@ -0,0 +1,274 @@
+#![cfg(test)]
+
+use super::super::camera::{Projection, Ray};
+use super::super::color::Color;
+use super::super::config;
+use super::super::gltf::{GltfLoader, GltfScene, PUNCTUAL_LIGHT_RADIUS};
+use super::super::material::SurfaceType;
+use super::super::rayintersectable::Intersection;
+use super::super::scene::Illuminable;
+use super::super::vector::{Vector2, Vector3};
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+fn assert_near(a: Vector3, b: Vector3) {
+    assert!((a - b).length() < 1e-6, "{:?} != {:?}", a, b);
+}
+
+fn intersect(gltf: &GltfScene, origin: Vector3, direction: Vector3) -> Intersection {
+    let ray = Ray {
+        origin,
+        direction,
+        time: 0.0,
+    };
+    let (hit, intersection) = gltf.scene.intersect(&ray);
+    assert!(hit, "{:?} missed", ray.origin);
+    intersection
+}
+
+// Both files hold the same scene
+fn assert_test_scene(gltf: &GltfScene) {
+    // Four meshes and the point light, the directional light is dropped
+    assert_eq!(gltf.scene.elements().len(), 5);
+
+    // Child of a translated root, scaled, with the first row of the texture at the top
+    let textured = intersect(
+        gltf,
+        Vector3::new(-1.9, 1.9, 0.0),
+        Vector3::new(0.0, 0.0, -1.0),
+    );
+    assert!((textured.distance - 5.0).abs() < 1e-9);
+    assert!((textured.uv - Vector2::new(0.025, 0.975)).length() < 1e-6);
+    assert!(matches!(textured.material.surface, SurfaceType::Diffuse));
+    assert!((textured.material.roughness - 0.49).abs() < 1e-9);
+    let right = intersect(
+        gltf,
+        Vector3::new(1.9, 0.0, 0.0),
+        Vector3::new(0.0, 0.0, -1.0),
+    );
+    // The ramp is black on the left and white on the right, times the base color factor
+    assert!(textured.material.albedo.x < 0.01);
+    assert!(right.material.albedo.x > 0.4 && right.material.albedo.x <= 0.5);
+
+    // Rotated a quarter turn around y to face +x
+    let gold = intersect(
+        gltf,
+        Vector3::new(10.0, 0.0, -5.0),
+        Vector3::new(-1.0, 0.0, 0.0),
+    );
+    assert!((gold.position.x - 3.0).abs() < 1e-9);
+    assert_near(gold.normal, Vector3::new(1.0, 0.0, 0.0));
+    assert!(matches!(gold.material.surface, SurfaceType::GGX { f0 } if f0 == 1.0));
+    assert_near(gold.material.albedo, Color::new(1.0, 0.8, 0.3));
+
+    let glass = intersect(
+        gltf,
+        Vector3::new(0.0, 10.0, 0.0),
+        Vector3::new(0.0, 0.0, -1.0),
+    );
+    assert!(matches!(
+        glass.material.surface,
+        SurfaceType::Refraction { refractive_index } if refractive_index == 1.33
+    ));
+    let lamp = intersect(
+        gltf,
+        Vector3::new(0.0, -10.0, 0.0),
+        Vector3::new(0.0, 0.0, -1.0),
+    );
+    assert_near(lamp.material.emission, Color::new(4.0, 2.0, 1.0));
+
+    let lights = gltf.scene.emissions();
+    assert_eq!(lights.len(), 1);
+    let surface = lights[0].sample_on_surface((0.3, 0.6)).unwrap();
+    let distance = (surface.position - Vector3::new(0.0, 3.0, -5.0)).length();
+    assert!((distance - PUNCTUAL_LIGHT_RADIUS).abs() <= config::OFFSET + 1e-9);
+
+    assert_eq!(gltf.cameras.len(), 2);
+    let perspective = &gltf.cameras[0];
+    assert_near(perspective.position, Vector3::new(0.0, 1.0, 0.0));
+    assert_near(perspective.forward, Vector3::new(0.0, 0.0, -1.0));
+    // The vertical field of view spans the shorter side of a landscape image
+    let top = perspective.ray(&Vector2::new(0.0, 1.0)).unwrap();
+    assert!((top.direction.y.atan2(-top.direction.z) - 0.4).abs() < 1e-9);
+
+    // Looking down with a column major matrix
+    let top_view = &gltf.cameras[1];
+    assert!(matches!(
+        top_view.projection,
+        Projection::Orthographic { .. }
+    ));
+    assert_near(top_view.position, Vector3::new(0.0, 5.0, 0.0));
+    assert_near(top_view.forward, Vector3::new(0.0, -1.0, 0.0));
+    let corner = top_view.ray(&Vector2::new(0.0, 1.0)).unwrap();
+    assert!(((corner.origin - top_view.position).length() - 1.5).abs() < 1e-9);
+}
+
+#[test]
+fn test_gltf_with_embedded_buffer_and_external_image() {
+    let gltf = GltfLoader::load("resources/models/test/scene.gltf", 4.0 / 3.0).unwrap();
+    assert_test_scene(&gltf);
+}
+
+#[test]
+fn test_glb_with_binary_chunk() {
+    let gltf = GltfLoader::load("resources/models/test/scene.glb", 4.0 / 3.0).unwrap();
+    assert_test_scene(&gltf);
+}
+
+#[test]
+fn test_roughness_texture_is_sampled_as_data() {
+    let text = fs::read_to_string("resources/models/test/scene.gltf").unwrap();
+    let mut root: Value = serde_json::from_str(&text).unwrap();
+    root["materials"][0]["pbrMetallicRoughness"]["metallicRoughnessTexture"] =
+        serde_json::json!({"index": 0});
+    let bytes = serde_json::to_vec(&root).unwrap();
+    let gltf = GltfLoader::parse(&bytes, Path::new("resources/models/test"), 4.0 / 3.0).unwrap();
+
+    // The same gray ramp as the base color, without its sRGB curve, squared into GGX alpha
+    let middle = intersect(
+        &gltf,
+        Vector3::new(0.0, 0.0, 0.0),
+        Vector3::new(0.0, 0.0, -1.0),
+    );
+    let stored = (middle.material.albedo.x / 0.5).powf(1.0 / config::GAMMA_FACTOR);
+    assert!(stored > 0.2 && stored < 0.8, "{}", stored);
+    let alpha = 0.7 * stored;
+    assert!(
+        (middle.material.roughness - alpha * alpha).abs() < 0.01,
+        "{}",
+        middle.material.roughness
+    );
+}
+
+#[test]
+fn test_nodes_share_meshes_and_inherit_transforms() {
+    let text = r#"{"scenes": [{"nodes": [0]}],
+        "nodes": [{"translation": [0, 0, -2], "children": [1, 2]},
+            {"mesh": 0}, {"translation": [3, 0, 0], "scale": [2, 2, 2], "mesh": 0}],
+        "meshes": [{"primitives": [{"attributes": {"POSITION": 0}}]}],
+        "accessors": [{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3"}],
+        "bufferViews": [{"buffer": 0, "byteLength": 36}],
+        "buffers": [{"uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAA", "byteLength": 36}]}"#;
+    let gltf = GltfLoader::parse(text.as_bytes(), Path::new(""), 1.0).unwrap();
+    assert_eq!(gltf.scene.elements().len(), 2);
+
+    let down = Vector3::new(0.0, 0.0, -1.0);
+    let first = intersect(&gltf, Vector3::new(0.2, 0.2, 0.0), down);
+    assert!((first.distance - 2.0).abs() < 1e-9);
+    let second = intersect(&gltf, Vector3::new(4.5, 0.2, 0.0), down);
+    assert_near(second.position, Vector3::new(4.5, 0.2, -2.0));
+    let (hit, _) = gltf.scene.intersect(&Ray {
+        origin: Vector3::new(1.5, 0.2, 0.0),
+        direction: down,
+        time: 0.0,
+    });
+    assert!(!hit);
+}
+
+#[test]
+fn test_spot_lights_and_metals() {
+    let text = r#"{"nodes": [{"mesh": 0, "translation": [0, 0, -2]}, {"mesh": 1, "translation": [2, 0, -2]},
+            {"translation": [0, 0, 5], "extensions": {"KHR_lights_punctual": {"light": 0}}},
+            {"extensions": {"KHR_lights_punctual": {"light": 1}}}],
+        "extensions": {"KHR_lights_punctual": {"lights": [
+            {"type": "spot", "intensity": 2, "spot": {"outerConeAngle": 0.5}},
+            {"type": "directional"}]}},
+        "materials": [{"pbrMetallicRoughness": {"baseColorFactor": [0.5, 0.25, 0, 1], "roughnessFactor": 0.5}},
+            {"pbrMetallicRoughness": {"metallicFactor": 0.3}}],
+        "meshes": [{"primitives": [{"attributes": {"POSITION": 0}, "material": 0}]},
+            {"primitives": [{"attributes": {"POSITION": 0}, "material": 1}]}],
+        "accessors": [{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3"}],
+        "bufferViews": [{"buffer": 0, "byteLength": 36}],
+        "buffers": [{"uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAA", "byteLength": 36}]}"#;
+    let gltf = GltfLoader::parse(text.as_bytes(), Path::new(""), 1.0).unwrap();
+    // The two meshes and the spot, the directional light is left out
+    assert_eq!(gltf.scene.elements().len(), 3);
+
+    // Metals reflect the base color at normal incidence, partly metallic materials are rounded
+    let down = Vector3::new(0.0, 0.0, -1.0);
+    let metal = intersect(&gltf, Vector3::new(0.2, 0.2, 0.0), down);
+    assert!(matches!(metal.material.surface, SurfaceType::GGX { f0 } if f0 == 0.5));
+    assert_near(metal.material.albedo, Color::new(1.0, 0.5, 0.0));
+    // Perceptual roughness is squared into GGX alpha
+    assert!((metal.material.roughness - 0.25).abs() < 1e-9);
+    let dielectric = intersect(&gltf, Vector3::new(2.2, 0.2, 0.0), down);
+    assert!(matches!(dielectric.material.surface, SurfaceType::Diffuse));
+
+    // The spot is a disk facing -z that lights its outer cone and nothing outside it
+    let lights = gltf.scene.emissions();
+    assert_eq!(lights.len(), 1);
+    let spot = lights[0];
+    let radius2 = PUNCTUAL_LIGHT_RADIUS * PUNCTUAL_LIGHT_RADIUS;
+    assert_near(
+        spot.material().emission.color,
+        Color::all_of(2.0 / (config::PI * radius2)),
+    );
+    let surface = spot.sample_on_surface((0.3, 0.6)).unwrap();
+    assert!((surface.position.z - 5.0).abs() < 1e-9);
+    assert_near(surface.normal, down);
+    let toward = |angle: f64| {
+        spot.emission_toward(
+            &Intersection::empty(),
+            &Vector3::new(angle.sin(), 0.0, -angle.cos()),
+        )
+    };
+    assert_eq!(toward(0.0), 1.0);
+    assert_eq!(toward(0.45), 1.0);
+    assert_eq!(toward(0.55), 0.0);
+}
+
+#[test]
+fn test_malformed_gltf() {
+    let error = |text: &str| {
+        let e = GltfLoader::parse(text.as_bytes(), Path::new(""), 1.0)
+            .err()
+            .unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        e.to_string()
+    };
+
+    assert!(error("{\"asset\": }").contains("line 1 column 11"));
+    // Nesting too deep for the parser is an error rather than a stack overflow
+    assert!(error(&"[".repeat(100_000)).contains("recursion limit"));
+    assert!(error(r#"{"buffers": [{"uri": "data:;base64,A*=="}]}"#).starts_with("buffers[0]: "));
+    let e = error(
+        r#"{"nodes": [{"mesh": 0}], "meshes": [{"primitives": [{"attributes": {"POSITION": 0}}]}],
+            "accessors": [{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3"}],
+            "bufferViews": [{"buffer": 0, "byteLength": 24}],
+            "buffers": [{"uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA", "byteLength": 24}]}"#,
+    );
+    assert_eq!(
+        e,
+        "nodes[0]: meshes[0]: primitives[0]: accessors[0]: 3 elements are out of bufferViews[0]"
+    );
+    // Offsets near the end of the address space are out of range rather than wrapping
+    let e = error(&format!(
+        r#"{{"images": [{{"bufferView": 0}}],
+            "bufferViews": [{{"buffer": 0, "byteOffset": {}, "byteLength": 2}}],
+            "buffers": [{{"uri": "data:;base64,AAAA", "byteLength": 3}}]}}"#,
+        usize::MAX
+    ));
+    assert_eq!(e, "images[0]: bufferViews[0] is out of its buffer");
+    let e = error(
+        r#"{"nodes": [{"mesh": 0}], "meshes": [{"primitives": [{"attributes": {"POSITION": 0}}]}],
+            "accessors": [{"bufferView": 0, "byteOffset": 18446744073709551615, "componentType": 5126, "count": 1, "type": "VEC3"}],
+            "bufferViews": [{"buffer": 0, "byteLength": 3}],
+            "buffers": [{"uri": "data:;base64,AAAA", "byteLength": 3}]}"#,
+    );
+    assert!(e.ends_with("1 elements are out of bufferViews[0]"), "{}", e);
+    assert!(
+        error(r#"{"scenes": [{"nodes": [0]}], "nodes": [{"children": [0]}]}"#)
+            .contains("own ancestor")
+    );
+    assert!(error(r#"{"nodes": [{"camera": 0}]}"#).contains("cameras[0] is not defined"));
+    assert!(
+        error(r#"{"buffers": [{"uri": "missing.bin"}]}"#).starts_with("buffers[0]: missing.bin")
+    );
+
+    let e = GltfLoader::load("resources/models/test/missing.gltf", 1.0)
+        .err()
+        .unwrap();
+    assert_eq!(e.kind(), io::ErrorKind::NotFound);
+}
@@ -0,0 +1,170 @@
+#![cfg(test)]
+
+use super::super::color::Color;
+use super::super::instance::Instance;
+use super::super::loader::ObjLoader;
+use super::super::material::Material;
+use super::super::matrix::Matrix44;
+use super::super::mesh::BvhMesh;
+use super::super::rayintersectable::Intersectable;
+use super::super::vector::Vector3;
+use super::fixtures::{diffuse, intersect};
+use std::sync::Arc;
+
+const BUNNY: &str = "resources/models/bunny/bunny_face1000.obj";
+
+fn bunny(matrix: Matrix44) -> BvhMesh {
+    BvhMesh::from(
+        ObjLoader::load(BUNNY, matrix, diffuse(Color::one()))
+            .unwrap()
+            .with_generated_normals(),
+    )
+}
+
+#[test]
+fn test_instance_hits_as_the_transformed_mesh() {
+    let matrix = Matrix44::translate(0.5, -0.3, 2.0)
+        * Matrix44::rotate_y(0.7)
+        * Matrix44::rotate_x(-0.4)
+        * Matrix44::scale_linear(1.7);
+    let baked = bunny(matrix);
+    let instance = Instance::new(
+        Arc::new(bunny(Matrix44::identity())),
+        matrix,
+        Arc::new(diffuse(Color::one())),
+    );
+
+    let aabb = baked.aabb();
+    let center = (aabb.min + aabb.max) * 0.5;
+    let size = aabb.max - aabb.min;
+    let mut hits = 0;
+    for i in 0..8 {
+        for j in 0..8 {
+            let target = center
+                + Vector3::new(
+                    size.x * (i as f64 / 7.0 - 0.5),
+                    size.y * (j as f64 / 7.0 - 0.5),
+                    0.0,
+                );
+            let origin = target + Vector3::new(0.3, 0.2, 5.0);
+            let expected = intersect(&baked, origin, target - origin);
+            let actual = intersect(&instance, origin, target - origin);
+            assert_eq!(expected.is_some(), actual.is_some());
+            if let (Some(expected), Some(actual)) = (expected, actual) {
+                assert!((expected.distance - actual.distance).abs() < 1e-9);
+                assert!((expected.position - actual.position).length() < 1e-9);
+                assert!((expected.normal - actual.normal).length() < 1e-6);
+                hits += 1;
+            }
+        }
+    }
+    assert!(hits > 10);
+
+    // Instances are not lights, so they have nothing to sample rather than panicking
+    assert!(instance.sample_on_surface((0.5, 0.5)).is_none());
+    assert!(instance.sample_from(&Vector3::zero(), (0.5, 0.5)).is_none());
+}
+
+#[test]
+fn test_instances_share_the_mesh_with_their_own_materials() {
+    let mesh = Arc::new(BvhMesh::from(
+        ObjLoader::load(
+            "resources/models/test/two_materials.obj",
+            Matrix44::identity(),
+            diffuse(Color::one()),
+        )
+        .unwrap(),
+    ));
+    let plain = Arc::new(BvhMesh::from(
+        ObjLoader::load(
+            "resources/models/test/quad_vn_vt.obj",
+            Matrix44::identity(),
+            diffuse(Color::one()),
+        )
+        .unwrap(),
+    ));
+    let blue = Instance::new(
+        plain.clone(),
+        Matrix44::translate(0.0, 0.0, -1.0),
+        Arc::new(diffuse(Color::new(0.0, 0.0, 1.0))),
+    );
+    let green = Instance::new(
+        plain.clone(),
+        Matrix44::translate(5.0, 0.0, -1.0),
+        Arc::new(diffuse(Color::new(0.0, 1.0, 0.0))),
+    );
+    let library = Instance::new(
+        mesh.clone(),
+        Matrix44::identity(),
+        Arc::new(diffuse(Color::new(0.0, 1.0, 0.0))),
+    );
+    assert_eq!(Arc::strong_count(&plain), 3);
+    assert_eq!(Arc::strong_count(&mesh), 2);
+
+    let down = |element: &dyn Intersectable, x: f64| {
+        intersect(
+            element,
+            Vector3::new(x, 0.5, 1.0),
+            Vector3::new(0.0, 0.0, -1.0),
+        )
+        .unwrap()
+    };
+    let hit = down(&blue, 0.5);
+    assert_eq!(
+        blue.material_at(&hit).albedo.color,
+        Color::new(0.0, 0.0, 1.0)
+    );
+    let hit = down(&green, 5.5);
+    assert_eq!(
+        green.material_at(&hit).albedo.color,
+        Color::new(0.0, 1.0, 0.0)
+    );
+    assert!(intersect(
+        &green,
+        Vector3::new(0.5, 0.5, 1.0),
+        Vector3::new(0.0, 0.0, -1.0)
+    )
+    .is_none());
+
+    // Faces with materials of their own keep them
+    let hit = down(&library, 0.5);
+    assert_eq!(
+        library.material_at(&hit).albedo.color,
+        Color::new(0.8, 0.1, 0.1)
+    );
+}
+
+#[test]
+fn test_forest_of_instances() {
+    let tree = Arc::new(bunny(Matrix44::identity()));
+    let materials: Vec<Arc<Material>> = (0..4)
+        .map(|i| Arc::new(diffuse(Color::all_of(i as f64 / 4.0))))
+        .collect();
+    let spacing = 2.0;
+    let trees: Vec<Instance> = (0..10_000)
+        .map(|i| {
+            let (x, z) = ((i % 100) as f64 * spacing, (i / 100) as f64 * spacing);
+            Instance::new(
+                tree.clone(),
+                Matrix44::translate(x, 0.0, z) * Matrix44::rotate_y(i as f64),
+                materials[i % 4].clone(),
+            )
+        })
+        .collect();
+    assert_eq!(Arc::strong_count(&tree), 10_001);
+
+    // Straight down onto the center of each tree, only its own instance is hit
+    let aabb = tree.aabb();
+    let center_y = (aabb.min.y + aabb.max.y) * 0.5;
+    for &i in &[0, 57, 4321, 9999] {
+        let position = *trees[i].matrix() * Vector3::new(0.0, center_y, 0.0);
+        let origin = position + Vector3::new(0.0, 10.0, 0.0);
+        let hits: Vec<usize> = trees
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| intersect(*t, origin, position - origin).is_some())
+            .map(|(j, _)| j)
+            .collect();
+        assert_eq!(hits, [i]);
+    }
+}
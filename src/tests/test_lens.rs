@@ -0,0 +1,182 @@
+#![cfg(test)]
+
+use super::super::camera::{Camera, FieldOfView, LensShape};
+use super::super::lens::{parse_lens_table, read_lens_table, LensElement, LensSystem};
+use super::super::vector::{Vector2, Vector3};
+use rand::{SeedableRng, StdRng};
+
+// Biconvex lens of focal length 50 mm, thin and stopped down to near paraxial
+const THIN_LENS: &str = "
+# radius thickness ior aperture
+50.0   0.001  1.5  2.0
+-50.0  0.0    1.0  2.0
+";
+
+fn thin_lens_system(focus_distance: f64) -> LensSystem {
+    LensSystem::new(
+        parse_lens_table(THIN_LENS).unwrap(),
+        36.0,
+        24.0,
+        1.5,
+        1.0,
+        focus_distance,
+    )
+    .unwrap()
+}
+
+// Point where the ray crosses the plane z = z
+fn at_depth(origin: Vector3, direction: Vector3, z: f64) -> Vector3 {
+    origin + direction * ((z - origin.z) / direction.z)
+}
+
+#[test]
+fn test_parse_lens_table() {
+    let elements = parse_lens_table(THIN_LENS).unwrap();
+    assert_eq!(
+        elements[0],
+        LensElement {
+            curvature_radius: 50.0,
+            thickness: 0.001,
+            ior: 1.5,
+            aperture_radius: 1.0,
+        }
+    );
+    assert_eq!(elements.len(), 2);
+
+    assert!(parse_lens_table("50.0 1.0 1.5").is_err());
+    assert!(parse_lens_table("50.0 1.0 glass 2.0").is_err());
+    assert!(parse_lens_table("# only a comment").is_err());
+    assert_eq!(
+        read_lens_table("resources/lenses/dgauss.50mm.dat")
+            .unwrap()
+            .len(),
+        11
+    );
+}
+
+#[test]
+fn test_thin_lens_limit_focuses_like_thin_lens_equation() {
+    let lens = thin_lens_system(1000.0);
+
+    // 1 / object + 1 / image = 1 / focal length, with object + image = 1000
+    let image = (1000.0 - (1000.0f64 * 1000.0 - 4.0 * 1000.0 * 50.0).sqrt()) * 0.5;
+    assert!((lens.film_distance() - image).abs() < 1e-2);
+
+    // Rays from a film point through the whole pupil meet on the plane in focus, where the
+    // magnification is object / image. Off axis, spherical surfaces distort a little
+    for coord in &[Vector2::new(0.0, 0.0), Vector2::new(0.5, -0.8)] {
+        let expected = Vector3::new(coord.x * 12.0, coord.y * 12.0, 0.0) * (1000.0 - image) / image;
+        for uv in &[(0.3, 0.2), (0.5, 0.5), (0.7, 0.6), (0.4, 0.75)] {
+            let (origin, direction, _) = lens.sample_ray(coord, *uv).unwrap();
+            let p = at_depth(origin, direction, -1000.0);
+            assert!((p.x - expected.x).abs() < 0.5, "{:?} != {:?}", p, expected);
+            assert!((p.y - expected.y).abs() < 0.5, "{:?} != {:?}", p, expected);
+        }
+    }
+}
+
+#[test]
+fn test_lens_camera_matches_thin_lens_camera() {
+    let focus_distance = 1000.0;
+    let lens = thin_lens_system(focus_distance);
+    let image = lens.film_distance();
+    let y_up = Vector3::new(0.0, 1.0, 0.0);
+    let lens_camera =
+        Camera::lens_system(Vector3::zero(), Vector3::new(0.0, 0.0, -1.0), y_up, lens);
+    // Pinhole at the lens with the equivalent field of view
+    let thin_camera = Camera::new(
+        Vector3::new(0.0, 0.0, -image),
+        Vector3::new(0.0, 0.0, -1000.0),
+        y_up,
+        FieldOfView::FocalLength {
+            focal_length: image,
+            sensor_width: 36.0,
+            sensor_height: 24.0,
+        },
+        1.5,
+        LensShape::Circle,
+        2.0,
+        focus_distance - image,
+    );
+
+    let mut rng: StdRng = SeedableRng::from_seed(&[1, 2, 3][..]);
+    for coord in &[
+        Vector2::new(0.0, 0.0),
+        Vector2::new(0.8, 0.6),
+        Vector2::new(-0.3, 0.6),
+    ] {
+        let thin = thin_camera.ray(coord).unwrap();
+        let expected = at_depth(thin.origin, thin.direction, -1000.0);
+
+        let chief = lens_camera.ray(coord).unwrap();
+        assert!((chief.direction - thin.direction).length() < 1e-3);
+        // Some samples fall on the margin of the exit pupil and are blocked
+        let mut passed = 0;
+        for _ in 0..16 {
+            if let Some((ray, _)) = lens_camera.sample_ray(coord, &mut rng) {
+                let p = at_depth(ray.origin, ray.direction, -1000.0);
+                assert!((p - expected).length() < 0.5, "{:?} != {:?}", p, expected);
+                passed += 1;
+            }
+        }
+        assert!(passed > 0);
+    }
+}
+
+#[test]
+fn test_lens_weights_have_unit_exposure_at_center_and_vignette() {
+    let lens = LensSystem::new(
+        read_lens_table("resources/lenses/dgauss.50mm.dat").unwrap(),
+        36.0,
+        24.0,
+        1.5,
+        1.0,
+        5000.0,
+    )
+    .unwrap();
+
+    let exposure = |coord: Vector2| {
+        let n = 64;
+        let mut sum = 0.0;
+        for i in 0..n * n {
+            let uv = (
+                ((i % n) as f64 + 0.5) / n as f64,
+                ((i / n) as f64 + 0.5) / n as f64,
+            );
+            if let Some((_, _, weight)) = lens.sample_ray(&coord, uv) {
+                sum += weight;
+            }
+        }
+        sum / (n * n) as f64
+    };
+
+    let center = exposure(Vector2::new(0.0, 0.0));
+    assert!((center - 1.0).abs() < 0.05, "{}", center);
+    let corner = exposure(Vector2::new(1.5, 1.0));
+    assert!(corner > 0.0 && corner < 0.8, "{}", corner);
+}
+
+#[test]
+fn test_focusing_closer_narrows_field_of_view() {
+    let elements = read_lens_table("resources/lenses/dgauss.50mm.dat").unwrap();
+    let far = LensSystem::new(elements.clone(), 36.0, 24.0, 1.5, 1.0, 100_000.0).unwrap();
+    let near = LensSystem::new(elements, 36.0, 24.0, 1.5, 1.0, 500.0).unwrap();
+    assert!(near.film_distance() > far.film_distance());
+
+    let edge = Vector2::new(1.0, 0.0);
+    let (_, far_direction) = far.chief_ray(&edge).unwrap();
+    let (_, near_direction) = near.chief_ray(&edge).unwrap();
+    assert!(
+        near_direction.x.abs() / near_direction.z.abs()
+            < far_direction.x.abs() / far_direction.z.abs()
+    );
+}
+
+#[test]
+fn test_lens_without_light_is_an_error() {
+    // Closed stop between the surfaces of the thin lens
+    let closed =
+        parse_lens_table("50.0 0.001 1.5 2.0\n0.0 0.0 1.0 0.0\n-50.0 0.0 1.0 2.0").unwrap();
+    assert!(LensSystem::new(closed, 36.0, 24.0, 1.5, 1.0, 1000.0).is_err());
+    assert!(LensSystem::new(vec![], 36.0, 24.0, 1.5, 1.0, 1000.0).is_err());
+}
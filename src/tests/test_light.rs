@@ -0,0 +1,234 @@
+#![cfg(test)]
+
+use super::super::camera::Ray;
+use super::super::color::Color;
+use super::super::config;
+use super::super::light::{DiskLight, RectangleLight};
+use super::super::matrix::Matrix44;
+use super::super::rayintersectable::{Disk, Intersectable, Rectangle};
+use super::super::scene::{Illuminable, Scene, Surface};
+use super::super::transform::Transformed;
+use super::super::vector::Vector3;
+use super::fixtures::{emissive, scene};
+
+// Two by one units at y = 2 facing down
+fn softbox() -> RectangleLight {
+    RectangleLight::new(Rectangle {
+        corner: Vector3::new(-1.0, 2.0, -0.5),
+        edge_u: Vector3::new(2.0, 0.0, 0.0),
+        edge_v: Vector3::new(0.0, 0.0, 1.0),
+        material: emissive(Color::all_of(10.0)),
+    })
+}
+
+fn emission_along(scene: &Scene, origin: Vector3, direction: Vector3) -> Color {
+    let (hit, intersection) = scene.intersect(&Ray {
+        origin,
+        direction: direction.normalized(),
+        time: 0.0,
+    });
+    assert!(hit);
+    intersection.material.emission
+}
+
+// Irradiance at position on a surface facing up, estimated with a grid of samples where those
+// without a surface add nothing
+fn irradiance(position: Vector3, sample: impl Fn((f64, f64)) -> Option<Surface>) -> f64 {
+    let n = 64;
+    let mut sum = 0.0;
+    for i in 0..n {
+        for j in 0..n {
+            let surface = match sample(((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64)) {
+                Some(surface) => surface,
+                None => continue,
+            };
+            let to_light = surface.position - position;
+            let distance2 = to_light.dot(&to_light);
+            let direction = to_light / distance2.sqrt();
+            let g = direction.y.max(0.0) * surface.normal.dot(&direction).abs() / distance2;
+            sum += g / surface.pdf;
+        }
+    }
+    sum / (n * n) as f64
+}
+
+#[test]
+fn test_rectangle_lights_are_sampled_by_solid_angle() {
+    let light = softbox();
+    // Every sample covers the same solid angle, that of a rectangle seen on its axis
+    let position = Vector3::new(0.0, 0.0, 0.0);
+    let expected = 4.0 * (2.0 / (20.0 * 17f64).sqrt()).asin();
+    for &random in &[(0.1, 0.2), (0.5, 0.5), (0.9, 0.3), (0.02, 0.98)] {
+        let surface = light.sample_from(&position, random).unwrap();
+        assert!((surface.position.y - 2.0).abs() < 1e-9);
+        assert!(surface.position.x.abs() <= 1.0 + 1e-9 && surface.position.z.abs() <= 0.5 + 1e-9);
+        let to_light = surface.position - position;
+        let distance2 = to_light.dot(&to_light);
+        let cos = surface.normal.dot(&to_light).abs() / distance2.sqrt();
+        let solid_angle = cos / (distance2 * surface.pdf);
+        assert!(
+            (solid_angle - expected).abs() < 1e-9,
+            "{} != {}",
+            solid_angle,
+            expected
+        );
+    }
+
+    // Both samplings estimate the same irradiance, off axis as well
+    for &position in &[Vector3::zero(), Vector3::new(2.5, 0.5, -1.0)] {
+        let by_area = irradiance(position, |random| light.sample_on_surface(random));
+        let by_solid_angle = irradiance(position, |random| light.sample_from(&position, random));
+        assert!(
+            (by_area - by_solid_angle).abs() < 1e-3 * by_area,
+            "{} != {}",
+            by_area,
+            by_solid_angle
+        );
+    }
+
+    // Points in the plane of the light fall back to the area
+    let beside = light
+        .sample_from(&Vector3::new(5.0, 2.0, 0.0), (0.5, 0.5))
+        .unwrap();
+    assert!((beside.pdf - 0.5).abs() < 1e-12);
+}
+
+#[test]
+fn test_disk_lights_are_sampled_by_solid_angle() {
+    // Radius 1 at y = 2 facing down
+    let light = DiskLight::new(Disk {
+        center: Vector3::new(0.0, 2.0, 0.0),
+        normal: Vector3::new(0.0, -1.0, 0.0),
+        radius: 1.0,
+        material: emissive(Color::all_of(10.0)),
+    });
+    let below = Vector3::zero();
+    let surface = light.sample_from(&below, (0.1, 0.3)).unwrap();
+    assert!((surface.position.y - 2.0).abs() < 1e-9);
+    assert!(surface.position.x.hypot(surface.position.z) <= 1.0 + 1e-9);
+
+    // A disk seen on its axis gives an irradiance of PI r^2 / (r^2 + d^2)
+    let expected = config::PI / 5.0;
+    let by_solid_angle = irradiance(below, |random| light.sample_from(&below, random));
+    assert!(
+        (by_solid_angle - expected).abs() < 1e-2 * expected,
+        "{} != {}",
+        by_solid_angle,
+        expected
+    );
+    let off_axis = Vector3::new(1.5, 0.5, -1.0);
+    let by_area = irradiance(off_axis, |random| light.sample_on_surface(random));
+    let by_solid_angle = irradiance(off_axis, |random| light.sample_from(&off_axis, random));
+    assert!(
+        (by_area - by_solid_angle).abs() < 1e-2 * by_area,
+        "{} != {}",
+        by_area,
+        by_solid_angle
+    );
+
+    // Points within reach of the rim fall back to the area
+    let close = light
+        .sample_from(&Vector3::new(0.5, 2.0, 0.0), (0.5, 0.5))
+        .unwrap();
+    assert!((close.pdf - config::PI.recip()).abs() < 1e-12);
+}
+
+#[test]
+fn test_area_lights_emit_from_the_front() {
+    let scene = scene(vec![
+        Box::new(softbox()),
+        Box::new(DiskLight::new(Disk {
+            center: Vector3::new(5.0, 0.0, 0.0),
+            normal: Vector3::new(-1.0, 0.0, 0.0),
+            radius: 1.0,
+            material: emissive(Color::all_of(3.0)),
+        })),
+    ]);
+    assert_eq!(scene.emissions().len(), 2);
+
+    let down = Vector3::new(0.0, -1.0, 0.0);
+    assert_eq!(
+        emission_along(&scene, Vector3::zero(), -down),
+        Color::all_of(10.0)
+    );
+    assert_eq!(
+        emission_along(&scene, Vector3::new(0.0, 5.0, 0.0), down),
+        Color::zero()
+    );
+    let side = Vector3::new(1.0, 0.0, 0.0);
+    assert_eq!(
+        emission_along(&scene, Vector3::zero(), side),
+        Color::all_of(3.0)
+    );
+    assert_eq!(
+        emission_along(&scene, Vector3::new(9.0, 0.0, 0.0), -side),
+        Color::zero()
+    );
+}
+
+#[test]
+fn test_spread_narrows_the_beam() {
+    let scene = scene(vec![Box::new(softbox().with_spread(config::PI / 3.0))]);
+    let target = Vector3::new(0.0, 2.0, 0.0);
+    // Within 30 degrees of the normal the radiance is that of the diffuse light, cut off
+    // outside rather than concentrated
+    let near = Vector3::new(0.0, -2.0, (0.4f64).tan() * 2.0);
+    assert_eq!(
+        emission_along(&scene, target + near, -near),
+        Color::all_of(10.0)
+    );
+    let far = Vector3::new(0.0, -2.0, (0.6f64).tan() * 2.0);
+    assert_eq!(emission_along(&scene, target + far, -far), Color::zero());
+}
+
+#[test]
+fn test_lights_hidden_from_the_camera() {
+    let scene = scene(vec![
+        Box::new(softbox().with_camera_visible(false)),
+        Box::new(Rectangle {
+            corner: Vector3::new(-5.0, 4.0, -5.0),
+            edge_u: Vector3::new(10.0, 0.0, 0.0),
+            edge_v: Vector3::new(0.0, 0.0, 10.0),
+            material: emissive(Color::zero()),
+        }),
+    ]);
+    let ray = Ray {
+        origin: Vector3::zero(),
+        direction: Vector3::new(0.0, 1.0, 0.0),
+        time: 0.0,
+    };
+    let (_, seen) = scene.intersect_from_camera(&ray);
+    assert!((seen.distance - 4.0).abs() < 1e-9);
+    let (_, lit) = scene.intersect(&ray);
+    assert!((lit.distance - 2.0).abs() < 1e-9);
+    assert_eq!(lit.material.emission, Color::all_of(10.0));
+    assert_eq!(scene.emissions().len(), 1);
+}
+
+#[test]
+fn test_transformed_lights() {
+    // Turned to face +x from x = -2
+    let light = Transformed::new(
+        Box::new(softbox().with_spread(config::PI / 2.0)),
+        Matrix44::rotate_z(config::PI / 2.0),
+    );
+    let position = Vector3::new(1.0, 0.2, 0.1);
+    let surface = light.sample_from(&position, (0.3, 0.7)).unwrap();
+    assert!((surface.position.x + 2.0).abs() < 1e-9);
+    assert!((surface.normal - Vector3::new(1.0, 0.0, 0.0)).length() < 1e-9);
+    let by_area = irradiance(position, |random| light.sample_on_surface(random));
+    let by_solid_angle = irradiance(position, |random| light.sample_from(&position, random));
+    assert!((by_area - by_solid_angle).abs() < 1e-3 * by_area);
+
+    let scene = scene(vec![Box::new(light)]);
+    let toward = Vector3::new(-1.0, 0.0, 0.0);
+    assert_eq!(
+        emission_along(&scene, Vector3::zero(), toward),
+        Color::all_of(10.0)
+    );
+    let slanted = Vector3::new(-1.0, 0.0, 1.5);
+    assert_eq!(
+        emission_along(&scene, Vector3::new(0.0, 0.0, -3.0), slanted),
+        Color::zero()
+    );
+}
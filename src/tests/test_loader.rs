@@ -0,0 +1,372 @@
+#![cfg(test)]
+
+use super::super::camera::Ray;
+use super::super::color::{gamma_to_linear, Color};
+use super::super::loader::{MtlLoader, ObjLoader, PlyLoader, StlLoader};
+use super::super::material::SurfaceType;
+use super::super::matrix::Matrix44;
+use super::super::mesh::{triangulate_polygon, Mesh};
+use super::super::rayintersectable::{Intersectable, Intersection};
+use super::super::vector::{Vector2, Vector3};
+use super::fixtures::material;
+use std::io;
+use std::path::Path;
+
+fn parse(obj: &str) -> io::Result<Mesh> {
+    ObjLoader::parse(obj.as_bytes(), Matrix44::identity(), material())
+}
+
+fn error_message(obj: &str) -> String {
+    match parse(obj) {
+        Ok(_) => panic!("Parsed malformed OBJ:\n{}", obj),
+        Err(e) => {
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+            e.to_string()
+        }
+    }
+}
+
+fn area(points: &[Vector3], triangles: &[[usize; 3]]) -> f64 {
+    triangles
+        .iter()
+        .map(|t| {
+            (points[t[1]] - points[t[0]])
+                .cross(&(points[t[2]] - points[t[0]]))
+                .length()
+                * 0.5
+        })
+        .sum()
+}
+
+#[test]
+fn test_obj_accepts_any_whitespace_and_comments() {
+    let mesh = parse("v 0 0 0\nv\t1  0 0 # right\n  v 0 1 0 1.0\r\n\nf 1\t2   3\n").unwrap();
+    assert_eq!(mesh.vertexes.len(), 3);
+    assert_eq!(mesh.faces.len(), 1);
+    assert_eq!(
+        (mesh.faces[0].v0, mesh.faces[0].v1, mesh.faces[0].v2),
+        (0, 1, 2)
+    );
+}
+
+#[test]
+fn test_obj_relative_indices() {
+    let mesh = parse(
+        "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 0 1\nf -3/-3 -2/-2 -1/-1\n\
+         v 5 5 5\nf 1 -3 -2\n",
+    )
+    .unwrap();
+    assert_eq!(mesh.faces[0].uvs, Some([0, 1, 2]));
+    assert_eq!(
+        (mesh.faces[1].v0, mesh.faces[1].v1, mesh.faces[1].v2),
+        (0, 1, 2)
+    );
+}
+
+#[test]
+fn test_obj_polygons_are_triangulated() {
+    let mesh = parse("v 0 0 0\nv 1 0 0\nv 2 1 0\nv 1 2 0\nv 0 1 0\nf 1 2 3 4 5\n").unwrap();
+    assert_eq!(mesh.faces.len(), 3);
+
+    // Concave L shape, a fan from the first vertex would cover the notch
+    let l_shape = [
+        Vector3::new(2.0, 0.0, 0.0),
+        Vector3::new(2.0, 1.0, 0.0),
+        Vector3::new(1.0, 1.0, 0.0),
+        Vector3::new(1.0, 2.0, 0.0),
+        Vector3::new(0.0, 2.0, 0.0),
+        Vector3::new(0.0, 0.0, 0.0),
+    ];
+    let triangles = triangulate_polygon(&l_shape);
+    assert_eq!(triangles.len(), 4);
+    assert!((area(&l_shape, &triangles) - 3.0).abs() < 1e-9);
+    // Winding is kept
+    for t in &triangles {
+        let normal = (l_shape[t[1]] - l_shape[t[0]]).cross(&(l_shape[t[2]] - l_shape[t[0]]));
+        assert!(normal.z > 0.0);
+    }
+}
+
+#[test]
+fn test_malformed_obj_files_report_lines() {
+    let e = ObjLoader::load(
+        "resources/models/test/malformed_index.obj",
+        Matrix44::identity(),
+        material(),
+    )
+    .err()
+    .unwrap();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    assert!(
+        e.to_string().contains("malformed_index.obj: line 5"),
+        "{}",
+        e
+    );
+
+    let e = ObjLoader::load(
+        "resources/models/test/malformed_vertex.obj",
+        Matrix44::identity(),
+        material(),
+    )
+    .err()
+    .unwrap();
+    assert!(e.to_string().contains("line 5"), "{}", e);
+
+    let e = ObjLoader::load(
+        "resources/models/test/missing.obj",
+        Matrix44::identity(),
+        material(),
+    )
+    .err()
+    .unwrap();
+    assert_eq!(e.kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+fn test_malformed_obj_lines() {
+    let vertexes = "v 0 0 0\nv 1 0 0\nv 0 1 0\n";
+    assert!(error_message("v 0 0\n").starts_with("line 1"));
+    assert!(error_message(&format!("{}f 1 2\n", vertexes)).starts_with("line 4"));
+    assert!(error_message(&format!("{}f 0 1 2\n", vertexes)).contains("vertex index 0"));
+    assert!(error_message(&format!("{}f 1 2 -4\n", vertexes)).contains("vertex index -4"));
+    assert!(error_message(&format!("{}f 1/1 2/1 3/1\n", vertexes)).contains("texture coordinate"));
+    assert!(error_message(&format!("{}f 1//x 2 3\n", vertexes)).contains("normal index x"));
+    assert!(error_message(&format!("{}f 1/1/1/1 2 3\n", vertexes)).contains("1/1/1/1"));
+    assert!(error_message("vn 0 0 1 0\n").starts_with("line 1"));
+}
+
+#[test]
+fn test_mtl_statements_map_to_materials() {
+    let materials = MtlLoader::load(Path::new("resources/models/test/materials.mtl")).unwrap();
+    let names: Vec<&str> = materials.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["red", "glass", "metal", "lamp", "bumpy"]);
+    let material = |name: &str| &materials.iter().find(|(n, _)| n == name).unwrap().1;
+
+    let red = material("red");
+    assert!(matches!(red.surface, SurfaceType::Diffuse));
+    assert_eq!(red.albedo.color, Color::new(0.8, 0.1, 0.1));
+
+    let glass = material("glass");
+    assert!(matches!(
+        glass.surface,
+        SurfaceType::Refraction { refractive_index } if refractive_index == 1.45
+    ));
+
+    // Brighter specular than diffuse, tinted by the specular color
+    let metal = material("metal");
+    assert!(matches!(metal.surface, SurfaceType::GGX { f0 } if f0 == 0.9));
+    assert!((metal.albedo.color - Color::new(1.0, 0.6 / 0.9, 0.3 / 0.9)).length() < 1e-9);
+    assert!((metal.roughness.color.x - 0.02f64.sqrt()).abs() < 1e-9);
+
+    assert_eq!(material("lamp").emission.color, Color::new(10.0, 8.0, 6.0));
+
+    let bumpy = material("bumpy");
+    assert!(bumpy.albedo.image_texture.is_some());
+    assert!(bumpy.bump.image_texture.is_some());
+    assert_eq!(bumpy.bump.color, Color::all_of(0.1));
+}
+
+#[test]
+fn test_obj_faces_use_materials_of_usemtl() {
+    let mesh = ObjLoader::load(
+        "resources/models/test/two_materials.obj",
+        Matrix44::identity(),
+        material(),
+    )
+    .unwrap();
+    assert_eq!(mesh.materials.len(), 5);
+    let face_materials: Vec<Option<usize>> = mesh.faces.iter().map(|f| f.material).collect();
+    assert_eq!(face_materials, [Some(0), Some(0), Some(4), Some(4)]);
+
+    let intersect_down = |x: f64| {
+        let ray = Ray {
+            origin: Vector3::new(x, 0.5, 1.0),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+            time: 0.0,
+        };
+        let mut intersection = Intersection::empty();
+        assert!(mesh.intersect(&ray, &mut intersection));
+        intersection
+    };
+
+    let red = intersect_down(0.5);
+    assert_eq!(
+        mesh.material_at(&red).albedo.color,
+        Color::new(0.8, 0.1, 0.1)
+    );
+    assert_eq!(red.normal, Vector3::new(0.0, 0.0, 1.0));
+
+    // Heights rise along u, which runs along x, so the bumped normal leans back to -x
+    let bumpy = intersect_down(1.5);
+    assert!(mesh.material_at(&bumpy).bump.image_texture.is_some());
+    assert!(
+        bumpy.normal.x < -1e-3 && bumpy.normal.z > 0.0,
+        "{:?}",
+        bumpy.normal
+    );
+    assert!(bumpy.normal.y.abs() < 1e-9);
+    assert!((bumpy.normal.length() - 1.0).abs() < 1e-9);
+
+    // Without usemtl faces keep the main material
+    let mesh = parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+    assert_eq!(mesh.faces[0].material, None);
+}
+
+#[test]
+fn test_malformed_mtl_lines() {
+    let error = |mtl: &str| {
+        MtlLoader::parse(mtl.as_bytes(), Path::new(""))
+            .err()
+            .unwrap()
+            .to_string()
+    };
+    assert!(error("Kd 1 1 1\n").starts_with("line 1: Kd before newmtl"));
+    assert!(error("newmtl a\n\nKd 1 x 1\n").starts_with("line 3"));
+    assert!(error("newmtl a\nKd 1 1\n").starts_with("line 2"));
+    assert!(error("newmtl a\nmap_Kd -bm 0.5\n").contains("file name"));
+    assert!(error("newmtl a\nmap_Kd missing.png\n").contains("missing.png"));
+
+    let e = error_message("mtllib resources/models/test/malformed.mtl\n");
+    assert!(
+        e.starts_with("line 1: resources/models/test/malformed.mtl: line 1"),
+        "{}",
+        e
+    );
+}
+
+#[test]
+fn test_missing_materials_fall_back_to_main_material() {
+    let mesh = parse(
+        "mtllib resources/models/test/missing.mtl\n\
+         mtllib resources/models/test/materials.mtl\n\
+         v 0 0 0\nv 1 0 0\nv 0 1 0\n\
+         usemtl red\nf 1 2 3\n\
+         usemtl missing\nf 1 2 3\n",
+    )
+    .unwrap();
+    let face_materials: Vec<Option<usize>> = mesh.faces.iter().map(|f| f.material).collect();
+    assert_eq!(face_materials, [Some(0), None]);
+}
+
+#[test]
+fn test_ply_formats_read_the_same_attributes() {
+    for path in &[
+        "resources/models/test/quad_ascii.ply",
+        "resources/models/test/quad_binary_le.ply",
+        "resources/models/test/quad_binary_be.ply",
+    ] {
+        let mesh = PlyLoader::load(path, Matrix44::translate(0.0, 0.0, -2.0), material()).unwrap();
+        assert_eq!(mesh.vertexes.len(), 4, "{}", path);
+        assert_eq!(mesh.vertexes[2], Vector3::new(1.0, 1.0, -2.0));
+        assert_eq!(mesh.normals[2], Vector3::new(1.0, 0.0, 1.0).normalized());
+        assert!((mesh.uvs[1] - Vector2::new(1.0, 0.0)).length() == 0.0);
+        assert_eq!(mesh.colors[0], Color::new(1.0, 0.0, 0.0));
+        // Colors are sRGB encoded
+        assert_eq!(
+            mesh.colors[3],
+            gamma_to_linear(Color::all_of(128.0 / 255.0))
+        );
+
+        // The quad is split in two, the edge element is skipped
+        assert_eq!(mesh.faces.len(), 2);
+        assert_eq!(mesh.faces[0].normals, Some([0, 1, 2]));
+        assert_eq!(mesh.faces[1].uvs, Some([0, 2, 3]));
+    }
+}
+
+#[test]
+fn test_vertex_colors_are_interpolated() {
+    let mesh = PlyLoader::load(
+        "resources/models/test/quad_ascii.ply",
+        Matrix44::identity(),
+        material(),
+    )
+    .unwrap();
+    let ray = Ray {
+        origin: Vector3::new(0.75, 0.25, 1.0),
+        direction: Vector3::new(0.0, 0.0, -1.0),
+        time: 0.0,
+    };
+    let mut intersection = Intersection::empty();
+    assert!(mesh.intersect(&ray, &mut intersection));
+    let expected = Color::new(0.25, 0.5, 0.25);
+    assert!((mesh.vertex_color(&intersection) - expected).length() < 1e-9);
+
+    // Meshes without colors are not tinted
+    let mut intersection = Intersection::empty();
+    let uncolored = parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+    let ray = Ray {
+        origin: Vector3::new(0.2, 0.2, 1.0),
+        ..ray
+    };
+    assert!(uncolored.intersect(&ray, &mut intersection));
+    assert_eq!(uncolored.vertex_color(&intersection), Color::one());
+}
+
+#[test]
+fn test_stl_formats_share_vertexes() {
+    for path in &[
+        "resources/models/test/tetrahedron_ascii.stl",
+        "resources/models/test/tetrahedron_binary.stl",
+    ] {
+        let mesh = StlLoader::load(path, Matrix44::scale_linear(2.0), material()).unwrap();
+        assert_eq!(mesh.vertexes.len(), 4, "{}", path);
+        assert_eq!(mesh.faces.len(), 4);
+        // Vertexes are numbered as they first appear, the first facet is 0 2 1
+        assert_eq!(mesh.vertexes[1], Vector3::new(0.0, 2.0, 0.0));
+        assert_eq!(mesh.vertexes[3], Vector3::new(0.0, 0.0, 2.0));
+        assert_eq!(
+            (mesh.faces[3].v0, mesh.faces[3].v1, mesh.faces[3].v2),
+            (2, 1, 3)
+        );
+        assert!(mesh.normals.is_empty() && mesh.colors.is_empty());
+    }
+}
+
+#[test]
+fn test_malformed_ply_and_stl() {
+    let ply = |text: &str| {
+        PlyLoader::parse(text.as_bytes(), Matrix44::identity(), material())
+            .err()
+            .unwrap()
+            .to_string()
+    };
+    let header = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\n\
+                  property float z\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n";
+    assert_eq!(ply("obj\n"), "line 1: not a PLY file");
+    assert!(ply("ply\nformat ascii 1.0\nproperty float x\n").starts_with("line 3"));
+    assert!(
+        ply("ply\nformat ascii 1.0\nelement vertex 1\nproperty half x\n")
+            .contains("line 4: unknown type half")
+    );
+    assert!(ply("ply\nelement vertex 0\nend_header\n").contains("no format"));
+    assert!(ply(&format!("{}0 0 0\n1 0 0\n", header)).starts_with("vertex 2: unexpected end"));
+    assert!(ply(&format!("{}0 0 0\n1 0 0\n0 x 0\n", header)).starts_with("vertex 2: line 12"));
+    assert_eq!(
+        ply(&format!("{}0 0 0\n1 0 0\n0 1 0\n3 0 1 3\n", header)),
+        "face 0: vertex index 3 is out of 3 defined"
+    );
+    let scalar_indices = header.replace("list uchar int vertex_indices", "int vertex_indices");
+    assert_eq!(
+        ply(&format!("{}0 0 0\n1 0 0\n0 1 0\n0\n", scalar_indices)),
+        "face vertex_indices is not a list"
+    );
+    let binary = header.replace("ascii", "binary_little_endian");
+    assert!(ply(&format!("{}\0\0\0", binary)).starts_with("vertex 0:"));
+
+    let stl = |text: &str| {
+        StlLoader::parse(text.as_bytes(), Matrix44::identity(), material())
+            .err()
+            .unwrap()
+            .to_string()
+    };
+    assert!(stl("solid a\nfacet normal 0 0 1\nouter loop\nvertex 0 0\n").starts_with("line 4"));
+    assert!(
+        stl("solid a\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\nendloop\n")
+            .starts_with("line 5: facet needs 3")
+    );
+    assert!(
+        stl("solid a\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\n").contains("unexpected end")
+    );
+    assert!(stl("solid a\nfacets\n").contains("unknown keyword facets"));
+}
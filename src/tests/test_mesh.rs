@@ -0,0 +1,82 @@
+#![cfg(test)]
+
+use super::super::loader::ObjLoader;
+use super::super::matrix::Matrix44;
+use super::super::mesh::{Face, Mesh};
+use super::super::rayintersectable::Intersection;
+use super::super::vector::{Vector2, Vector3};
+use super::fixtures::{assert_near, intersect, material};
+
+const QUAD: &str = "resources/models/test/quad_vn_vt.obj";
+
+fn intersect_down(mesh: &Mesh, x: f64, y: f64) -> Intersection {
+    intersect(mesh, Vector3::new(x, y, 1.0), Vector3::new(0.0, 0.0, -1.0)).unwrap()
+}
+
+#[test]
+fn test_obj_normals_and_uvs_are_interpolated() {
+    let mesh = ObjLoader::load(QUAD, Matrix44::identity(), material()).unwrap();
+    assert_eq!(mesh.faces.len(), 2);
+    assert_eq!(mesh.uvs.len(), 4);
+    assert_eq!(mesh.normals.len(), 2);
+
+    for &(x, y) in &[(0.25, 0.75), (0.8, 0.3)] {
+        let intersection = intersect_down(&mesh, x, y);
+        assert!((intersection.uv.x - x).abs() < 1e-9 && (intersection.uv.y - y).abs() < 1e-9);
+
+        let leaning = Vector3::new(1.0, 0.0, 1.0).normalized();
+        let expected = (Vector3::new(0.0, 0.0, 1.0) * (1.0 - x) + leaning * x).normalized();
+        assert_near(intersection.normal, expected);
+    }
+}
+
+#[test]
+fn test_obj_normals_are_transformed_by_inverse_transpose() {
+    let mesh = ObjLoader::load(QUAD, Matrix44::scale(2.0, 1.0, 1.0), material()).unwrap();
+    assert_near(mesh.vertexes[2], Vector3::new(2.0, 1.0, 0.0));
+    assert_near(mesh.normals[1], Vector3::new(0.5, 0.0, 1.0).normalized());
+}
+
+#[test]
+fn test_generated_normals_are_weighted_by_angle() {
+    // Corner of a cube whose z face is split into two triangles at the corner
+    let mesh = Mesh {
+        vertexes: vec![
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(1.0, 1.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            Vector3::new(0.0, 0.0, 1.0),
+        ],
+        colors: vec![],
+        normals: vec![],
+        uvs: vec![],
+        faces: vec![
+            Face::new(0, 1, 2),
+            Face::new(0, 2, 3),
+            Face::new(0, 4, 1),
+            Face::new(0, 3, 4),
+        ],
+        material: material(),
+        materials: vec![],
+    }
+    .with_generated_normals();
+
+    // Each face counts by its angle at the corner, not by its number of triangles
+    assert_near(mesh.normals[0], Vector3::new(1.0, 1.0, 1.0).normalized());
+    assert_eq!(mesh.faces[1].normals, Some([0, 2, 3]));
+    assert!(mesh.faces.iter().all(|f| f.uvs.is_none()));
+
+    // Barycentric coordinates remain the uv without texture coordinates
+    let intersection = intersect_down(&mesh, 0.6, 0.2);
+    assert!((intersection.uv - Vector2::new(0.4, 0.2)).length() < 1e-9);
+}
+
+#[test]
+fn test_generated_normals_keep_given_normals() {
+    let mesh = ObjLoader::load(QUAD, Matrix44::identity(), material())
+        .unwrap()
+        .with_generated_normals();
+    assert_eq!(mesh.faces[0].normals, Some([0, 1, 1]));
+    assert_eq!(mesh.normals.len(), 6);
+}
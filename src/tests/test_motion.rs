@@ -0,0 +1,144 @@
+#![cfg(test)]
+
+use super::super::aabb::Aabb;
+use super::super::config;
+use super::super::matrix::Matrix44;
+use super::super::motion::{Moving, TransformKeyframe};
+use super::super::quaternion::Quaternion;
+use super::super::rayintersectable::{Cuboid, Intersectable, Sphere};
+use super::super::vector::Vector3;
+use super::fixtures::{assert_near, intersect_at, material};
+
+fn keyframe(time: f64, translation: Vector3, angle: f64, scale: Vector3) -> TransformKeyframe {
+    TransformKeyframe {
+        time,
+        translation,
+        rotation: Quaternion::from_axis_angle(&Vector3::new(0.0, 1.0, 0.0), angle),
+        scale,
+    }
+}
+
+#[test]
+fn test_quaternion_rotation() {
+    let axis = Vector3::new(0.0, 1.0, 0.0);
+    let p = Vector3::new(1.0, 2.0, 3.0);
+    let rotation = Quaternion::from_axis_angle(&axis, 0.7);
+    assert_near(rotation.to_matrix() * p, Matrix44::rotate_y(0.7) * p);
+
+    let half = Quaternion::identity().slerp(&Quaternion::from_axis_angle(&axis, 1.2), 0.5);
+    assert_near(half.to_matrix() * p, Matrix44::rotate_y(0.6) * p);
+}
+
+#[test]
+fn test_moving_sphere() {
+    let sphere = Sphere {
+        center: Vector3::zero(),
+        radius: 1.0,
+        material: material(),
+    };
+    let moving = Moving::new(
+        Box::new(sphere),
+        vec![
+            keyframe(0.0, Vector3::zero(), 0.0, Vector3::one()),
+            keyframe(1.0, Vector3::new(2.0, 0.0, 0.0), 0.0, Vector3::one()),
+        ],
+    );
+
+    let hit = intersect_at(
+        &moving,
+        Vector3::new(1.0, 0.0, 5.0),
+        Vector3::new(0.0, 0.0, -1.0),
+        0.5,
+    )
+    .unwrap();
+    assert_near(hit.position, Vector3::new(1.0, 0.0, 1.0));
+    assert_near(hit.normal, Vector3::new(0.0, 0.0, 1.0));
+    assert!((hit.distance - 4.0).abs() < 1e-9);
+
+    assert!(intersect_at(
+        &moving,
+        Vector3::new(2.5, 0.0, 5.0),
+        Vector3::new(0.0, 0.0, -1.0),
+        0.0
+    )
+    .is_none());
+    assert!(intersect_at(
+        &moving,
+        Vector3::new(2.5, 0.0, 5.0),
+        Vector3::new(0.0, 0.0, -1.0),
+        1.0
+    )
+    .is_some());
+    // Clamped after the last keyframe
+    assert!(intersect_at(
+        &moving,
+        Vector3::new(2.5, 0.0, 5.0),
+        Vector3::new(0.0, 0.0, -1.0),
+        3.0
+    )
+    .is_some());
+
+    // Light samples have no time to move with
+    assert!(moving.sample_on_surface((0.5, 0.5)).is_none());
+}
+
+#[test]
+fn test_spinning_scaled_cuboid() {
+    let cuboid = Cuboid {
+        aabb: Aabb {
+            min: Vector3::all_of(-1.0),
+            max: Vector3::all_of(1.0),
+        },
+        material: material(),
+    };
+    let scale = Vector3::new(2.0, 1.0, 1.0);
+    let moving = Moving::new(
+        Box::new(cuboid),
+        vec![
+            keyframe(0.0, Vector3::zero(), 0.0, scale),
+            keyframe(1.0, Vector3::zero(), config::PI * 0.5, scale),
+        ],
+    );
+
+    let origin = Vector3::new(0.0, 0.0, 5.0);
+    let start = intersect_at(&moving, origin, Vector3::new(0.0, 0.0, -1.0), 0.0).unwrap();
+    assert!((start.distance - 4.0).abs() < 1e-9);
+    assert_near(start.normal, Vector3::new(0.0, 0.0, 1.0));
+
+    // Long side faces the ray after a quarter turn
+    let end = intersect_at(&moving, origin, Vector3::new(0.0, 0.0, -1.0), 1.0).unwrap();
+    assert!((end.distance - 3.0).abs() < 1e-9);
+    assert_near(end.normal, Vector3::new(0.0, 0.0, 1.0));
+
+    // Rotated 45 degrees halfway
+    let half = intersect_at(&moving, origin, Vector3::new(0.0, 0.0, -1.0), 0.5).unwrap();
+    assert!((half.position.z - 2f64.sqrt()).abs() < 1e-9);
+    assert_near(
+        half.normal,
+        Matrix44::rotate_y(config::PI * 0.25) * Vector3::new(0.0, 0.0, 1.0),
+    );
+}
+
+#[test]
+fn test_moving_aabb_covers_sweep() {
+    let sphere = Sphere {
+        center: Vector3::new(2.0, 0.0, 0.0),
+        radius: 0.5,
+        material: material(),
+    };
+    let moving = Moving::new(
+        Box::new(sphere),
+        vec![
+            keyframe(0.0, Vector3::zero(), 0.0, Vector3::one()),
+            keyframe(1.0, Vector3::zero(), config::PI * 0.5, Vector3::one()),
+            keyframe(2.0, Vector3::zero(), config::PI, Vector3::one()),
+        ],
+    );
+
+    let aabb = moving.aabb();
+    for step in 0..=20 {
+        let center = moving.keyframe_at(step as f64 * 0.1).matrix() * Vector3::new(2.0, 0.0, 0.0);
+        assert!(aabb.min.x <= center.x - 0.5 && center.x + 0.5 <= aabb.max.x);
+        assert!(aabb.min.z <= center.z - 0.5 && center.z + 0.5 <= aabb.max.z);
+    }
+}
@@ -0,0 +1,335 @@
+#![cfg(test)]
+
+use super::super::config;
+use super::super::math::polynomial_roots;
+use super::super::matrix::Matrix44;
+use super::super::rayintersectable::{
+    Cone, Cylinder, Disk, Intersectable, Plane, Rectangle, Torus,
+};
+use super::super::transform::Transformed;
+use super::super::vector::{Vector2, Vector3};
+use super::fixtures::{assert_near, intersect, material};
+
+fn assert_near_uv(a: Vector2, b: Vector2) {
+    assert!((a - b).length() < 1e-9, "{:?} != {:?}", a, b);
+}
+
+// Samples lie on the surface with the normal of a hit there, up to its side
+fn assert_samples_on_surface(element: &dyn Intersectable) -> Vec<Vector3> {
+    let n = 16;
+    let mut positions = vec![];
+    for i in 0..n {
+        for j in 0..n {
+            let random = ((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+            let surface = element.sample_on_surface(random).unwrap();
+            let hit = intersect(
+                element,
+                surface.position + surface.normal * 0.01,
+                -surface.normal,
+            )
+            .unwrap();
+            assert!((hit.position - surface.position).length() < 1e-6);
+            assert!(hit.normal.dot(&surface.normal).abs() > 1.0 - 1e-6);
+            positions.push(surface.position);
+        }
+    }
+    positions
+}
+
+#[test]
+fn test_polynomial_roots() {
+    // (x + 1)(x - 1)(x - 2)(x - 3)
+    let quartic = [-6.0, 5.0, 5.0, -5.0, 1.0];
+    let roots = polynomial_roots(&quartic, -5.0, 5.0);
+    assert_eq!(roots.len(), 4);
+    for (root, expected) in roots.iter().zip(&[-1.0, 1.0, 2.0, 3.0]) {
+        assert!((root - expected).abs() < 1e-12, "{} != {}", root, expected);
+    }
+    assert_eq!(polynomial_roots(&quartic, 1.5, 2.5).len(), 1);
+    assert!(polynomial_roots(&[1.0, 0.0, 1.0], -5.0, 5.0).is_empty());
+}
+
+#[test]
+fn test_plane() {
+    let plane = Plane {
+        point: Vector3::new(0.0, -1.0, 0.0),
+        normal: Vector3::new(0.0, 2.0, 0.0),
+        material: material(),
+    };
+    let above = intersect(
+        &plane,
+        Vector3::new(3.25, 1.0, -7.5),
+        Vector3::new(0.0, -1.0, 0.0),
+    )
+    .unwrap();
+    assert!((above.distance - 2.0).abs() < 1e-9);
+    assert_near(above.normal, Vector3::new(0.0, 1.0, 0.0));
+    assert!(above.uv.x >= 0.0 && above.uv.x < 1.0 && above.uv.y >= 0.0 && above.uv.y < 1.0);
+    // Textures repeat every unit
+    let next = intersect(
+        &plane,
+        Vector3::new(4.25, 1.0, -6.5),
+        Vector3::new(0.0, -1.0, 0.0),
+    )
+    .unwrap();
+    assert_near_uv(above.uv, next.uv);
+
+    // Seen from below it faces down
+    let below = intersect(
+        &plane,
+        Vector3::new(0.0, -3.0, 0.0),
+        Vector3::new(1.0, 1.0, 0.0),
+    )
+    .unwrap();
+    assert_near(below.normal, Vector3::new(0.0, -1.0, 0.0));
+    assert!(intersect(&plane, Vector3::zero(), Vector3::new(1.0, 0.0, 0.0)).is_none());
+    // Unbounded, so there is no uniform point on it to sample
+    assert!(plane.sample_on_surface((0.5, 0.5)).is_none());
+
+    // Planes stay unbounded when transformed
+    let tilted = Transformed::new(Box::new(plane), Matrix44::rotate_z(config::PI / 2.0));
+    let side = intersect(
+        &tilted,
+        Vector3::new(5.0, 0.0, 0.0),
+        Vector3::new(-1.0, 0.0, 0.0),
+    )
+    .unwrap();
+    assert_near(side.position, Vector3::new(1.0, 0.0, 0.0));
+}
+
+#[test]
+fn test_disk() {
+    let disk = Disk {
+        center: Vector3::new(0.0, 0.0, -2.0),
+        normal: Vector3::new(0.0, 0.0, 1.0),
+        radius: 2.0,
+        material: material(),
+    };
+    assert_near(disk.aabb().min, Vector3::new(-2.0, -2.0, -2.0));
+    assert_near(disk.aabb().max, Vector3::new(2.0, 2.0, -2.0));
+
+    let hit = intersect(
+        &disk,
+        Vector3::new(1.0, 0.0, 0.0),
+        Vector3::new(0.0, 0.0, -1.0),
+    )
+    .unwrap();
+    assert!((hit.distance - 2.0).abs() < 1e-9);
+    assert_near(hit.normal, Vector3::new(0.0, 0.0, 1.0));
+    assert!((hit.uv.y - 0.5).abs() < 1e-9);
+    assert!(intersect(
+        &disk,
+        Vector3::new(1.5, 1.5, 0.0),
+        Vector3::new(0.0, 0.0, -1.0)
+    )
+    .is_none());
+
+    assert!(disk.nee_available());
+    assert!(
+        (disk.sample_on_surface((0.3, 0.7)).unwrap().pdf - (4.0 * config::PI).recip()).abs()
+            < 1e-12
+    );
+    let positions = assert_samples_on_surface(&disk);
+    assert!(positions
+        .iter()
+        .all(|p| (*p - disk.center).length() <= 2.0 + 1e-9));
+}
+
+#[test]
+fn test_rectangle() {
+    let rectangle = Rectangle {
+        corner: Vector3::new(-1.0, 2.0, -1.0),
+        edge_u: Vector3::new(2.0, 0.0, 0.0),
+        edge_v: Vector3::new(1.0, 0.0, 3.0),
+        material: material(),
+    };
+    // Facing edge_u x edge_v, which is down
+    let hit = intersect(
+        &rectangle,
+        Vector3::new(1.0, 0.0, 0.5),
+        Vector3::new(0.0, 1.0, 0.0),
+    )
+    .unwrap();
+    assert_near(hit.position, Vector3::new(1.0, 2.0, 0.5));
+    assert_near(hit.normal, Vector3::new(0.0, -1.0, 0.0));
+    assert_near_uv(hit.uv, Vector2::new(0.75, 0.5));
+    // Left of the slanted edge
+    assert!(intersect(
+        &rectangle,
+        Vector3::new(-0.5, 0.0, 1.0),
+        Vector3::new(0.0, 1.0, 0.0)
+    )
+    .is_none());
+
+    assert!((rectangle.sample_on_surface((0.5, 0.5)).unwrap().pdf - 6f64.recip()).abs() < 1e-12);
+    assert_near(
+        rectangle.sample_on_surface((0.5, 0.5)).unwrap().position,
+        Vector3::new(0.5, 2.0, 0.5),
+    );
+    assert_samples_on_surface(&rectangle);
+}
+
+#[test]
+fn test_cylinder() {
+    let cylinder = Cylinder {
+        center: Vector3::new(0.0, 1.0, 0.0),
+        radius: 1.0,
+        height: 2.0,
+        material: material(),
+    };
+    let outside = intersect(
+        &cylinder,
+        Vector3::new(0.0, 1.5, 5.0),
+        Vector3::new(0.0, 0.0, -1.0),
+    )
+    .unwrap();
+    assert_near(outside.position, Vector3::new(0.0, 1.5, 1.0));
+    assert_near(outside.normal, Vector3::new(0.0, 0.0, 1.0));
+    assert!((outside.uv.y - 0.25).abs() < 1e-9);
+
+    // The open tube is hit from inside, facing the ray
+    let inside = intersect(
+        &cylinder,
+        Vector3::new(0.0, 2.0, 0.0),
+        Vector3::new(1.0, 0.0, 0.0),
+    )
+    .unwrap();
+    assert_near(inside.position, Vector3::new(1.0, 2.0, 0.0));
+    assert_near(inside.normal, Vector3::new(-1.0, 0.0, 0.0));
+    // Through the open ends and above the top
+    assert!(intersect(
+        &cylinder,
+        Vector3::new(0.0, 5.0, 0.0),
+        Vector3::new(0.0, -1.0, 0.0)
+    )
+    .is_none());
+    assert!(intersect(
+        &cylinder,
+        Vector3::new(0.0, 3.5, 5.0),
+        Vector3::new(0.0, 0.0, -1.0)
+    )
+    .is_none());
+
+    assert!(
+        (cylinder.sample_on_surface((0.1, 0.2)).unwrap().pdf - (4.0 * config::PI).recip()).abs()
+            < 1e-12
+    );
+    assert_samples_on_surface(&cylinder);
+}
+
+#[test]
+fn test_cone() {
+    let cone = Cone {
+        center: Vector3::zero(),
+        radius: 1.0,
+        height: 1.0,
+        material: material(),
+    };
+    let side = intersect(
+        &cone,
+        Vector3::new(5.0, 0.5, 0.0),
+        Vector3::new(-1.0, 0.0, 0.0),
+    )
+    .unwrap();
+    assert_near(side.position, Vector3::new(0.5, 0.5, 0.0));
+    let half = 0.5f64.sqrt();
+    assert_near(side.normal, Vector3::new(half, half, 0.0));
+    assert!((side.uv.y - 0.5).abs() < 1e-9);
+    // Straight down onto the slope
+    let top = intersect(
+        &cone,
+        Vector3::new(0.25, 5.0, 0.0),
+        Vector3::new(0.0, -1.0, 0.0),
+    )
+    .unwrap();
+    assert_near(top.position, Vector3::new(0.25, 0.75, 0.0));
+    // The other nappe above the apex is not part of it
+    assert!(intersect(
+        &cone,
+        Vector3::new(5.0, 1.5, 0.0),
+        Vector3::new(-1.0, 0.0, 0.0)
+    )
+    .is_none());
+
+    // The centroid of the lateral surface is a third up
+    let positions = assert_samples_on_surface(&cone);
+    let mean_y = positions.iter().map(|p| p.y).sum::<f64>() / positions.len() as f64;
+    assert!((mean_y - 1.0 / 3.0).abs() < 1e-2, "{}", mean_y);
+    assert!(
+        (cone.sample_on_surface((0.5, 0.5)).unwrap().pdf - (config::PI * 2f64.sqrt()).recip())
+            .abs()
+            < 1e-12
+    );
+    // The apex has no normal of its own, it takes the axis
+    let apex = cone.sample_on_surface((0.0, 0.3)).unwrap();
+    assert_near(apex.position, Vector3::new(0.0, 1.0, 0.0));
+    assert_near(apex.normal, Vector3::new(0.0, 1.0, 0.0));
+}
+
+#[test]
+fn test_torus() {
+    let torus = Torus {
+        center: Vector3::new(0.0, 0.0, -5.0),
+        major_radius: 2.0,
+        minor_radius: 0.5,
+        material: material(),
+    };
+    let rim = intersect(
+        &torus,
+        Vector3::new(10.0, 0.0, -5.0),
+        Vector3::new(-1.0, 0.0, 0.0),
+    )
+    .unwrap();
+    assert_near(rim.position, Vector3::new(2.5, 0.0, -5.0));
+    assert_near(rim.normal, Vector3::new(1.0, 0.0, 0.0));
+    assert!(rim.uv.y.abs() < 1e-9 || (rim.uv.y - 1.0).abs() < 1e-9);
+
+    let top = intersect(
+        &torus,
+        Vector3::new(0.0, 3.0, -3.0),
+        Vector3::new(0.0, -1.0, 0.0),
+    )
+    .unwrap();
+    assert_near(top.position, Vector3::new(0.0, 0.5, -3.0));
+    assert_near(top.normal, Vector3::new(0.0, 1.0, 0.0));
+    assert!((top.uv.y - 0.25).abs() < 1e-9);
+
+    // Through the hole, and grazing past the outside
+    assert!(intersect(
+        &torus,
+        Vector3::new(0.0, 3.0, -5.0),
+        Vector3::new(0.0, -1.0, 0.0)
+    )
+    .is_none());
+    assert!(intersect(
+        &torus,
+        Vector3::new(10.0, 0.6, -5.0),
+        Vector3::new(-1.0, 0.0, 0.0)
+    )
+    .is_none());
+    // Across the ring, the near side of the tube is hit first
+    let across = intersect(
+        &torus,
+        Vector3::new(-10.0, 0.0, -5.0),
+        Vector3::new(1.0, 0.0, 0.0),
+    )
+    .unwrap();
+    assert_near(across.position, Vector3::new(-2.5, 0.0, -5.0));
+    // From inside the tube
+    let inside = intersect(
+        &torus,
+        Vector3::new(2.0, 0.0, -5.0),
+        Vector3::new(0.0, 1.0, 0.0),
+    )
+    .unwrap();
+    assert!((inside.distance - 0.5).abs() < 1e-9);
+
+    // The outer side holds more area, cos(phi) averaging r / 2R over it
+    let positions = assert_samples_on_surface(&torus);
+    let mean_cos = positions
+        .iter()
+        .map(|p| ((*p - torus.center).xz().length() - 2.0) / 0.5)
+        .sum::<f64>()
+        / positions.len() as f64;
+    assert!((mean_cos - 0.125).abs() < 1e-2, "{}", mean_cos);
+}
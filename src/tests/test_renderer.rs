@@ -0,0 +1,149 @@
+#![cfg(test)]
+
+use super::super::accumulation::AccumulationBuffer;
+use super::super::color::Color;
+use super::super::filter;
+use super::super::renderer::{Interval, PathTracingRenderer, ProgressSnapshot, Renderer};
+use super::super::tile::Region;
+use super::super::tonemap;
+use image::codecs::hdr::HdrDecoder;
+use image::{ImageBuffer, Rgb};
+use std::env;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+fn renderer() -> PathTracingRenderer {
+    let mut renderer = PathTracingRenderer::new(16, filter::identity_filter, tonemap::none);
+    renderer.begin_render();
+    renderer
+}
+
+// Pixels of the same value each sampling, whose relative error is zero
+fn converged() -> AccumulationBuffer {
+    let mut accumulation = AccumulationBuffer::new(2, 2);
+    for pixel in &mut accumulation.pixels {
+        pixel.add(Color::one());
+        pixel.add(Color::one());
+    }
+    accumulation
+}
+
+#[test]
+fn test_no_stop_condition() {
+    assert!(!renderer().should_stop(&[&converged()], Duration::from_secs(1)));
+}
+
+#[test]
+fn test_stop_when_interrupted() {
+    let renderer = renderer();
+    let accumulation = AccumulationBuffer::new(2, 2);
+    assert!(!renderer.should_stop(&[&accumulation], Duration::ZERO));
+    renderer.interrupted.store(true, Ordering::SeqCst);
+    assert!(renderer.should_stop(&[&accumulation], Duration::ZERO));
+}
+
+#[test]
+fn test_stop_at_time_limit() {
+    let mut renderer = renderer();
+    let accumulation = AccumulationBuffer::new(2, 2);
+    renderer.time_limit = Some(Duration::from_secs(60));
+    assert!(!renderer.should_stop(&[&accumulation], Duration::from_secs(1)));
+    // Another sampling as long as the last one would exceed the limit
+    assert!(renderer.should_stop(&[&accumulation], Duration::from_secs(61)));
+}
+
+#[test]
+fn test_stop_at_target_error() {
+    let mut renderer = renderer();
+    renderer.target_error = Some(0.05);
+    assert!(renderer.should_stop(&[&converged()], Duration::ZERO));
+
+    // A single sampling has no error estimate yet
+    let mut accumulation = AccumulationBuffer::new(2, 2);
+    for pixel in &mut accumulation.pixels {
+        pixel.add(Color::one());
+    }
+    assert!(!renderer.should_stop(&[&accumulation], Duration::ZERO));
+
+    // Every view has to reach the target
+    assert!(!renderer.should_stop(&[&converged(), &accumulation], Duration::ZERO));
+    assert!(renderer.should_stop(&[&converged(), &converged()], Duration::ZERO));
+}
+
+#[test]
+fn test_mean_relative_error_of_empty_region() {
+    let accumulation = AccumulationBuffer::with_region(4, 4, Region::new(1, 1, 0, 0));
+    assert_eq!(accumulation.mean_relative_error(), 0.0);
+    assert_eq!(converged().mean_relative_error(), 0.0);
+}
+
+#[test]
+fn test_interval_is_due() {
+    let every_third = Interval {
+        every_sampling: Some(3),
+        every_duration: None,
+    };
+    let due: Vec<_> = (1..=7)
+        .filter(|&s| every_third.is_due(s, Duration::ZERO))
+        .collect();
+    assert_eq!(due, vec![3, 6]);
+
+    let never = Interval {
+        every_sampling: Some(0),
+        every_duration: None,
+    };
+    assert!((1..=10).all(|s| !never.is_due(s, Duration::from_secs(3600))));
+
+    let by_duration = Interval {
+        every_sampling: None,
+        every_duration: Some(Duration::from_secs(10)),
+    };
+    assert!(!by_duration.is_due(1, Duration::from_secs(9)));
+    assert!(by_duration.is_due(1, Duration::from_secs(10)));
+
+    // Either condition is enough
+    let both = Interval {
+        every_sampling: Some(4),
+        every_duration: Some(Duration::from_secs(10)),
+    };
+    assert!(both.is_due(4, Duration::ZERO));
+    assert!(both.is_due(5, Duration::from_secs(10)));
+    assert!(!both.is_due(5, Duration::from_secs(9)));
+}
+
+#[test]
+fn test_save_progress_image() {
+    let directory = env::temp_dir();
+    let path = directory.join("fulleffect_test_progress.png");
+    let hdr_path = directory.join("fulleffect_test_progress.hdr");
+    let snapshot = ProgressSnapshot {
+        path: path.to_str().unwrap().to_string(),
+        hdr_path: Some(hdr_path.to_str().unwrap().to_string()),
+        interval: Interval {
+            every_sampling: Some(1),
+            every_duration: None,
+        },
+    };
+    let mut accumulation = AccumulationBuffer::new(3, 2);
+    accumulation.pixels[4].add(Color::all_of(8.0));
+    let mut imgbuf = ImageBuffer::new(3, 2);
+    imgbuf.put_pixel(1, 1, Rgb([10, 20, 30]));
+
+    renderer()
+        .save_progress_image(&snapshot, &accumulation, &imgbuf)
+        .unwrap();
+
+    let saved = image::open(&path).unwrap().to_rgb8();
+    assert_eq!(saved, imgbuf);
+    // Opening an HDR as a dynamic image clamps it, so decode the radiance itself
+    let hdr = HdrDecoder::new(BufReader::new(File::open(&hdr_path).unwrap())).unwrap();
+    assert_eq!((hdr.metadata().width, hdr.metadata().height), (3, 2));
+    let radiance = hdr.read_image_hdr().unwrap();
+    assert_eq!(radiance[4].0, [2.0; 3]);
+    assert_eq!(radiance[0].0, [0.0; 3]);
+    // Temporary files were renamed over the targets
+    assert!(!directory.join("fulleffect_test_progress.png.tmp").exists());
+    assert!(!directory.join("fulleffect_test_progress.hdr.tmp").exists());
+}
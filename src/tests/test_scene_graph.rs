@@ -0,0 +1,160 @@
+#![cfg(test)]
+
+use super::super::camera::Ray;
+use super::super::color::Color;
+use super::super::instance::Instance;
+use super::super::loader::ObjLoader;
+use super::super::material::{Material, SurfaceType};
+use super::super::matrix::Matrix44;
+use super::super::mesh::BvhMesh;
+use super::super::rayintersectable::{Intersection, Plane};
+use super::super::scene::Illuminable;
+use super::super::scene_graph::{Group, SceneNode};
+use super::super::texture::Texture;
+use super::super::vector::Vector3;
+use super::fixtures::{diffuse, intersect, scene};
+use std::sync::Arc;
+
+fn quad() -> Arc<BvhMesh> {
+    Arc::new(BvhMesh::from(
+        ObjLoader::load(
+            "resources/models/test/quad_vn_vt.obj",
+            Matrix44::identity(),
+            Material::new(
+                SurfaceType::Diffuse,
+                Texture::white(),
+                Texture::black(),
+                Texture::white(),
+            ),
+        )
+        .unwrap(),
+    ))
+}
+
+#[test]
+fn test_children_inherit_the_matrices_of_their_groups() {
+    let outer = Matrix44::translate(1.0, 2.0, -3.0) * Matrix44::scale_linear(2.0);
+    let inner = Matrix44::rotate_y(0.5) * Matrix44::rotate_x(-0.3);
+    let leaf = Matrix44::translate(0.0, 0.5, 0.0);
+    let mesh = quad();
+    let elements = Group::new(outer)
+        .with_child(
+            Group::new(inner).with_child(Group::new(leaf).with_child(SceneNode::mesh(
+                mesh.clone(),
+                Arc::new(diffuse(Color::one())),
+            ))),
+        )
+        .flatten();
+    assert_eq!(elements.len(), 1);
+
+    let instance = Instance::new(mesh, outer * inner * leaf, Arc::new(diffuse(Color::one())));
+    for &(u, v) in &[(0.2, 0.3), (0.5, 0.5), (0.9, 0.1)] {
+        let target = *instance.matrix() * Vector3::new(u, v, 0.0);
+        let origin = target + Vector3::new(0.3, -0.2, 5.0);
+        let actual = intersect(elements[0].as_ref(), origin, target - origin).unwrap();
+        let expected = intersect(&instance, origin, target - origin).unwrap();
+        assert!((actual.position - target).length() < 1e-9);
+        assert!((actual.normal - expected.normal).length() < 1e-9);
+    }
+}
+
+#[test]
+fn test_groups_flatten_to_instances_of_shared_meshes() {
+    let mesh = quad();
+    let row = |z: f64| {
+        (0..3).fold(Group::new(Matrix44::translate(0.0, 0.0, z)), |group, i| {
+            group.with_child(
+                Group::new(Matrix44::translate(i as f64 * 4.0, 0.0, 0.0)).with_child(
+                    SceneNode::mesh(
+                        mesh.clone(),
+                        Arc::new(diffuse(Color::all_of(i as f64 / 2.0))),
+                    ),
+                ),
+            )
+        })
+    };
+    let elements = Group::new(Matrix44::identity())
+        .with_child(row(0.0))
+        .with_child(row(-2.0))
+        .flatten();
+    assert_eq!(elements.len(), 6);
+    assert_eq!(Arc::strong_count(&mesh), 7);
+
+    // The front one of the two quads at x = 8 is hit, with its own material
+    let origin = Vector3::new(8.5, 0.5, 5.0);
+    let hits: Vec<(usize, Intersection)> = elements
+        .iter()
+        .enumerate()
+        .filter_map(|(i, e)| {
+            intersect(e.as_ref(), origin, Vector3::new(0.0, 0.0, -1.0)).map(|hit| (i, hit))
+        })
+        .collect();
+    assert_eq!(hits.len(), 2);
+    let (nearest, hit) = hits
+        .iter()
+        .min_by(|a, b| a.1.distance.partial_cmp(&b.1.distance).unwrap())
+        .unwrap();
+    assert_eq!(*nearest, 2);
+    assert!((hit.distance - 5.0).abs() < 1e-9);
+    assert_eq!(
+        elements[*nearest].material_at(hit).albedo.color,
+        Color::all_of(1.0)
+    );
+}
+
+#[test]
+fn test_scene_finds_nearest_instance_through_its_bvh() {
+    let mesh = quad();
+    let mut root = Group::new(Matrix44::identity());
+    for i in 0..40 {
+        let matrix = Matrix44::translate((i % 8) as f64 * 1.5, (i / 8) as f64, -(i % 3) as f64)
+            * Matrix44::rotate_y(i as f64 * 0.1);
+        root.children.push(
+            Group::new(matrix)
+                .with_child(SceneNode::mesh(
+                    mesh.clone(),
+                    Arc::new(diffuse(Color::all_of(i as f64 / 40.0))),
+                ))
+                .into(),
+        );
+    }
+    let mut elements = root.flatten();
+    // Unbounded elements are tested apart from the BVH
+    elements.push(Box::new(Plane {
+        point: Vector3::new(0.0, 0.0, -5.0),
+        normal: Vector3::new(0.0, 0.0, 1.0),
+        material: Material::new(
+            SurfaceType::Diffuse,
+            Texture::black(),
+            Texture::black(),
+            Texture::white(),
+        ),
+    }));
+    let expected: Vec<_> = (0..elements.len())
+        .map(|i| elements[i].material().albedo.color)
+        .collect();
+    let scene = scene(elements);
+
+    for y in 0..20 {
+        for x in 0..30 {
+            let origin = Vector3::new(x as f64 * 0.4 - 0.5, y as f64 * 0.3 - 0.5, 4.0);
+            let direction = Vector3::new(0.1, -0.05, -1.0);
+            let nearest = scene
+                .elements()
+                .iter()
+                .enumerate()
+                .filter_map(|(i, e)| intersect(e.as_ref(), origin, direction).map(|hit| (i, hit)))
+                .min_by(|a, b| a.1.distance.partial_cmp(&b.1.distance).unwrap())
+                .unwrap();
+
+            let (hit, intersection) = scene.intersect(&Ray {
+                origin,
+                direction: direction.normalized(),
+                time: 0.0,
+            });
+            assert!(hit);
+            assert!((intersection.distance - nearest.1.distance).abs() < 1e-9);
+            assert_eq!(intersection.material.albedo, expected[nearest.0]);
+        }
+    }
+}
@@ -0,0 +1,208 @@
+#![cfg(test)]
+
+use super::super::accumulation::AccumulationBuffer;
+use super::super::camera::{Camera, FieldOfView, LensShape, Ray};
+use super::super::color::Color;
+use super::super::filter;
+use super::super::rayintersectable::Intersectable;
+use super::super::renderer::{PathTracingRenderer, Renderer};
+use super::super::scene::Illuminable;
+use super::super::stereo::{Convergence, Eye, StereoLayout, StereoRig};
+use super::super::tonemap;
+use super::super::vector::{Vector2, Vector3};
+use super::fixtures::{assert_near, scene};
+use image::{ImageBuffer, Rgb};
+use std::sync::atomic::Ordering;
+
+fn center_camera() -> Camera {
+    Camera::new(
+        Vector3::new(0.0, 1.0, 10.0),
+        Vector3::new(0.0, 1.0, 0.0),
+        Vector3::new(0.0, 1.0, 0.0),
+        FieldOfView::Vertical(40.0),
+        1.5,
+        LensShape::Circle,
+        0.0,
+        10.0,
+    )
+}
+
+// Point where the ray crosses the plane z = z
+fn at_depth(ray: &Ray, z: f64) -> Vector3 {
+    ray.origin + ray.direction * ((z - ray.origin.z) / ray.direction.z)
+}
+
+#[test]
+fn test_off_axis_eyes_have_zero_parallax_at_convergence() {
+    let rig = StereoRig {
+        interaxial: 0.4,
+        convergence_distance: 6.0,
+        convergence: Convergence::OffAxis,
+    };
+    let [left, right] = rig.eyes(&center_camera());
+
+    assert_near(left.position, Vector3::new(-0.2, 1.0, 10.0));
+    assert_near(right.position, Vector3::new(0.2, 1.0, 10.0));
+    // Eyes stay parallel, so no vertical parallax
+    assert_near(left.forward, Vector3::new(0.0, 0.0, -1.0));
+    assert_near(right.forward, Vector3::new(0.0, 0.0, -1.0));
+
+    for coord in &[Vector2::new(0.0, 0.0), Vector2::new(0.7, -0.4)] {
+        let l = left.ray(coord).unwrap();
+        let r = right.ray(coord).unwrap();
+        assert_near(at_depth(&l, 4.0), at_depth(&r, 4.0));
+        // Rays of the same pixel cross at the convergence plane
+        assert!(at_depth(&l, -10.0).x > at_depth(&r, -10.0).x);
+    }
+    assert_near(
+        at_depth(&left.ray(&Vector2::new(0.0, 0.0)).unwrap(), 4.0),
+        Vector3::new(0.0, 1.0, 4.0),
+    );
+}
+
+#[test]
+fn test_toe_in_eyes_look_at_convergence_point() {
+    let rig = StereoRig {
+        interaxial: 0.4,
+        convergence_distance: 6.0,
+        convergence: Convergence::ToeIn,
+    };
+    let camera = center_camera();
+    let convergence_point = Vector3::new(0.0, 1.0, 4.0);
+
+    for eye in &[Eye::Left, Eye::Right] {
+        let eye_camera = rig.eye(&camera, *eye);
+        let center = eye_camera.ray(&Vector2::new(0.0, 0.0)).unwrap();
+        assert_near(at_depth(&center, 4.0), convergence_point);
+        assert!(
+            (eye_camera.plane_half_right.length() - camera.plane_half_right.length()).abs() < 1e-9
+        );
+    }
+}
+
+#[test]
+fn test_omni_directional_stereo_rays_are_tangent_to_circle() {
+    let rig = StereoRig {
+        interaxial: 0.4,
+        convergence_distance: 6.0,
+        convergence: Convergence::OffAxis,
+    };
+    let camera = Camera::equirectangular(
+        Vector3::zero(),
+        Vector3::new(0.0, 0.0, -1.0),
+        Vector3::new(0.0, 1.0, 0.0),
+    );
+    let [left, right] = rig.eyes(&camera);
+
+    for x in &[-1.5, -0.5, 0.0, 0.3, 1.0] {
+        let coord = Vector2::new(*x, 0.2);
+        let l = left.ray(&coord).unwrap();
+        let r = right.ray(&coord).unwrap();
+        assert_near(l.direction, r.direction);
+        for ray in &[&l, &r] {
+            assert!((ray.origin.length() - 0.2).abs() < 1e-9);
+            assert!(ray.origin.dot(&ray.direction).abs() < 1e-9);
+        }
+        // Right eye is on the right of the direction seen from above
+        let right_of_direction = r.direction.cross(&Vector3::new(0.0, 1.0, 0.0));
+        assert!(r.origin.dot(&right_of_direction) > 0.0);
+        assert_near(l.origin, -r.origin);
+    }
+}
+
+#[test]
+fn test_stereo_layout_composes_eyes() {
+    let left = ImageBuffer::from_pixel(4, 3, Rgb([255u8, 0, 0]));
+    let right = ImageBuffer::from_pixel(4, 3, Rgb([0u8, 0, 255]));
+
+    let side_by_side = StereoLayout::SideBySide.compose(&left, &right);
+    assert_eq!(side_by_side.dimensions(), (8, 3));
+    assert_eq!(side_by_side.get_pixel(3, 2), &Rgb([255, 0, 0]));
+    assert_eq!(side_by_side.get_pixel(4, 0), &Rgb([0, 0, 255]));
+
+    let top_bottom = StereoLayout::TopBottom.compose(&left, &right);
+    assert_eq!(top_bottom.dimensions(), (4, 6));
+    assert_eq!(top_bottom.get_pixel(3, 2), &Rgb([255, 0, 0]));
+    assert_eq!(top_bottom.get_pixel(0, 3), &Rgb([0, 0, 255]));
+
+    assert_eq!("top-bottom".parse(), Ok(StereoLayout::TopBottom));
+    assert!("anaglyph".parse::<StereoLayout>().is_err());
+}
+
+#[test]
+fn test_interrupted_stereo_render_completes_both_eyes() {
+    let scene = scene(vec![]);
+    let rig = StereoRig {
+        interaxial: 0.3,
+        convergence_distance: 10.0,
+        convergence: Convergence::OffAxis,
+    };
+    let mut renderer = PathTracingRenderer::new(8, filter::identity_filter, tonemap::none);
+    renderer.interrupted.store(true, Ordering::SeqCst);
+
+    let mut imgbufs = vec![ImageBuffer::new(4, 2); 2];
+    let sampled = renderer.render_views(&scene, &rig.eyes(&center_camera()), &mut imgbufs);
+    assert_eq!(sampled, vec![1, 1]);
+}
+
+// Asks to stop at the given sampling, like an interrupt or a time limit would, and keeps the
+// number of views of each report
+struct StoppingRenderer {
+    stop_at: u32,
+    reported: Vec<usize>,
+}
+
+impl Renderer for StoppingRenderer {
+    fn max_sampling(&self) -> u32 {
+        8
+    }
+
+    fn calc_pixel(
+        &self,
+        _scene: &dyn Illuminable,
+        _camera: &Camera,
+        _emissions: &Vec<&Box<dyn Intersectable>>,
+        _normalized_coord: &Vector2,
+        _sampling: u32,
+    ) -> Color {
+        Color::one()
+    }
+
+    fn report_progress(
+        &mut self,
+        accumulations: &[&AccumulationBuffer],
+        sampling: u32,
+        _imgbufs: &mut [&mut ImageBuffer<Rgb<u8>, Vec<u8>>],
+    ) -> bool {
+        self.reported.push(accumulations.len());
+        sampling == self.stop_at
+    }
+
+    fn filter(&self) -> filter::PixelArrayFilterFn {
+        filter::identity_filter
+    }
+
+    fn tonemap(&self) -> tonemap::TonemapFn {
+        tonemap::none
+    }
+}
+
+#[test]
+fn test_stereo_eyes_stop_at_same_sampling() {
+    let scene = scene(vec![]);
+    let rig = StereoRig {
+        interaxial: 0.3,
+        convergence_distance: 10.0,
+        convergence: Convergence::OffAxis,
+    };
+    // Both eyes are reported together once per sampling
+    let mut renderer = StoppingRenderer {
+        stop_at: 2,
+        reported: vec![],
+    };
+
+    let mut imgbufs = vec![ImageBuffer::new(4, 2); 2];
+    let sampled = renderer.render_views(&scene, &rig.eyes(&center_camera()), &mut imgbufs);
+    assert_eq!(sampled, vec![2, 2]);
+    assert_eq!(renderer.reported, vec![2, 2]);
+}
@@ -0,0 +1,30 @@
+#![cfg(test)]
+
+use super::super::color::{gamma_to_linear, Color};
+use super::super::texture::{ImageTexture, Texture};
+use super::super::vector::Vector2;
+use image::{DynamicImage, ImageBuffer, Rgb};
+use std::sync::Arc;
+
+#[test]
+fn test_data_textures_are_sampled_without_gamma() {
+    let texture = Texture {
+        image_texture: Some(ImageTexture {
+            image: Arc::new(DynamicImage::ImageRgb8(ImageBuffer::from_pixel(
+                2,
+                2,
+                Rgb([51; 3]),
+            ))),
+        }),
+        color: Color::all_of(0.5),
+    };
+    let uv = Vector2::new(0.25, 0.75);
+    let stored = Color::all_of(0.2);
+
+    // Colors are decoded from sRGB, data such as heights is taken as stored
+    assert!((texture.sample(uv) - gamma_to_linear(stored) * 0.5).length() < 1e-9);
+    assert!((texture.sample_linear(uv) - stored * 0.5).length() < 1e-9);
+
+    let color = Texture::of_color(Color::all_of(0.3));
+    assert_eq!(color.sample_linear(uv), color.sample(uv));
+}
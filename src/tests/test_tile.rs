@@ -0,0 +1,72 @@
+#![cfg(test)]
+
+use super::super::tile::{Region, TileOrder, Tiling};
+
+#[test]
+fn test_tiles_cover_region() {
+    for order in &[TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+        let tiling = Tiling {
+            size: 16,
+            order: *order,
+        };
+        let (width, height) = (70, 45);
+        let mut covered = vec![0; (width * height) as usize];
+        for tile in tiling.tiles(width, height) {
+            assert!(Region::new(0, 0, width, height).contains(&tile));
+            for i in tile.pixel_indexes(width) {
+                covered[i] += 1;
+            }
+        }
+        assert!(covered.iter().all(|c| *c == 1), "{:?}", order);
+    }
+}
+
+#[test]
+fn test_hilbert_tiles_are_adjacent() {
+    let tiling = Tiling {
+        size: 8,
+        order: TileOrder::Hilbert,
+    };
+    let tiles = tiling.tiles(64, 64);
+    for pair in tiles.windows(2) {
+        let dx = (pair[0].x as i64 - pair[1].x as i64).abs();
+        let dy = (pair[0].y as i64 - pair[1].y as i64).abs();
+        assert_eq!(dx + dy, 8);
+    }
+}
+
+#[test]
+fn test_spiral_starts_from_center() {
+    let tiling = Tiling {
+        size: 10,
+        order: TileOrder::Spiral,
+    };
+    let tiles = tiling.tiles(50, 50);
+    assert_eq!(tiles[0], Region::new(20, 20, 10, 10));
+}
+
+#[test]
+fn test_region_from_str() {
+    assert_eq!("1, 2,3,4".parse::<Region>(), Ok(Region::new(1, 2, 3, 4)));
+    assert!("1,2,3".parse::<Region>().is_err());
+    assert!("a,2,3,4".parse::<Region>().is_err());
+}
+
+#[test]
+fn test_region_contains() {
+    let frame = Region::new(0, 0, 640, 480);
+    assert!(frame.contains(&frame));
+    assert!(frame.contains(&Region::new(600, 400, 40, 80)));
+    assert!(!frame.contains(&Region::new(600, 400, 41, 80)));
+
+    // Edges past the range of u32 must not wrap around into the frame
+    assert!(!frame.contains(&Region::new(1, 0, u32::MAX, 480)));
+    assert!(!frame.contains(&Region::new(0, u32::MAX, 640, 2)));
+    assert!(!Region::new(1, 0, u32::MAX, 1).contains(&Region::new(1, 0, 1, 1)));
+
+    // Pixel counts past the range of u32
+    assert_eq!(
+        Region::new(0, 0, 100_000, 100_000).num_of_pixel(),
+        10_000_000_000
+    );
+}
@@ -0,0 +1,135 @@
+#![cfg(test)]
+
+use super::super::aabb::Aabb;
+use super::super::config;
+use super::super::matrix::Matrix44;
+use super::super::rayintersectable::{Cuboid, Intersectable, Sphere};
+use super::super::scene_graph::{Group, SceneNode};
+use super::super::transform::Transformed;
+use super::super::vector::Vector3;
+use super::fixtures::{assert_near, intersect, material};
+
+fn unit_sphere() -> Box<Sphere> {
+    Box::new(Sphere {
+        center: Vector3::zero(),
+        radius: 1.0,
+        material: material(),
+    })
+}
+
+#[test]
+fn test_rotated_cuboid() {
+    let cuboid = Cuboid {
+        aabb: Aabb {
+            min: Vector3::all_of(-1.0),
+            max: Vector3::all_of(1.0),
+        },
+        material: material(),
+    };
+    let rotated = Transformed::new(
+        Box::new(cuboid),
+        Matrix44::translate(0.0, 0.0, -5.0) * Matrix44::rotate_y(config::PI / 4.0),
+    );
+
+    // An edge faces the ray, a little closer than a face would
+    let edge = intersect(&rotated, Vector3::zero(), Vector3::new(0.0, 0.0, -1.0)).unwrap();
+    assert!((edge.distance - (5.0 - 2f64.sqrt())).abs() < 1e-9);
+    // Beside the edge the face turned toward +x is hit
+    let face = intersect(
+        &rotated,
+        Vector3::new(0.3, 0.0, 0.0),
+        Vector3::new(0.0, 0.0, -1.0),
+    )
+    .unwrap();
+    let half = 0.5f64.sqrt();
+    assert_near(face.normal, Vector3::new(half, 0.0, half));
+    assert!((face.position.x + face.position.z + 5.0 - 2f64.sqrt()).abs() < 1e-9);
+    // Corners of the unrotated box are empty now
+    assert!(intersect(
+        &rotated,
+        Vector3::new(0.95, 5.0, -4.05),
+        Vector3::new(0.0, -1.0, 0.0)
+    )
+    .is_none());
+}
+
+#[test]
+fn test_scaled_sphere_is_an_ellipsoid() {
+    let ellipsoid = Transformed::new(unit_sphere(), Matrix44::scale(2.0, 1.0, 1.0));
+    assert_near(ellipsoid.aabb().max, Vector3::new(2.0, 1.0, 1.0));
+
+    let side = intersect(
+        &ellipsoid,
+        Vector3::new(5.0, 0.0, 0.0),
+        Vector3::new(-1.0, 0.0, 0.0),
+    )
+    .unwrap();
+    assert!((side.distance - 3.0).abs() < 1e-9);
+
+    // Normals follow the gradient of x^2 / 4 + y^2 + z^2 rather than the scaled sphere normal
+    let p = Vector3::new(2f64.sqrt(), 0.5f64.sqrt(), 0.0);
+    let hit = intersect(&ellipsoid, p * 2.0, -p).unwrap();
+    assert_near(hit.position, p);
+    assert_near(hit.normal, Vector3::new(p.x / 4.0, p.y, 0.0).normalized());
+}
+
+#[test]
+fn test_samples_of_transformed_lights() {
+    let ellipsoid = Transformed::new(
+        unit_sphere(),
+        Matrix44::translate(1.0, 2.0, 3.0)
+            * Matrix44::rotate_z(0.3)
+            * Matrix44::scale(2.0, 1.0, 1.0),
+    );
+    assert!(ellipsoid.nee_available());
+
+    // The mean of inverse densities over the surface is its area, that of a prolate spheroid
+    let n = 200;
+    let mut area = 0.0;
+    for i in 0..n {
+        for j in 0..n {
+            let random = ((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+            let surface = ellipsoid.sample_on_surface(random).unwrap();
+            area += surface.pdf.recip() / (n * n) as f64;
+
+            // Samples lie on the surface with the normal of a hit there
+            let hit = intersect(
+                &ellipsoid,
+                surface.position + surface.normal,
+                -surface.normal,
+            )
+            .unwrap();
+            assert!((hit.position - surface.position).length() < 1e-3);
+            assert!((hit.normal - surface.normal).length() < 1e-3);
+        }
+    }
+    let e = 0.75f64.sqrt();
+    let expected = 2.0 * config::PI * (1.0 + 2.0 / e * e.asin());
+    assert!(
+        (area - expected).abs() < 1e-3 * expected,
+        "{} != {}",
+        area,
+        expected
+    );
+}
+
+#[test]
+fn test_primitives_in_groups() {
+    let elements = Group::new(Matrix44::translate(0.0, 0.0, -4.0))
+        .with_child(
+            Group::new(Matrix44::scale(1.0, 3.0, 1.0))
+                .with_child(SceneNode::Element(unit_sphere())),
+        )
+        .flatten();
+    assert_eq!(elements.len(), 1);
+    assert!(elements[0].nee_available());
+
+    let top = intersect(
+        elements[0].as_ref(),
+        Vector3::new(0.0, 10.0, -4.0),
+        Vector3::new(0.0, -1.0, 0.0),
+    )
+    .unwrap();
+    assert_near(top.position, Vector3::new(0.0, 3.0, -4.0));
+    assert_near(top.normal, Vector3::new(0.0, 1.0, 0.0));
+}
@@ -5,12 +5,15 @@ use crate::vector::Vector3;
 use image::{DynamicImage, GenericImageView};
 use std::fmt;
 use std::path::Path;
+use std::sync::Arc;
 
 use crate::color::Color;
 use crate::vector::Vector2;
 
+// Images are shared by the textures referring to the same file
+#[derive(Clone)]
 pub struct ImageTexture {
-    pub image: DynamicImage,
+    pub image: Arc<DynamicImage>,
 }
 
 impl fmt::Debug for ImageTexture {
@@ -27,11 +30,16 @@ impl fmt::Debug for ImageTexture {
 impl ImageTexture {
     pub fn new(path: &str) -> ImageTexture {
         ImageTexture {
-            image: image::open(&Path::new(path)).unwrap(),
+            image: Arc::new(image::open(&Path::new(path)).unwrap()),
         }
     }
 
     pub fn sample_bilinear(&self, u: f64, v: f64) -> Vector3 {
+        gamma_to_linear(self.sample_bilinear_linear(u, v))
+    }
+
+    // Values as stored without the sRGB curve, for data such as heights or roughness
+    pub fn sample_bilinear_linear(&self, u: f64, v: f64) -> Vector3 {
         let x = u * self.image.width() as f64;
         let y = v * self.image.height() as f64;
         let x1 = x.floor();
@@ -44,12 +52,11 @@ impl ImageTexture {
         let p21 = self.sample_nearest_screen(x2 as u32, y1 as u32);
         let p22 = self.sample_nearest_screen(x2 as u32, y2 as u32);
 
-        let gamma = (p11 * (x2 - x) * (y2 - y)
+        (p11 * (x2 - x) * (y2 - y)
             + p21 * (x - x1) * (y2 - y)
             + p12 * (x2 - x) * (y - y1)
             + p22 * (x - x1) * (y - y1))
-            / ((x2 - x1) * (y2 - y1));
-        gamma_to_linear(gamma)
+            / ((x2 - x1) * (y2 - y1))
     }
 
     fn sample_nearest_screen(&self, x: u32, y: u32) -> Vector3 {
@@ -59,7 +66,7 @@ impl ImageTexture {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Texture {
     pub image_texture: Option<ImageTexture>,
     pub color: Color,
@@ -94,4 +101,13 @@ impl Texture {
             self.color
         }
     }
+
+    // Sample of a data texture, whose image isn't sRGB encoded
+    pub fn sample_linear(&self, uv: Vector2) -> Color {
+        if let Some(ref texture) = self.image_texture {
+            texture.sample_bilinear_linear(uv.x, uv.y) * self.color
+        } else {
+            self.color
+        }
+    }
 }
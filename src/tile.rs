@@ -0,0 +1,161 @@
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Region {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Region {
+        Region {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn num_of_pixel(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+
+    // Right and bottom edges, none for a region reaching past the range of u32
+    fn end(&self) -> Option<(u32, u32)> {
+        Some((
+            self.x.checked_add(self.width)?,
+            self.y.checked_add(self.height)?,
+        ))
+    }
+
+    pub fn contains(&self, other: &Region) -> bool {
+        match (self.end(), other.end()) {
+            (Some((right, bottom)), Some((other_right, other_bottom))) => {
+                self.x <= other.x
+                    && self.y <= other.y
+                    && other_right <= right
+                    && other_bottom <= bottom
+            }
+            _ => false,
+        }
+    }
+
+    // Indexes of the pixels of this region in a row-major buffer of given width
+    pub fn pixel_indexes(&self, buffer_width: u32) -> impl Iterator<Item = usize> {
+        let region = *self;
+        (region.y..region.y + region.height).flat_map(move |y| {
+            (region.x..region.x + region.width)
+                .map(move |x| y as usize * buffer_width as usize + x as usize)
+        })
+    }
+}
+
+// "x,y,width,height"
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|v| v.trim().parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        match values[..] {
+            [x, y, width, height] => Ok(Region::new(x, y, width, height)),
+            _ => Err(format!("Expected x,y,width,height but got {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TileOrder {
+    Scanline,
+    Spiral,
+    Hilbert,
+}
+
+impl FromStr for TileOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "scanline" => Ok(TileOrder::Scanline),
+            "spiral" => Ok(TileOrder::Spiral),
+            "hilbert" => Ok(TileOrder::Hilbert),
+            _ => Err(format!("Unknown tile order {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Tiling {
+    pub size: u32,
+    pub order: TileOrder,
+}
+
+impl Tiling {
+    // Tiles covering (0, 0, width, height), in the order they should be rendered
+    pub fn tiles(&self, width: u32, height: u32) -> Vec<Region> {
+        let size = self.size.max(1);
+        let columns = width.div_ceil(size);
+        let rows = height.div_ceil(size);
+
+        let mut coords: Vec<(u32, u32)> = (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (column, row)))
+            .collect();
+
+        match self.order {
+            TileOrder::Scanline => {}
+            TileOrder::Spiral => {
+                // Ring by ring from the center, counterclockwise within a ring
+                let center_x = (columns as f64 - 1.0) * 0.5;
+                let center_y = (rows as f64 - 1.0) * 0.5;
+                coords.sort_by(|a, b| {
+                    let key = |&(column, row): &(u32, u32)| {
+                        let dx = column as f64 - center_x;
+                        let dy = row as f64 - center_y;
+                        (dx.abs().max(dy.abs()), dy.atan2(dx))
+                    };
+                    key(a).partial_cmp(&key(b)).unwrap()
+                });
+            }
+            TileOrder::Hilbert => {
+                let n = columns.max(rows).next_power_of_two();
+                coords.sort_by_key(|&(column, row)| hilbert_index(n, column, row));
+            }
+        }
+
+        coords
+            .iter()
+            .map(|&(column, row)| {
+                let x = column * size;
+                let y = row * size;
+                Region::new(x, y, size.min(width - x), size.min(height - y))
+            })
+            .collect()
+    }
+}
+
+// Distance along the Hilbert curve filling an n x n grid, n must be a power of two
+// https://en.wikipedia.org/wiki/Hilbert_curve
+fn hilbert_index(n: u32, x: u32, y: u32) -> u64 {
+    let (mut x, mut y) = (x, y);
+    let mut d = 0u64;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = ((x & s) > 0) as u32;
+        let ry = ((y & s) > 0) as u32;
+        d += s as u64 * s as u64 * ((3 * rx) ^ ry) as u64;
+        if ry == 0 {
+            if rx == 1 {
+                x = n - 1 - x;
+                y = n - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    d
+}
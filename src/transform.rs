@@ -0,0 +1,145 @@
+use crate::aabb::Aabb;
+use crate::camera::Ray;
+use crate::color::Color;
+use crate::material::Material;
+use crate::matrix::Matrix44;
+use crate::rayintersectable::{Intersectable, Intersection};
+use crate::scene::Surface;
+use crate::vector::Vector3;
+
+// Intersects an element placed by matrix, whose inverse and its transpose are given, in the
+// element's own space
+pub(crate) fn intersect_transformed(
+    element: &dyn Intersectable,
+    matrix: &Matrix44,
+    inverse: &Matrix44,
+    normal_matrix: &Matrix44,
+    ray: &Ray,
+    intersection: &mut Intersection,
+) -> bool {
+    // Distances are measured along the unit direction in each space
+    let direction = inverse.transform_direction(&ray.direction);
+    let scale = direction.length();
+    let local_ray = Ray {
+        origin: *inverse * ray.origin,
+        direction: direction / scale,
+        time: ray.time,
+    };
+
+    let distance = intersection.distance;
+    intersection.distance = distance * scale;
+    if !element.intersect(&local_ray, intersection) {
+        intersection.distance = distance;
+        return false;
+    }
+
+    intersection.position = *matrix * intersection.position;
+    intersection.distance /= scale;
+    // Normals are transformed by the inverse transpose
+    intersection.normal = normal_matrix
+        .transform_direction(&intersection.normal)
+        .normalized();
+    true
+}
+
+// Element placed by an affine matrix, such as a rotated cuboid or a sphere scaled to an
+// ellipsoid
+pub struct Transformed {
+    pub element: Box<dyn Intersectable>,
+    matrix: Matrix44,
+    inverse: Matrix44,
+    // Inverse transpose, which keeps normals perpendicular to the surface
+    normal_matrix: Matrix44,
+    // Volume scale of the linear part
+    det: f64,
+    aabb: Aabb,
+}
+
+impl Transformed {
+    pub fn new(element: Box<dyn Intersectable>, matrix: Matrix44) -> Transformed {
+        let inverse = matrix.inverse();
+        let x = matrix.transform_direction(&Vector3::new(1.0, 0.0, 0.0));
+        let y = matrix.transform_direction(&Vector3::new(0.0, 1.0, 0.0));
+        let z = matrix.transform_direction(&Vector3::new(0.0, 0.0, 1.0));
+        Transformed {
+            aabb: element.aabb().transformed(&matrix),
+            normal_matrix: inverse.transposed(),
+            det: x.dot(&y.cross(&z)),
+            inverse,
+            matrix,
+            element,
+        }
+    }
+
+    pub fn matrix(&self) -> &Matrix44 {
+        &self.matrix
+    }
+
+    // Areas are scaled by the determinant times the length of the transformed normal, so the
+    // density over area falls by as much
+    fn to_world(&self, surface: Surface) -> Surface {
+        let normal = self.normal_matrix.transform_direction(&surface.normal);
+        Surface {
+            position: self.matrix * surface.position,
+            normal: normal.normalized(),
+            pdf: surface.pdf / (self.det.abs() * normal.length()),
+        }
+    }
+}
+
+impl Intersectable for Transformed {
+    fn aabb(&self) -> Aabb {
+        self.aabb
+    }
+
+    fn intersect(&self, ray: &Ray, intersection: &mut Intersection) -> bool {
+        if !self.aabb.intersect_with_ray(ray).0 {
+            return false;
+        }
+        intersect_transformed(
+            self.element.as_ref(),
+            &self.matrix,
+            &self.inverse,
+            &self.normal_matrix,
+            ray,
+            intersection,
+        )
+    }
+
+    fn material(&self) -> &Material {
+        self.element.material()
+    }
+
+    fn material_at(&self, intersection: &Intersection) -> &Material {
+        self.element.material_at(intersection)
+    }
+
+    fn vertex_color(&self, intersection: &Intersection) -> Color {
+        self.element.vertex_color(intersection)
+    }
+
+    fn emission_toward(&self, intersection: &Intersection, direction: &Vector3) -> f64 {
+        self.element
+            .emission_toward(intersection, &self.inverse.transform_direction(direction))
+    }
+
+    fn visible_to_camera(&self) -> bool {
+        self.element.visible_to_camera()
+    }
+
+    fn nee_available(&self) -> bool {
+        self.element.nee_available()
+    }
+
+    fn sample_on_surface(&self, random: (f64, f64)) -> Option<Surface> {
+        self.element
+            .sample_on_surface(random)
+            .map(|surface| self.to_world(surface))
+    }
+
+    fn sample_from(&self, position: &Vector3, random: (f64, f64)) -> Option<Surface> {
+        self.element
+            .sample_from(&(self.inverse * *position), random)
+            .map(|surface| self.to_world(surface))
+    }
+}